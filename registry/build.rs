@@ -0,0 +1,18 @@
+use anyhow::{Context, Result};
+use cardstock_registry_codegen::rust;
+use cardstock_registry_codegen::schema::Schema;
+use std::path::PathBuf;
+use std::{env, fs};
+
+const PROTOCOL_PATH: &str = "res/protocol.toml";
+
+fn main() -> Result<()> {
+    println!("cargo:rerun-if-changed={PROTOCOL_PATH}");
+    let schema = Schema::load(PROTOCOL_PATH)?;
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").context("`OUT_DIR` isn't set")?);
+    let out_path = out_dir.join("packets.rs");
+    fs::write(&out_path, rust::generate(&schema))
+        .with_context(|| format!("failed to write `{}`", out_path.display()))?;
+    Ok(())
+}
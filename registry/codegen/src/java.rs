@@ -0,0 +1,604 @@
+use crate::schema::{EnumDef, FieldDef, FieldType, PacketDef, Schema, Side, StructDef};
+use crate::{lower_camel_case, words};
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::{fs, iter};
+
+#[cfg(test)]
+mod tests;
+
+const HEADER: &str =
+    "// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.";
+const MAX_LINE_LEN: usize = 120;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct JavaFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+pub fn generate(schema: &Schema) -> Result<Vec<JavaFile>> {
+    let mut files = Vec::new();
+    for e in &schema.enums {
+        files.push(generate_enum(schema, e));
+    }
+    for s in &schema.structs {
+        let file = generate_struct(schema, s)
+            .with_context(|| format!("failed to generate the struct `{}`", s.name))?;
+        files.push(file);
+    }
+    for packet in &schema.client_packets {
+        let file = generate_client_packet(schema, packet)
+            .with_context(|| format!("failed to generate the client packet `{}`", packet.name))?;
+        files.push(file);
+    }
+    for packet in &schema.server_packets {
+        files.push(generate_server_packet(schema, packet));
+    }
+    files.push(generate_server_packet_interface(schema));
+    Ok(files)
+}
+
+pub fn write(schema: &Schema, root: impl AsRef<Path>) -> Result<usize> {
+    let root = root.as_ref();
+    let files = generate(schema)?;
+    let paths: BTreeSet<_> = files.iter().map(|file| root.join(&file.path)).collect();
+
+    // Remove files generated for packets that no longer exist
+    let package_dir = root.join(package_path(&schema.java_package));
+    for dir in [
+        package_dir.clone(),
+        package_dir.join("client"),
+        package_dir.join("server"),
+    ] {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry
+                .with_context(|| format!("failed to list `{}`", dir.display()))?
+                .path();
+            if paths.contains(&path) || path.extension() != Some(OsStr::new("java")) {
+                continue;
+            }
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read `{}`", path.display()))?;
+            if contents.starts_with(HEADER) {
+                fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove `{}`", path.display()))?;
+            }
+        }
+    }
+
+    for file in &files {
+        let path = root.join(&file.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create `{}`", parent.display()))?;
+        }
+        fs::write(&path, &file.contents)
+            .with_context(|| format!("failed to write `{}`", path.display()))?;
+    }
+    Ok(files.len())
+}
+
+fn generate_enum(schema: &Schema, e: &EnumDef) -> JavaFile {
+    let name = &e.name;
+    let variants = e
+        .variants
+        .iter()
+        .map(|variant| format!("    {}", screaming_snake_case(variant)))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    let mut body = format!("public enum {name} {{\n{variants};\n\n");
+    body.push_str(&format!(
+        "    public static @NotNull {name} read(@NotNull PacketByteBuf buf) {{\n"
+    ));
+    body.push_str("        int variant = buf.readUnsignedByte();\n");
+    body.push_str(&format!("        {name}[] variants = values();\n"));
+    body.push_str("        if (variant >= variants.length) {\n");
+    body.push_str("            throw new IndexOutOfBoundsException(variant);\n");
+    body.push_str("        }\n");
+    body.push_str("        return variants[variant];\n");
+    body.push_str("    }\n\n");
+    body.push_str("    public void write(@NotNull PacketByteBuf buf) {\n");
+    body.push_str("        buf.writeUnsignedByte(this.ordinal());\n");
+    body.push_str("    }\n");
+    body.push_str("}\n");
+
+    let imports = Imports::from(["org.jetbrains.annotations.NotNull".to_string()]);
+    file(schema, None, name, &imports, &body)
+}
+
+fn generate_struct(schema: &Schema, s: &StructDef) -> Result<JavaFile> {
+    let name = &s.name;
+    let mut imports = Imports::new();
+    imports.insert("org.jetbrains.annotations.NotNull".into());
+
+    let components = components(schema, None, &s.fields, false, &mut imports);
+    let reads = s
+        .fields
+        .iter()
+        .map(|field| read_expr(schema, None, &field.ty, "buf", 0, &mut imports))
+        .collect::<Vec<_>>();
+    let mut body = format!("public record {name}({components}) {{\n");
+    body.push_str(&format!(
+        "    public static @NotNull {name} read(@NotNull PacketByteBuf buf) {{\n"
+    ));
+    body.push_str(&format!(
+        "        return new {name}({});\n",
+        reads.join(", ")
+    ));
+    body.push_str("    }\n\n");
+    body.push_str("    public void write(@NotNull PacketByteBuf buf) {\n");
+    for field in &s.fields {
+        let statement = write_field(schema, None, field, false, &mut imports)?;
+        body.push_str(&format!("        {statement};\n"));
+    }
+    body.push_str("    }\n");
+    body.push_str("}\n");
+
+    Ok(file(schema, None, name, &imports, &body))
+}
+
+fn generate_client_packet(schema: &Schema, packet: &PacketDef) -> Result<JavaFile> {
+    let side = Some(Side::Client);
+    let name = class_name(Side::Client, packet);
+    let mut imports = Imports::new();
+
+    // Client packets are created by callers, so optional fields are nullable instead of `Optional`s
+    let components = components(schema, side, &packet.fields, true, &mut imports);
+    let mut body = format!("public record {name}({components})\n");
+    body.push_str("    implements ClientPacket\n");
+    body.push_str("{\n");
+
+    // Callers can leave out any number of optional fields at the end, so adding one doesn't break
+    // them
+    let optional_tail = packet
+        .fields
+        .iter()
+        .rev()
+        .take_while(|field| matches!(field.ty, FieldType::Option(_)))
+        .count();
+    for left_out in (1..=optional_tail).rev() {
+        let given = &packet.fields[..packet.fields.len() - left_out];
+        let params = self::components(schema, side, given, true, &mut imports);
+        let args = given
+            .iter()
+            .map(|field| lower_camel_case(&field.name))
+            .chain(iter::repeat_n("null".to_string(), left_out))
+            .collect::<Vec<_>>()
+            .join(", ");
+        body.push_str(&format!("    public {name}({params}) {{\n"));
+        body.push_str(&format!("        this({args});\n"));
+        body.push_str("    }\n\n");
+    }
+
+    body.push_str("    @Override\n");
+    body.push_str("    public int id() {\n");
+    body.push_str(&format!("        return {:#04x};\n", packet.id));
+    body.push_str("    }\n");
+    if !packet.request {
+        body.push_str("\n    @Override\n");
+        body.push_str("    public boolean isRequest() {\n");
+        body.push_str("        return false;\n");
+        body.push_str("    }\n");
+    }
+    if !packet.fields.is_empty() {
+        imports.insert("org.jetbrains.annotations.NotNull".into());
+        imports.insert(qualified(schema, "PacketByteBuf"));
+        body.push_str("\n    @Override\n");
+        body.push_str("    public void write(@NotNull PacketByteBuf buf) {\n");
+        for field in &packet.fields {
+            let statement = write_field(schema, side, field, true, &mut imports)?;
+            body.push_str(&format!("        {statement};\n"));
+        }
+        body.push_str("    }\n");
+    }
+    if packet.fields.iter().any(|field| field.secret) {
+        imports.insert("org.jetbrains.annotations.NotNull".into());
+        body.push_str(&redacted_to_string(&name, &packet.fields));
+    }
+    body.push_str("}\n");
+
+    Ok(file(schema, side, &name, &imports, &body))
+}
+
+// Matches the record's own `toString`, except that secret fields are only shown as `***` when
+// they're present, so they don't end up in logs
+fn redacted_to_string(name: &str, fields: &[FieldDef]) -> String {
+    let mut expr = format!("\"{name}[");
+    for (i, field) in fields.iter().enumerate() {
+        let component = lower_camel_case(&field.name);
+        let value = match (&field.ty, field.secret) {
+            (_, false) => format!("this.{component}"),
+            (FieldType::Option(_), true) => format!("(this.{component} == null ? null : \"***\")"),
+            (_, true) => "\"***\"".into(),
+        };
+        let separator = if i == 0 { "" } else { ", " };
+        expr.push_str(&format!("{separator}{component}=\" + {value} + \""));
+    }
+    expr.push_str("]\"");
+
+    let mut body = String::from("\n    @Override\n");
+    body.push_str("    public @NotNull String toString() {\n");
+    body.push_str(&format!("        return {expr};\n"));
+    body.push_str("    }\n");
+    body
+}
+
+fn generate_server_packet(schema: &Schema, packet: &PacketDef) -> JavaFile {
+    let side = Some(Side::Server);
+    let name = class_name(Side::Server, packet);
+    let mut imports = Imports::new();
+
+    let mut components = components(schema, side, &packet.fields, false, &mut imports);
+    if packet.request {
+        components = if components.is_empty() {
+            "long requestId".into()
+        } else {
+            format!("long requestId, {components}")
+        };
+    }
+    let body = format!("public record {name}({components})\n    implements ServerPacket {{}}\n");
+
+    file(schema, side, &name, &imports, &body)
+}
+
+fn generate_server_packet_interface(schema: &Schema) -> JavaFile {
+    let side = Some(Side::Server);
+    let mut imports = Imports::new();
+    imports.insert("org.jetbrains.annotations.NotNull".into());
+    imports.insert(qualified(schema, "PacketByteBuf"));
+
+    let mut body = String::from("public interface ServerPacket {\n");
+    body.push_str("    static @NotNull ServerPacket read(int id, @NotNull PacketByteBuf buf) {\n");
+    body.push_str("        return switch (id) {\n");
+    for packet in &schema.server_packets {
+        let mut args = Vec::new();
+        if packet.request {
+            args.push("buf.readUnsignedInt()".to_string());
+        }
+        for field in &packet.fields {
+            args.push(read_expr(schema, side, &field.ty, "buf", 0, &mut imports));
+        }
+        let start = format!(
+            "            case {:#04x} -> new {}(",
+            packet.id,
+            class_name(Side::Server, packet)
+        );
+        let line = format!("{start}{});\n", args.join(", "));
+        if line.len() - 1 <= MAX_LINE_LEN {
+            body.push_str(&line);
+        } else {
+            body.push_str(&format!("{start}\n"));
+            body.push_str(&format!(
+                "                {}\n",
+                args.join(",\n                ")
+            ));
+            body.push_str("            );\n");
+        }
+    }
+    body.push_str(
+        "            default -> throw new IllegalArgumentException(String.format(\"The packet ID is invalid. (0x%02x)\", id));\n",
+    );
+    body.push_str("        };\n");
+    body.push_str("    }\n");
+    body.push_str("}\n");
+
+    file(schema, side, "ServerPacket", &imports, &body)
+}
+
+fn components(
+    schema: &Schema,
+    side: Option<Side>,
+    fields: &[FieldDef],
+    nullable_options: bool,
+    imports: &mut Imports,
+) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let ty = match &field.ty {
+                FieldType::Option(inner) if nullable_options => {
+                    imports.insert("org.jetbrains.annotations.Nullable".into());
+                    format!("@Nullable {}", boxed_type(schema, side, inner, imports))
+                }
+                ty => java_type(schema, side, ty, imports),
+            };
+            format!("{ty} {}", lower_camel_case(&field.name))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn java_type(schema: &Schema, side: Option<Side>, ty: &FieldType, imports: &mut Imports) -> String {
+    match ty {
+        FieldType::Bool => "boolean".into(),
+        FieldType::U8 | FieldType::U16 => "int".into(),
+        FieldType::U32 | FieldType::U64 => "long".into(),
+        ty => {
+            imports.insert("org.jetbrains.annotations.NotNull".into());
+            format!("@NotNull {}", object_type(schema, side, ty, imports))
+        }
+    }
+}
+
+fn boxed_type(
+    schema: &Schema,
+    side: Option<Side>,
+    ty: &FieldType,
+    imports: &mut Imports,
+) -> String {
+    match ty {
+        FieldType::Bool => "Boolean".into(),
+        FieldType::U8 | FieldType::U16 => "Integer".into(),
+        FieldType::U32 | FieldType::U64 => "Long".into(),
+        ty => object_type(schema, side, ty, imports),
+    }
+}
+
+fn object_type(
+    schema: &Schema,
+    side: Option<Side>,
+    ty: &FieldType,
+    imports: &mut Imports,
+) -> String {
+    match ty {
+        FieldType::String => "String".into(),
+        FieldType::LogLevel => {
+            imports.insert("org.slf4j.Logger".into());
+            imports.insert("java.util.function.BiConsumer".into());
+            "BiConsumer<@NotNull Logger, String>".into()
+        }
+        FieldType::Option(inner) => {
+            imports.insert("java.util.Optional".into());
+            format!("Optional<{}>", generic_arg(schema, side, inner, imports))
+        }
+        FieldType::List(inner) => {
+            imports.insert("java.util.List".into());
+            format!("List<{}>", generic_arg(schema, side, inner, imports))
+        }
+        FieldType::Named(name) => {
+            if side.is_some() {
+                imports.insert(qualified(schema, name));
+            }
+            name.clone()
+        }
+        ty => boxed_type(schema, side, ty, imports),
+    }
+}
+
+fn generic_arg(
+    schema: &Schema,
+    side: Option<Side>,
+    ty: &FieldType,
+    imports: &mut Imports,
+) -> String {
+    match ty {
+        FieldType::Bool | FieldType::U8 | FieldType::U16 | FieldType::U32 | FieldType::U64 => {
+            boxed_type(schema, side, ty, imports)
+        }
+        ty => {
+            imports.insert("org.jetbrains.annotations.NotNull".into());
+            format!("@NotNull {}", object_type(schema, side, ty, imports))
+        }
+    }
+}
+
+fn read_expr(
+    schema: &Schema,
+    side: Option<Side>,
+    ty: &FieldType,
+    buf: &str,
+    depth: usize,
+    imports: &mut Imports,
+) -> String {
+    match ty {
+        FieldType::Option(inner) => {
+            let read = read_fn(schema, side, inner, depth, imports);
+            format!("{buf}.readOptional({read})")
+        }
+        FieldType::List(inner) => {
+            let read = read_fn(schema, side, inner, depth, imports);
+            format!("{buf}.readList({read})")
+        }
+        FieldType::Named(name) => {
+            if side.is_some() {
+                imports.insert(qualified(schema, name));
+            }
+            format!("{name}.read({buf})")
+        }
+        ty => format!("{buf}.{}()", primitive_read_method(ty)),
+    }
+}
+
+fn primitive_read_method(ty: &FieldType) -> &'static str {
+    match ty {
+        FieldType::Bool => "readBoolean",
+        FieldType::U8 => "readUnsignedByte",
+        FieldType::U16 => "readUnsignedShort",
+        FieldType::U32 => "readUnsignedInt",
+        FieldType::U64 => "readSignedLong",
+        FieldType::String => "readString",
+        FieldType::LogLevel => "readLogFn",
+        FieldType::Option(_) | FieldType::List(_) | FieldType::Named(_) => {
+            unreachable!("`{ty:?}` isn't a primitive")
+        }
+    }
+}
+
+fn read_fn(
+    schema: &Schema,
+    side: Option<Side>,
+    ty: &FieldType,
+    depth: usize,
+    imports: &mut Imports,
+) -> String {
+    match ty {
+        FieldType::Option(_) | FieldType::List(_) => {
+            let buf = lambda_param("b", depth);
+            let read = read_expr(schema, side, ty, &buf, depth + 1, imports);
+            format!("{buf} -> {read}")
+        }
+        FieldType::Named(name) => {
+            if side.is_some() {
+                imports.insert(qualified(schema, name));
+            }
+            format!("{name}::read")
+        }
+        ty => {
+            if side.is_some() {
+                imports.insert(qualified(schema, "PacketByteBuf"));
+            }
+            format!("PacketByteBuf::{}", primitive_read_method(ty))
+        }
+    }
+}
+
+fn write_field(
+    schema: &Schema,
+    side: Option<Side>,
+    field: &FieldDef,
+    nullable_options: bool,
+    imports: &mut Imports,
+) -> Result<String> {
+    let value = format!("this.{}", lower_camel_case(&field.name));
+    let value = match &field.ty {
+        FieldType::Option(_) if !nullable_options => format!("{value}.orElse(null)"),
+        _ => value,
+    };
+    write_expr(schema, side, &field.ty, "buf", &value, 0, imports)
+        .with_context(|| format!("the field `{}` can't be written", field.name))
+}
+
+// Options are always written from nullable values
+fn write_expr(
+    schema: &Schema,
+    side: Option<Side>,
+    ty: &FieldType,
+    buf: &str,
+    value: &str,
+    depth: usize,
+    imports: &mut Imports,
+) -> Result<String> {
+    let expr = match ty {
+        FieldType::Option(inner) => {
+            let write = write_fn(schema, side, inner, depth, imports)?;
+            format!("{buf}.writeOptional({value}, {write})")
+        }
+        FieldType::List(inner) => {
+            let write = write_fn(schema, side, inner, depth, imports)?;
+            format!("{buf}.writeList({value}, {write})")
+        }
+        FieldType::Named(_) => format!("{value}.write({buf})"),
+        ty => format!("{buf}.{}({value})", primitive_write_method(ty)?),
+    };
+    Ok(expr)
+}
+
+fn primitive_write_method(ty: &FieldType) -> Result<&'static str> {
+    let method = match ty {
+        FieldType::Bool => "writeBoolean",
+        FieldType::U8 => "writeUnsignedByte",
+        FieldType::U16 => "writeUnsignedShort",
+        FieldType::U32 => "writeUnsignedInt",
+        FieldType::U64 => "writeSignedLong",
+        FieldType::String => "writeString",
+        FieldType::LogLevel => bail!("log levels can't be written by the Java client"),
+        FieldType::Option(_) | FieldType::List(_) | FieldType::Named(_) => {
+            unreachable!("`{ty:?}` isn't a primitive")
+        }
+    };
+    Ok(method)
+}
+
+fn write_fn(
+    schema: &Schema,
+    side: Option<Side>,
+    ty: &FieldType,
+    depth: usize,
+    imports: &mut Imports,
+) -> Result<String> {
+    match ty {
+        FieldType::Option(_) | FieldType::List(_) | FieldType::Named(_) => {
+            let buf = lambda_param("b", depth);
+            let value = lambda_param("v", depth);
+            // Nested options are `Optional`s, since the outer container can't hold nulls
+            let unwrapped = match ty {
+                FieldType::Option(_) => format!("{value}.orElse(null)"),
+                _ => value.clone(),
+            };
+            let write = write_expr(schema, side, ty, &buf, &unwrapped, depth + 1, imports)?;
+            Ok(format!("({buf}, {value}) -> {write}"))
+        }
+        ty => {
+            if side.is_some() {
+                imports.insert(qualified(schema, "PacketByteBuf"));
+            }
+            Ok(format!("PacketByteBuf::{}", primitive_write_method(ty)?))
+        }
+    }
+}
+
+fn lambda_param(name: &str, depth: usize) -> String {
+    if depth == 0 {
+        name.into()
+    } else {
+        format!("{name}{depth}")
+    }
+}
+
+fn file(
+    schema: &Schema,
+    side: Option<Side>,
+    name: &str,
+    imports: &Imports,
+    body: &str,
+) -> JavaFile {
+    let package = match side {
+        Some(side) => format!("{}.{side}", schema.java_package),
+        None => schema.java_package.clone(),
+    };
+
+    let mut contents = format!("{HEADER}\n\npackage {package};\n\n");
+    let (java, other): (Vec<_>, Vec<_>) = imports
+        .iter()
+        .filter(|import| import.rsplit_once('.').map(|(p, _)| p) != Some(package.as_str()))
+        .partition(|import| import.starts_with("java."));
+    for group in [other, java] {
+        if group.is_empty() {
+            continue;
+        }
+        for import in group {
+            contents.push_str(&format!("import {import};\n"));
+        }
+        contents.push('\n');
+    }
+    contents.push_str(body);
+
+    JavaFile {
+        path: package_path(&package).join(format!("{name}.java")),
+        contents,
+    }
+}
+
+type Imports = BTreeSet<String>;
+
+fn qualified(schema: &Schema, name: &str) -> String {
+    format!("{}.{name}", schema.java_package)
+}
+
+fn class_name(side: Side, packet: &PacketDef) -> String {
+    format!("{}{}Packet", side.prefix(), packet.name)
+}
+
+fn package_path(package: &str) -> PathBuf {
+    package.split('.').collect()
+}
+
+fn screaming_snake_case(camel: &str) -> String {
+    words(camel).join("_").to_ascii_uppercase()
+}
@@ -0,0 +1,19 @@
+use super::*;
+use std::path::Path;
+
+#[test]
+fn java_packets_match_the_schema() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let schema = Schema::load(manifest_dir.join("../res/protocol.toml")).unwrap();
+    let java_root = manifest_dir.join("../../server-ext/src/main/java");
+
+    for file in generate(&schema).unwrap() {
+        let path = java_root.join(&file.path);
+        let on_disk = fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            on_disk == file.contents,
+            "`{}` is out of date, regenerate it with cardstock-registry-codegen",
+            path.display()
+        );
+    }
+}
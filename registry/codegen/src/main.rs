@@ -0,0 +1,16 @@
+use anyhow::{bail, Context, Result};
+use cardstock_registry_codegen::java;
+use cardstock_registry_codegen::schema::Schema;
+use std::env;
+
+fn main() -> Result<()> {
+    let args: Vec<_> = env::args_os().skip(1).collect();
+    let [schema_path, java_root] = &args[..] else {
+        bail!("usage: cardstock-registry-codegen <schema> <java source root>");
+    };
+
+    let schema = Schema::load(schema_path)?;
+    let written = java::write(&schema, java_root).context("failed to write the Java packets")?;
+    println!("Wrote {written} Java files.");
+    Ok(())
+}
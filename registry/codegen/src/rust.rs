@@ -0,0 +1,384 @@
+use crate::schema::{EnumDef, FieldDef, FieldType, PacketDef, Schema, Side, StructDef};
+use crate::words;
+
+const HEADER: &str = "\
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
+#[allow(unused_imports)]
+use crate::net::packets::{Packet, RequestId};
+#[allow(unused_imports)]
+use crate::net::types::{NetReadExt, NetWriteExt};
+#[allow(unused_imports)]
+use anyhow::{bail, Context, Result};
+#[allow(unused_imports)]
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+#[allow(unused_imports)]
+use std::fmt;
+use std::io::{Read, Write};
+";
+
+pub fn generate(schema: &Schema) -> String {
+    let mut out = String::from(HEADER);
+    for e in &schema.enums {
+        generate_enum(&mut out, e);
+    }
+    for s in &schema.structs {
+        generate_struct(&mut out, s);
+    }
+    for side in [Side::Client, Side::Server] {
+        generate_packets(&mut out, side, schema.packets(side));
+    }
+    out
+}
+
+fn generate_enum(out: &mut String, e: &EnumDef) {
+    let name = &e.name;
+    let desc = words(name).join(" ");
+
+    out.push_str("\n#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]\n");
+    out.push_str(&format!("pub enum {name} {{\n"));
+    for variant in &e.variants {
+        out.push_str(&format!("    {variant},\n"));
+    }
+    out.push_str("}\n\n");
+
+    out.push_str(&format!("impl {name} {{\n"));
+    out.push_str(&format!(
+        "    pub const ALL: [Self; {}] = [\n",
+        e.variants.len()
+    ));
+    for variant in &e.variants {
+        out.push_str(&format!("        Self::{variant},\n"));
+    }
+    out.push_str("    ];\n\n");
+    out.push_str("    pub fn read_from(buf: &mut impl Read) -> Result<Self> {\n");
+    out.push_str(&format!(
+        "        let byte = buf.read_u8().context(\"failed to read the {desc} byte\")?;\n"
+    ));
+    out.push_str("        let value = match byte {\n");
+    for (i, variant) in e.variants.iter().enumerate() {
+        out.push_str(&format!("            {i} => Self::{variant},\n"));
+    }
+    out.push_str(&format!(
+        "            invalid => bail!(\"invalid {desc} ({{invalid}})\"),\n"
+    ));
+    out.push_str("        };\n");
+    out.push_str("        Ok(value)\n");
+    out.push_str("    }\n\n");
+
+    out.push_str("    pub fn write_to(&self, buf: &mut impl Write) -> Result<()> {\n");
+    out.push_str("        let byte = match self {\n");
+    for (i, variant) in e.variants.iter().enumerate() {
+        out.push_str(&format!("            Self::{variant} => {i},\n"));
+    }
+    out.push_str("        };\n");
+    out.push_str(&format!(
+        "        buf.write_u8(byte).context(\"failed to write the {desc} byte\")\n"
+    ));
+    out.push_str("    }\n");
+    out.push_str("}\n");
+}
+
+fn generate_struct(out: &mut String, s: &StructDef) {
+    let name = &s.name;
+    let field_names = s
+        .fields
+        .iter()
+        .map(|field| field.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    out.push_str("\n#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]\n");
+    out.push_str(&format!("pub struct {name} {{\n"));
+    for field in &s.fields {
+        out.push_str(&format!(
+            "    pub {}: {},\n",
+            field.name,
+            rust_type(&field.ty)
+        ));
+    }
+    out.push_str("}\n\n");
+
+    out.push_str(&format!("impl {name} {{\n"));
+    out.push_str("    pub fn read_from(buf: &mut impl Read) -> Result<Self> {\n");
+    for field in &s.fields {
+        out.push_str(&read_field(field, "        "));
+    }
+    out.push_str(&format!("        Ok(Self {{ {field_names} }})\n"));
+    out.push_str("    }\n\n");
+
+    out.push_str("    pub fn write_to(&self, buf: &mut impl Write) -> Result<()> {\n");
+    out.push_str(&format!("        let Self {{ {field_names} }} = self;\n"));
+    for field in &s.fields {
+        out.push_str(&write_field(field, "        "));
+    }
+    out.push_str("        Ok(())\n");
+    out.push_str("    }\n");
+    out.push_str("}\n");
+}
+
+fn generate_packets(out: &mut String, side: Side, packets: &[PacketDef]) {
+    let name = format!("{}Packet", side.prefix());
+    let has_secrets = packets
+        .iter()
+        .any(|packet| packet.fields.iter().any(|field| field.secret));
+
+    if has_secrets {
+        out.push_str("\n#[derive(Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]\n");
+    } else {
+        out.push_str("\n#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]\n");
+    }
+    out.push_str(&format!("pub enum {name} {{\n"));
+    for packet in packets {
+        if !packet.request && packet.fields.is_empty() {
+            out.push_str(&format!("    {},\n", packet.name));
+            continue;
+        }
+        out.push_str(&format!("    {} {{\n", packet.name));
+        if packet.request {
+            out.push_str("        request_id: RequestId,\n");
+        }
+        for field in &packet.fields {
+            out.push_str(&format!(
+                "        {}: {},\n",
+                field.name,
+                rust_type(&field.ty)
+            ));
+        }
+        out.push_str("    },\n");
+    }
+    out.push_str("}\n\n");
+
+    out.push_str(&format!("impl Packet for {name} {{\n"));
+    out.push_str("    fn read(id: u8, buf: &mut impl Read) -> Result<Self> {\n");
+    out.push_str("        let packet = match id {\n");
+    for packet in packets {
+        let construct = construct(packet);
+        if !packet.request && packet.fields.is_empty() {
+            out.push_str(&format!("            {:#04x} => {construct},\n", packet.id));
+            continue;
+        }
+        out.push_str(&format!("            {:#04x} => {{\n", packet.id));
+        if packet.request {
+            out.push_str("                let request_id = buf.read_request_id()?;\n");
+        }
+        for field in &packet.fields {
+            out.push_str(&read_field(field, "                "));
+        }
+        out.push_str(&format!("                {construct}\n"));
+        out.push_str("            }\n");
+    }
+    out.push_str("            _ => bail!(\"the packet ID is invalid ({id:#04x})\"),\n");
+    out.push_str("        };\n");
+    out.push_str("        Ok(packet)\n");
+    out.push_str("    }\n\n");
+
+    out.push_str("    fn write(&self, buf: &mut impl Write) -> Result<u8> {\n");
+    out.push_str("        let id = match self {\n");
+    for packet in packets {
+        let construct = construct(packet);
+        if !packet.request && packet.fields.is_empty() {
+            out.push_str(&format!("            {construct} => {:#04x},\n", packet.id));
+            continue;
+        }
+        out.push_str(&format!("            {construct} => {{\n"));
+        if packet.request {
+            out.push_str("                buf.write_request_id(*request_id)?;\n");
+        }
+        for field in &packet.fields {
+            out.push_str(&write_field(field, "                "));
+        }
+        out.push_str(&format!("                {:#04x}\n", packet.id));
+        out.push_str("            }\n");
+    }
+    out.push_str("        };\n");
+    out.push_str("        Ok(id)\n");
+    out.push_str("    }\n");
+    out.push_str("}\n\n");
+
+    let (requests, others): (Vec<_>, Vec<_>) = packets.iter().partition(|p| p.request);
+    let names = packets
+        .iter()
+        .map(|packet| format!("\"{}\"", packet.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    out.push_str(&format!("impl {name} {{\n"));
+    out.push_str(&format!(
+        "    pub const NAMES: &'static [&'static str] = &[{names}];\n\n"
+    ));
+    out.push_str("    pub fn request_id(&self) -> Option<RequestId> {\n");
+    out.push_str("        match self {\n");
+    if !requests.is_empty() {
+        let arms = requests
+            .iter()
+            .map(|packet| format!("Self::{} {{ request_id, .. }}", packet.name))
+            .collect::<Vec<_>>()
+            .join("\n            | ");
+        out.push_str(&format!("            {arms} => Some(*request_id),\n"));
+    }
+    if !others.is_empty() {
+        let arms = others
+            .iter()
+            .map(|packet| pattern(packet, ".."))
+            .collect::<Vec<_>>()
+            .join("\n            | ");
+        out.push_str(&format!("            {arms} => None,\n"));
+    }
+    out.push_str("        }\n");
+    out.push_str("    }\n\n");
+
+    out.push_str("    pub fn name(&self) -> &'static str {\n");
+    out.push_str("        match self {\n");
+    for packet in packets {
+        out.push_str(&format!(
+            "            {} => \"{}\",\n",
+            pattern(packet, ".."),
+            packet.name
+        ));
+    }
+    out.push_str("        }\n");
+    out.push_str("    }\n");
+    out.push_str("}\n");
+
+    if has_secrets {
+        generate_redacted_debug(out, &name, packets);
+    }
+}
+
+// Secret fields are only shown as `***`, or whether they're present for options, so they don't end
+// up in logs
+fn generate_redacted_debug(out: &mut String, name: &str, packets: &[PacketDef]) {
+    out.push_str(&format!("\nimpl fmt::Debug for {name} {{\n"));
+    out.push_str("    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {\n");
+    out.push_str("        match self {\n");
+    for packet in packets {
+        if !packet.request && packet.fields.is_empty() {
+            out.push_str(&format!(
+                "            Self::{0} => f.write_str(\"{0}\"),\n",
+                packet.name
+            ));
+            continue;
+        }
+        let mut bindings = Vec::new();
+        let mut debug = format!("f.debug_struct(\"{}\")", packet.name);
+        if packet.request {
+            bindings.push("request_id".to_string());
+            debug.push_str(".field(\"request_id\", request_id)");
+        }
+        for field in &packet.fields {
+            let name = &field.name;
+            let (binding, value) = match (&field.ty, field.secret) {
+                (_, false) => (name.clone(), name.clone()),
+                (FieldType::Option(_), true) => {
+                    (name.clone(), format!("&{name}.as_ref().map(|_| \"***\")"))
+                }
+                (_, true) => (format!("{name}: _"), "&\"***\"".into()),
+            };
+            bindings.push(binding);
+            debug.push_str(&format!(".field(\"{name}\", {value})"));
+        }
+        out.push_str(&format!(
+            "            Self::{} {{ {} }} => {debug}.finish(),\n",
+            packet.name,
+            bindings.join(", ")
+        ));
+    }
+    out.push_str("        }\n");
+    out.push_str("    }\n");
+    out.push_str("}\n");
+}
+
+fn construct(packet: &PacketDef) -> String {
+    let mut fields = Vec::new();
+    if packet.request {
+        fields.push("request_id");
+    }
+    fields.extend(packet.fields.iter().map(|field| field.name.as_str()));
+    pattern(packet, &fields.join(", "))
+}
+
+fn pattern(packet: &PacketDef, fields: &str) -> String {
+    if !packet.request && packet.fields.is_empty() {
+        format!("Self::{}", packet.name)
+    } else {
+        format!("Self::{} {{ {fields} }}", packet.name)
+    }
+}
+
+fn read_field(field: &FieldDef, indent: &str) -> String {
+    format!(
+        "{indent}let {} = {}.context(\"failed to read the {}\")?;\n",
+        field.name,
+        read_expr(&field.ty),
+        field.desc()
+    )
+}
+
+fn write_field(field: &FieldDef, indent: &str) -> String {
+    format!(
+        "{indent}{}.context(\"failed to write the {}\")?;\n",
+        write_expr(&field.ty, &field.name),
+        field.desc()
+    )
+}
+
+fn rust_type(ty: &FieldType) -> String {
+    match ty {
+        FieldType::Bool => "bool".into(),
+        FieldType::U8 => "u8".into(),
+        FieldType::U16 => "u16".into(),
+        FieldType::U32 => "u32".into(),
+        FieldType::U64 => "u64".into(),
+        FieldType::String => "String".into(),
+        FieldType::LogLevel => "log::Level".into(),
+        FieldType::Option(inner) => format!("Option<{}>", rust_type(inner)),
+        FieldType::List(inner) => format!("Vec<{}>", rust_type(inner)),
+        FieldType::Named(name) => name.clone(),
+    }
+}
+
+// Every expression evaluates to an `anyhow::Result` so they can nest inside options and lists
+fn read_expr(ty: &FieldType) -> String {
+    match ty {
+        FieldType::Bool => "buf.read_bool()".into(),
+        FieldType::U8 => "anyhow::Ok(buf.read_u8()?)".into(),
+        FieldType::U16 => "anyhow::Ok(buf.read_u16::<BigEndian>()?)".into(),
+        FieldType::U32 => "anyhow::Ok(buf.read_u32::<BigEndian>()?)".into(),
+        FieldType::U64 => "anyhow::Ok(buf.read_u64::<BigEndian>()?)".into(),
+        FieldType::String => "buf.read_string()".into(),
+        FieldType::LogLevel => "buf.read_log_level()".into(),
+        FieldType::Option(inner) => format!("buf.read_option({})", read_fn(inner)),
+        FieldType::List(inner) => format!("buf.read_list({})", read_fn(inner)),
+        FieldType::Named(name) => format!("{name}::read_from(buf)"),
+    }
+}
+
+fn read_fn(ty: &FieldType) -> String {
+    match ty {
+        FieldType::Named(name) => format!("{name}::read_from"),
+        ty => format!("|buf| {}", read_expr(ty)),
+    }
+}
+
+// `value` always names a reference to the value being written
+fn write_expr(ty: &FieldType, value: &str) -> String {
+    match ty {
+        FieldType::Bool => format!("buf.write_bool(*{value})"),
+        FieldType::U8 => format!("anyhow::Ok(buf.write_u8(*{value})?)"),
+        FieldType::U16 => format!("anyhow::Ok(buf.write_u16::<BigEndian>(*{value})?)"),
+        FieldType::U32 => format!("anyhow::Ok(buf.write_u32::<BigEndian>(*{value})?)"),
+        FieldType::U64 => format!("anyhow::Ok(buf.write_u64::<BigEndian>(*{value})?)"),
+        FieldType::String => format!("buf.write_str({value})"),
+        FieldType::LogLevel => format!("buf.write_log_level(*{value})"),
+        FieldType::Option(inner) => format!(
+            "buf.write_option({value}.as_ref(), |buf, {value}| {})",
+            write_expr(inner, value)
+        ),
+        FieldType::List(inner) => format!(
+            "buf.write_list({value}, |buf, {value}| {})",
+            write_expr(inner, value)
+        ),
+        FieldType::Named(_) => format!("{value}.write_to(buf)"),
+    }
+}
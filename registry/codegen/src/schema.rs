@@ -0,0 +1,256 @@
+use anyhow::{bail, Context, Error, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    pub java_package: String,
+    #[serde(default)]
+    pub enums: Vec<EnumDef>,
+    #[serde(default)]
+    pub structs: Vec<StructDef>,
+    #[serde(default)]
+    pub client_packets: Vec<PacketDef>,
+    #[serde(default)]
+    pub server_packets: Vec<PacketDef>,
+}
+
+impl Schema {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read the schema at `{}`", path.display()))?;
+        contents
+            .parse()
+            .with_context(|| format!("the schema at `{}` is invalid", path.display()))
+    }
+
+    pub fn packets(&self, side: Side) -> &[PacketDef] {
+        match side {
+            Side::Client => &self.client_packets,
+            Side::Server => &self.server_packets,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut type_names = HashSet::new();
+        for name in self.enums.iter().map(|e| &e.name) {
+            if !type_names.insert(name) {
+                bail!("the type `{name}` is defined more than once");
+            }
+        }
+        for e in &self.enums {
+            if e.variants.is_empty() || e.variants.len() > 256 {
+                bail!("the enum `{}` must have between 1 and 256 variants", e.name);
+            }
+        }
+
+        // Structs may only use structs defined before them, which rules out recursive types
+        for s in &self.structs {
+            self.validate_fields(&s.fields, &type_names)
+                .with_context(|| format!("the struct `{}` is invalid", s.name))?;
+            if let Some(field) = s.fields.iter().find(|field| field.secret) {
+                bail!(
+                    "the struct `{}` can't have the secret field `{}`, only packets can",
+                    s.name,
+                    field.name
+                );
+            }
+            if !type_names.insert(&s.name) {
+                bail!("the type `{}` is defined more than once", s.name);
+            }
+        }
+
+        for side in [Side::Client, Side::Server] {
+            let mut names = HashSet::new();
+            let mut ids = HashSet::new();
+            for packet in self.packets(side) {
+                if !names.insert(&packet.name) {
+                    bail!(
+                        "the {side} packet `{}` is defined more than once",
+                        packet.name
+                    );
+                }
+                if !ids.insert(packet.id) {
+                    bail!(
+                        "the {side} packet ID {:#04x} is used more than once",
+                        packet.id
+                    );
+                }
+                self.validate_fields(&packet.fields, &type_names)
+                    .with_context(|| format!("the {side} packet `{}` is invalid", packet.name))?;
+                if packet.request && packet.fields.iter().any(|f| f.name == "request_id") {
+                    bail!(
+                        "the {side} packet `{}` is a request, so it can't have a `request_id` field",
+                        packet.name
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_fields(&self, fields: &[FieldDef], type_names: &HashSet<&String>) -> Result<()> {
+        let mut names = HashSet::new();
+        for field in fields {
+            if !names.insert(&field.name) {
+                bail!("the field `{}` is defined more than once", field.name);
+            }
+            if let Some(name) = field.ty.named().find(|name| !type_names.contains(name)) {
+                bail!("the field `{}` uses the unknown type `{name}`", field.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Schema {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let schema: Self = toml::from_str(s).context("failed to parse the schema")?;
+        schema.validate()?;
+        Ok(schema)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<String>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StructDef {
+    pub name: String,
+    #[serde(default)]
+    pub fields: Vec<FieldDef>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PacketDef {
+    pub name: String,
+    pub id: u8,
+    #[serde(default)]
+    pub request: bool,
+    #[serde(default)]
+    pub fields: Vec<FieldDef>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldDef {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+    desc: Option<String>,
+    #[serde(default)]
+    pub secret: bool,
+}
+
+impl FieldDef {
+    pub fn desc(&self) -> String {
+        self.desc
+            .clone()
+            .unwrap_or_else(|| self.name.replace('_', " "))
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub enum FieldType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    String,
+    LogLevel,
+    Option(Box<FieldType>),
+    List(Box<FieldType>),
+    Named(String),
+}
+
+impl FieldType {
+    pub fn named(&self) -> impl Iterator<Item = &String> {
+        let mut ty = self;
+        loop {
+            match ty {
+                Self::Option(inner) | Self::List(inner) => ty = inner,
+                Self::Named(name) => break Some(name).into_iter(),
+                _ => break None.into_iter(),
+            }
+        }
+    }
+}
+
+impl FromStr for FieldType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(inner) = generic_arg(s, "option") {
+            return Ok(Self::Option(Box::new(inner.parse()?)));
+        }
+        if let Some(inner) = generic_arg(s, "list") {
+            return Ok(Self::List(Box::new(inner.parse()?)));
+        }
+        let ty = match s {
+            "bool" => Self::Bool,
+            "u8" => Self::U8,
+            "u16" => Self::U16,
+            "u32" => Self::U32,
+            "u64" => Self::U64,
+            "string" => Self::String,
+            "log_level" => Self::LogLevel,
+            name if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                Self::Named(name.to_string())
+            }
+            invalid => bail!("the field type `{invalid}` is invalid"),
+        };
+        Ok(ty)
+    }
+}
+
+impl TryFrom<String> for FieldType {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+fn generic_arg<'a>(s: &'a str, name: &str) -> Option<&'a str> {
+    s.strip_prefix(name)?.strip_prefix('<')?.strip_suffix('>')
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Side {
+    Client,
+    Server,
+}
+
+impl Side {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::Client => "Client",
+            Self::Server => "Server",
+        }
+    }
+}
+
+impl std::fmt::Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Client => "client",
+            Self::Server => "server",
+        })
+    }
+}
@@ -0,0 +1,29 @@
+#![no_main]
+
+use cardstock_registry::net::packets::{Packet, PartialPacket, ServerPacket};
+use cardstock_registry::net::types::{NetReadExt, NetWriteExt, PacketOpResult};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut partial = PartialPacket::new();
+    for &byte in data {
+        partial = partial.next(byte);
+        let PartialPacket::Complete { id, packet } = partial else {
+            continue;
+        };
+        partial = PartialPacket::new();
+
+        let Ok(decoded) = ServerPacket::read(id, &mut &packet[..]) else {
+            continue;
+        };
+        let mut buf = Vec::new();
+        assert!(matches!(
+            buf.write_packet(&decoded),
+            Ok(PacketOpResult::Ok(()))
+        ));
+        let Ok(PacketOpResult::Ok(reencoded)) = (&buf[..]).read_packet::<ServerPacket>() else {
+            panic!("a re-encoded packet failed to decode: {decoded:?}");
+        };
+        assert_eq!(reencoded, decoded);
+    }
+});
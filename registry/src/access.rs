@@ -0,0 +1,107 @@
+use crate::data::config::AccessConfig;
+use anyhow::{bail, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[cfg(test)]
+mod tests;
+
+// An IP range like `10.0.0.0/8`, or a single IP without a prefix length
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients connecting to an IPv6 socket show up as mapped addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => prefix_matches(
+                range.to_bits().into(),
+                ip.to_bits().into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                prefix_matches(range.to_bits(), ip.to_bits(), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(range: u128, ip: u128, bits: u8, prefix_len: u8) -> bool {
+    let shift = bits - prefix_len;
+    shift == bits || range >> shift == ip >> shift
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self {
+        let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        Self { addr, prefix_len }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .with_context(|| format!("`{addr}` isn't an IP address"))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .with_context(|| format!("`{prefix_len}` isn't a prefix length"))?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            bail!("the prefix length of `{s}` is longer than {max_len} bits");
+        }
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match (self.addr, self.prefix_len) {
+            (IpAddr::V4(_), 32) | (IpAddr::V6(_), 128) => write!(f, "{}", self.addr),
+            (addr, prefix_len) => write!(f, "{addr}/{prefix_len}"),
+        }
+    }
+}
+
+impl AccessConfig {
+    // The deny list wins over the allow list, and an empty allow list allows everyone
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip));
+        allowed && !self.deny.iter().any(|cidr| cidr.contains(ip))
+    }
+}
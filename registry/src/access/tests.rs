@@ -0,0 +1,51 @@
+use super::*;
+
+fn cidr(s: &str) -> Cidr {
+    s.parse().unwrap()
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn ranges_contain_their_ips() {
+    assert!(cidr("10.0.0.0/8").contains(ip("10.255.0.1")));
+    assert!(!cidr("10.0.0.0/8").contains(ip("11.0.0.1")));
+    assert!(cidr("192.168.1.7").contains(ip("192.168.1.7")));
+    assert!(!cidr("192.168.1.7").contains(ip("192.168.1.8")));
+    assert!(cidr("0.0.0.0/0").contains(ip("1.2.3.4")));
+    assert!(cidr("2001:db8::/32").contains(ip("2001:db8:1::1")));
+    assert!(!cidr("2001:db8::/32").contains(ip("10.0.0.1")));
+}
+
+#[test]
+fn mapped_ipv4_addresses_match_ipv4_ranges() {
+    assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+}
+
+#[test]
+fn invalid_ranges_are_rejected() {
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    assert!("10.0.0/8".parse::<Cidr>().is_err());
+    assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+}
+
+#[test]
+fn ranges_round_trip_through_strings() {
+    for s in ["10.0.0.0/8", "192.168.1.7", "2001:db8::/32", "::1"] {
+        assert_eq!(cidr(s).to_string(), s);
+    }
+}
+
+#[test]
+fn deny_wins_over_allow() {
+    let access = AccessConfig {
+        allow: vec![cidr("10.0.0.0/8")],
+        deny: vec![cidr("10.0.0.13")],
+    };
+    assert!(access.permits(ip("10.0.0.1")));
+    assert!(!access.permits(ip("10.0.0.13")));
+    assert!(!access.permits(ip("11.0.0.1")));
+    assert!(AccessConfig::default().permits(ip("11.0.0.1")));
+}
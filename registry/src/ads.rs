@@ -0,0 +1,74 @@
+use crate::data::config::AdCampaign;
+use crate::data::store::DataStore;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::time::SystemTime;
+
+#[cfg(test)]
+mod tests;
+
+// What an ad would be shown next to
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AdTarget<'a> {
+    pub plugin: Option<&'a str>,
+    pub authors: Option<&'a str>,
+    pub cmd: Option<&'a str>,
+}
+
+impl AdCampaign {
+    pub fn is_running(&self, now: SystemTime, impressions: u64) -> bool {
+        self.start.is_none_or(|start| start <= now)
+            && self.end.is_none_or(|end| now < end)
+            && self.max_impressions.is_none_or(|max| impressions < max)
+    }
+
+    pub fn targets(&self, target: &AdTarget) -> bool {
+        let plugin = self.plugins.is_empty()
+            || target
+                .plugin
+                .is_some_and(|plugin| contains_ignore_case(&self.plugins, plugin));
+        // Plugins send their authors as one string, usually separated by commas
+        let authors = self.authors.is_empty()
+            || target.authors.is_some_and(|authors| {
+                authors
+                    .split(',')
+                    .any(|author| contains_ignore_case(&self.authors, author.trim()))
+            });
+        let cmd = self.cmds.is_empty()
+            || target.cmd.is_some_and(|cmd| {
+                self.cmds
+                    .iter()
+                    .any(|pattern| match pattern.strip_suffix('*') {
+                        Some(prefix) => cmd.starts_with(prefix),
+                        None => cmd == pattern,
+                    })
+            });
+        plugin && authors && cmd
+    }
+}
+
+fn contains_ignore_case(list: &[String], name: &str) -> bool {
+    list.iter().any(|entry| entry.eq_ignore_ascii_case(name))
+}
+
+// Picks one of the running campaigns targeting `target` by weight and counts the impression
+pub fn choose<'a>(
+    campaigns: &'a [AdCampaign],
+    target: &AdTarget,
+    data: &mut DataStore,
+    rng: &mut impl Rng,
+) -> Option<&'a AdCampaign> {
+    let now = SystemTime::now();
+    let eligible: Vec<_> = campaigns
+        .iter()
+        .filter(|campaign| {
+            campaign.is_running(now, data.ad_impressions(&campaign.name))
+                && campaign.targets(target)
+        })
+        .collect();
+    let campaign = *eligible
+        .choose_weighted(rng, |campaign| campaign.weight)
+        .ok()?;
+    data.record_ad_impression(&campaign.name);
+    Some(campaign)
+}
@@ -0,0 +1,74 @@
+use super::*;
+use std::time::Duration;
+
+fn campaign(name: &str) -> AdCampaign {
+    AdCampaign {
+        name: name.into(),
+        text: format!("Buy {name}!"),
+        ..AdCampaign::default()
+    }
+}
+
+const ECONOMY: AdTarget = AdTarget {
+    plugin: Some("Essentials"),
+    authors: Some("Zenexer, ementalo"),
+    cmd: Some("eco"),
+};
+
+#[test]
+fn campaigns_run_between_their_start_and_end() {
+    let now = SystemTime::now();
+    let hour = Duration::from_secs(60 * 60);
+    let mut running = campaign("running");
+    running.start = Some(now - hour);
+    running.end = Some(now + hour);
+    assert!(running.is_running(now, 0));
+    assert!(!running.is_running(now - 2 * hour, 0));
+    assert!(!running.is_running(now + hour, 0));
+}
+
+#[test]
+fn campaigns_stop_at_their_impression_cap() {
+    let mut capped = campaign("capped");
+    capped.max_impressions = Some(2);
+    assert!(capped.is_running(SystemTime::now(), 1));
+    assert!(!capped.is_running(SystemTime::now(), 2));
+}
+
+#[test]
+fn campaigns_target_plugins_authors_and_cmds() {
+    assert!(campaign("everyone").targets(&ECONOMY));
+    assert!(campaign("everyone").targets(&AdTarget::default()));
+
+    let mut targeted = campaign("hosting");
+    targeted.plugins = vec!["essentials".into()];
+    targeted.authors = vec!["ementalo".into()];
+    targeted.cmds = vec!["eco*".into(), "pay".into()];
+    assert!(targeted.targets(&ECONOMY));
+    assert!(!targeted.targets(&AdTarget::default()));
+    assert!(!targeted.targets(&AdTarget {
+        cmd: Some("afk"),
+        ..ECONOMY
+    }));
+    assert!(!targeted.targets(&AdTarget {
+        authors: Some("md_5"),
+        ..ECONOMY
+    }));
+}
+
+#[test]
+fn choosing_counts_impressions_until_the_cap() {
+    let mut capped = campaign("capped");
+    capped.max_impressions = Some(1);
+    let campaigns = [capped];
+    let mut data = DataStore::default();
+    let mut rng = rand::thread_rng();
+
+    let chosen = choose(&campaigns, &ECONOMY, &mut data, &mut rng);
+    assert_eq!(
+        chosen.map(|campaign| campaign.name.as_str()),
+        Some("capped")
+    );
+    assert_eq!(data.ad_impressions("capped"), 1);
+    assert!(choose(&campaigns, &ECONOMY, &mut data, &mut rng).is_none());
+}
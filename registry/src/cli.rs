@@ -0,0 +1,336 @@
+use crate::data::config::Config;
+use crate::data::lock::DataLock;
+use crate::data::store::{Ban, BanTarget, DataStore};
+use crate::data::PersistentData;
+use crate::keys::Scope;
+use crate::Paths;
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use humantime_serde::re::humantime;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Debug, Parser)]
+#[command(version, about = "The Cardstock command registry")]
+pub struct Cli {
+    #[command(flatten)]
+    pub paths: PathArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct PathArgs {
+    /// The directory containing the config and data files
+    #[arg(
+        long,
+        env = "CARDSTOCK_REGISTRY_DATA_DIR",
+        default_value = ".",
+        global = true
+    )]
+    pub data_dir: PathBuf,
+    /// The config file, instead of the one in the data directory
+    #[arg(long, env = "CARDSTOCK_REGISTRY_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// The data file, instead of the one in the data directory
+    #[arg(long, env = "CARDSTOCK_REGISTRY_DATA", global = true)]
+    pub data: Option<PathBuf>,
+}
+
+impl PathArgs {
+    pub fn resolve(self) -> Paths {
+        let defaults = Paths::in_dir(&self.data_dir);
+        Paths {
+            config: self.config.unwrap_or(defaults.config),
+            data: self.data.unwrap_or(defaults.data),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Run the registry server (the default)
+    Serve,
+    /// List the registered commands, optionally only those of one plugin
+    List { plugin: Option<String> },
+    /// List the plugins that declared an ID, which own their commands
+    Plugins,
+    /// Show which plugin a command is registered to
+    Check { cmd: String },
+    /// Register a command to a plugin, by its ID or name
+    Claim { cmd: String, plugin: String },
+    /// Unregister a command
+    Release { cmd: String },
+    /// Register an already registered command to a different plugin
+    Transfer { cmd: String, plugin: String },
+    /// Merge the commands from another data file into the data store
+    Import {
+        path: PathBuf,
+        /// Replace the owners of commands that are already registered
+        #[arg(long)]
+        overwrite: bool,
+    },
+    /// Write the whole data store to a file, or to stdout if no file is given
+    Export { path: Option<PathBuf> },
+    /// Ban an IP or IP range, a plugin or a server
+    Ban {
+        kind: BanKind,
+        target: String,
+        /// The reason shown to the banned client
+        #[arg(long, default_value = "No reason was given.")]
+        reason: String,
+        /// How long the ban lasts, like `12h` or `7days`, instead of forever
+        #[arg(long, value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Lift a ban
+    Unban { kind: BanKind, target: String },
+    /// List the bans
+    Bans,
+    /// Issue an API key for a Cardstock server, which is only shown once
+    AddKey {
+        /// The server the key identifies
+        name: String,
+        /// What the key allows, instead of everything
+        #[arg(long = "scope", value_name = "SCOPE")]
+        scopes: Vec<Scope>,
+    },
+    /// Revoke a server's API key
+    RevokeKey { name: String },
+    /// List the API keys
+    Keys,
+    /// Check that the config can be loaded
+    ValidateConfig,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, ValueEnum)]
+pub enum BanKind {
+    Ip,
+    Plugin,
+    Server,
+}
+
+impl BanKind {
+    fn target(self, target: String) -> Result<BanTarget> {
+        let target = match self {
+            Self::Ip => BanTarget::Ip(target.parse()?),
+            Self::Plugin => BanTarget::Plugin(target),
+            Self::Server => BanTarget::Server(target),
+        };
+        Ok(target)
+    }
+}
+
+impl Cli {
+    pub fn run(self) -> Result<()> {
+        let paths = self.paths.resolve();
+        match self.command.unwrap_or(Command::Serve) {
+            Command::Serve => crate::run(&paths),
+            Command::List { plugin } => list(&paths, plugin),
+            Command::Plugins => plugins(&paths),
+            Command::Check { cmd } => check(&paths, &cmd),
+            Command::Claim { cmd, plugin } => modify_data(&paths, |data| {
+                let owner = data.owner_key(&plugin).to_string();
+                data.register(cmd.clone(), owner)?;
+                println!("Registered /{cmd} to {plugin}.");
+                Ok(())
+            }),
+            Command::Release { cmd } => modify_data(&paths, |data| {
+                let owner = data.release(&cmd)?;
+                println!("Released /{cmd} from {owner}.");
+                Ok(())
+            }),
+            Command::Transfer { cmd, plugin } => modify_data(&paths, |data| {
+                let new_owner = data.owner_key(&plugin).to_string();
+                let owner = data.transfer(&cmd, new_owner)?;
+                println!("Transferred /{cmd} from {owner} to {plugin}.");
+                Ok(())
+            }),
+            Command::Import { path, overwrite } => modify_data(&paths, |data| {
+                let imported = DataStore::load(&path)?;
+                import(data, &imported, overwrite)
+            }),
+            Command::Export { path } => export(&paths, path),
+            Command::Ban {
+                kind,
+                target,
+                reason,
+                duration,
+            } => {
+                let target = kind.target(target)?;
+                modify_data(&paths, |data| {
+                    let expires = duration.map(|duration| SystemTime::now() + duration);
+                    match expires {
+                        Some(expires) => println!(
+                            "Banned the {target} until {}.",
+                            humantime::format_rfc3339_seconds(expires)
+                        ),
+                        None => println!("Banned the {target}."),
+                    }
+                    data.ban(Ban {
+                        target,
+                        reason,
+                        expires,
+                    });
+                    Ok(())
+                })
+            }
+            Command::Unban { kind, target } => {
+                let target = kind.target(target)?;
+                modify_data(&paths, |data| {
+                    data.unban(&target)?;
+                    println!("Unbanned the {target}.");
+                    Ok(())
+                })
+            }
+            Command::Bans => bans(&paths),
+            Command::AddKey { name, scopes } => modify_data(&paths, |data| {
+                let scopes = if scopes.is_empty() {
+                    Scope::ALL.into()
+                } else {
+                    scopes.into_iter().collect()
+                };
+                let key = data.add_api_key(name.clone(), scopes)?;
+                println!("Issued an API key for `{name}`: {key}");
+                println!("Store it now, it can't be shown again.");
+                Ok(())
+            }),
+            Command::RevokeKey { name } => modify_data(&paths, |data| {
+                data.revoke_api_key(&name)?;
+                println!("Revoked the API key for `{name}`.");
+                Ok(())
+            }),
+            Command::Keys => keys(&paths),
+            Command::ValidateConfig => {
+                Config::load(&paths.config)?;
+                println!("The config at `{}` is valid.", paths.config.display());
+                Ok(())
+            }
+        }
+    }
+}
+
+fn list(paths: &Paths, plugin: Option<String>) -> Result<()> {
+    let data = DataStore::load_or_default(&paths.data).context("failed to load the data store")?;
+    let plugin = plugin.as_deref().map(|plugin| data.owner_key(plugin));
+    let cmds: BTreeMap<_, _> = data
+        .cmds()
+        .filter(|(_, owner)| plugin.is_none_or(|plugin| *owner == plugin))
+        .collect();
+    for (cmd, owner) in &cmds {
+        match data.owner_name(owner) {
+            name if name == *owner => println!("/{cmd} {owner}"),
+            name => println!("/{cmd} {name} ({owner})"),
+        }
+    }
+    Ok(())
+}
+
+fn plugins(paths: &Paths) -> Result<()> {
+    let data = DataStore::load_or_default(&paths.data).context("failed to load the data store")?;
+    let plugins: BTreeMap<_, _> = data.plugins().collect();
+    for (id, meta) in plugins {
+        let version = meta.version.as_deref().unwrap_or("unknown version");
+        let cmds = data.owned_by(id).count();
+        println!("{id}: {} {version} ({cmds} commands)", meta.name);
+        for detail in [&meta.website, &meta.description].into_iter().flatten() {
+            println!("  {detail}");
+        }
+    }
+    Ok(())
+}
+
+fn check(paths: &Paths, cmd: &str) -> Result<()> {
+    let data = DataStore::load_or_default(&paths.data).context("failed to load the data store")?;
+    match data.check(cmd) {
+        Some(owner) => println!("/{cmd} is registered to {}.", data.owner_name(&owner)),
+        None => println!("/{cmd} is unregistered."),
+    }
+    Ok(())
+}
+
+fn bans(paths: &Paths) -> Result<()> {
+    let data = DataStore::load_or_default(&paths.data).context("failed to load the data store")?;
+    let now = SystemTime::now();
+    for ban in data.bans() {
+        let expiry = match ban.expires {
+            Some(_) if !ban.is_active(now) => " (expired)".to_string(),
+            Some(expires) => format!(" (until {})", humantime::format_rfc3339_seconds(expires)),
+            None => String::new(),
+        };
+        println!("{}{expiry}: {}", ban.target, ban.reason);
+    }
+    Ok(())
+}
+
+fn keys(paths: &Paths) -> Result<()> {
+    let data = DataStore::load_or_default(&paths.data).context("failed to load the data store")?;
+    for key in data.api_keys() {
+        let scopes: Vec<_> = key.scopes.iter().map(Scope::to_string).collect();
+        let status = match key.revoked {
+            Some(revoked) => format!("revoked {}", humantime::format_rfc3339_seconds(revoked)),
+            None => format!("created {}", humantime::format_rfc3339_seconds(key.created)),
+        };
+        println!("{} [{}] ({status})", key.name, scopes.join(", "));
+    }
+    Ok(())
+}
+
+fn import(data: &mut DataStore, imported: &DataStore, overwrite: bool) -> Result<()> {
+    let mut conflicts: Vec<_> = imported
+        .cmds()
+        .filter(|(cmd, owner)| data.check(cmd).is_some_and(|existing| *existing != *owner))
+        .map(|(cmd, _)| format!("`{cmd}`"))
+        .collect();
+    if !conflicts.is_empty() && !overwrite {
+        conflicts.sort_unstable();
+        bail!(
+            "some commands are already registered to other plugins ({}), pass `--overwrite` to replace their owners",
+            conflicts.join(", ")
+        );
+    }
+
+    let mut changed = 0;
+    for (cmd, owner) in imported.cmds() {
+        match data.check(cmd) {
+            Some(existing) if *existing == owner => continue,
+            Some(_) => {
+                data.transfer(cmd, owner)?;
+            }
+            None => data.register(cmd, owner)?,
+        }
+        changed += 1;
+    }
+    println!("Imported {changed} commands.");
+    Ok(())
+}
+
+// Everything is exported, including plugin metadata, bans and API key hashes, so the export can
+// replace the data file as a backup
+fn export(paths: &Paths, path: Option<PathBuf>) -> Result<()> {
+    let data = DataStore::load_or_default(&paths.data).context("failed to load the data store")?;
+    let serialized = toml::to_string(&data).context("failed to serialize the data store")?;
+    match path {
+        Some(path) => {
+            fs::write(&path, serialized)
+                .with_context(|| format!("failed to write to `{}`", path.display()))?;
+            println!("Exported to `{}`.", path.display());
+        }
+        None => print!("{serialized}"),
+    }
+    Ok(())
+}
+
+fn modify_data(paths: &Paths, modify: impl FnOnce(&mut DataStore) -> Result<()>) -> Result<()> {
+    let _lock = DataLock::acquire(&paths.data)?;
+    let mut data =
+        DataStore::load_or_default(&paths.data).context("failed to load the data store")?;
+    modify(&mut data)?;
+    data.save(&paths.data)
+        .context("failed to save the data store")
+}
@@ -0,0 +1,150 @@
+use super::*;
+use crate::data::store::PluginMeta;
+use std::path::Path;
+use std::{env, process};
+
+// Starts out empty instead of with the default commands
+fn data_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("cardstock-registry-cli-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    save(&dir, &DataStore::default());
+    dir
+}
+
+fn run(dir: &Path, args: &[&str]) -> Result<()> {
+    let dir = dir.to_str().unwrap();
+    let args = ["cardstock-registry", "--data-dir", dir]
+        .into_iter()
+        .chain(args.iter().copied());
+    Cli::try_parse_from(args).unwrap().run()
+}
+
+fn data(dir: &Path) -> DataStore {
+    DataStore::load(Paths::in_dir(dir).data).unwrap()
+}
+
+fn save(dir: &Path, data: &DataStore) {
+    data.save(Paths::in_dir(dir).data).unwrap();
+}
+
+fn owner(data: &DataStore, cmd: &str) -> Option<String> {
+    data.check(cmd).map(|owner| owner.to_string())
+}
+
+#[test]
+fn claimed_commands_belong_to_the_plugin_id() {
+    let dir = data_dir("claim");
+    let mut store = DataStore::default();
+    let meta = PluginMeta {
+        name: "Essentials".into(),
+        ..PluginMeta::default()
+    };
+    store
+        .record_plugin("essentialsx", "survival", meta)
+        .unwrap();
+    save(&dir, &store);
+
+    run(&dir, &["claim", "afk", "Essentials"]).unwrap();
+    let taken = run(&dir, &["claim", "afk", "CMI"]);
+    let store = data(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(owner(&store, "afk").as_deref(), Some("essentialsx"));
+    assert!(taken.is_err());
+}
+
+#[test]
+fn transfer_only_moves_registered_commands() {
+    let dir = data_dir("transfer");
+    run(&dir, &["claim", "afk", "Essentials"]).unwrap();
+    run(&dir, &["transfer", "afk", "CMI"]).unwrap();
+    let unregistered = run(&dir, &["transfer", "home", "CMI"]);
+    let store = data(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(owner(&store, "afk").as_deref(), Some("CMI"));
+    assert!(unregistered.is_err());
+    assert_eq!(owner(&store, "home"), None);
+}
+
+#[test]
+fn imports_only_replace_owners_with_overwrite() {
+    let dir = data_dir("import");
+    run(&dir, &["claim", "afk", "Essentials"]).unwrap();
+    let mut imported = DataStore::default();
+    imported.register("afk", "CMI").unwrap();
+    imported.register("home", "CMI").unwrap();
+    let imported_path = dir.join("imported.toml");
+    imported.save(&imported_path).unwrap();
+    let imported_path = imported_path.to_str().unwrap();
+
+    let conflict = run(&dir, &["import", imported_path]);
+    let before = data(&dir);
+    run(&dir, &["import", imported_path, "--overwrite"]).unwrap();
+    let after = data(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(conflict.unwrap_err().to_string().contains("`afk`"));
+    assert_eq!(owner(&before, "afk").as_deref(), Some("Essentials"));
+    assert_eq!(owner(&before, "home"), None);
+    assert_eq!(owner(&after, "afk").as_deref(), Some("CMI"));
+    assert_eq!(owner(&after, "home").as_deref(), Some("CMI"));
+}
+
+#[test]
+fn exports_round_trip_the_whole_data_store() {
+    let dir = data_dir("export");
+    let mut store = DataStore::default();
+    store.register("afk", "Essentials").unwrap();
+    let meta = PluginMeta {
+        name: "Essentials".into(),
+        version: Some("2.20.0".into()),
+        ..PluginMeta::default()
+    };
+    store
+        .record_plugin("essentialsx", "survival", meta)
+        .unwrap();
+    store.ban(Ban {
+        target: BanTarget::Server("griefers".into()),
+        reason: "Spam.".into(),
+        expires: None,
+    });
+    store
+        .add_api_key("survival", Scope::ALL.into_iter().collect())
+        .unwrap();
+    save(&dir, &store);
+
+    let exported_path = dir.join("exported.toml");
+    run(&dir, &["export", exported_path.to_str().unwrap()]).unwrap();
+    let exported = DataStore::load(&exported_path).unwrap();
+    let restored_dir = data_dir("restored");
+    run(&restored_dir, &["import", exported_path.to_str().unwrap()]).unwrap();
+    let restored = data(&restored_dir);
+    fs::remove_dir_all(&dir).unwrap();
+    fs::remove_dir_all(&restored_dir).unwrap();
+
+    assert_eq!(exported, store);
+    let cmds = |data: &DataStore| {
+        data.cmds()
+            .map(|(cmd, owner)| (cmd.to_string(), owner.to_string()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    assert_eq!(cmds(&restored), cmds(&store));
+}
+
+#[test]
+fn changes_fail_while_the_data_store_is_locked() {
+    let dir = data_dir("lock");
+    let lock = DataLock::acquire(Paths::in_dir(&dir).data).unwrap();
+    let locked = run(&dir, &["claim", "afk", "Essentials"]);
+    let while_locked = data(&dir);
+    drop(lock);
+    run(&dir, &["claim", "afk", "Essentials"]).unwrap();
+    let store = data(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(locked.unwrap_err().to_string().contains("is locked"));
+    assert!(while_locked.is_empty());
+    assert_eq!(owner(&store, "afk").as_deref(), Some("Essentials"));
+}
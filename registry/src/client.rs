@@ -0,0 +1,428 @@
+use crate::net::packets::{
+    ClientPacket, CmdOwner, DisconnectReason, PartialPacket, RequestId, ServerPacket,
+};
+use crate::net::types::{NetReadExt, NetWriteExt, PacketOpResult};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, trace, Level};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use std::{error, fmt};
+
+#[cfg(test)]
+mod tests;
+
+pub struct RegistryClient<S: Read + Write> {
+    stream: S,
+    next_request_id: RequestId,
+    ads_enabled: bool,
+    pending: HashMap<RequestId, Vec<ServerPacket>>,
+    completed: HashMap<RequestId, Vec<ServerPacket>>,
+    // Kept across reads so a packet cut off by a read timeout is finished by the next read
+    partial: PartialPacket,
+    disconnected: bool,
+}
+
+impl RegistryClient<TcpStream> {
+    pub fn connect(
+        addr: impl ToSocketAddrs,
+        version: impl Into<String>,
+        api_key: Option<String>,
+    ) -> Result<Self> {
+        let stream = TcpStream::connect(addr).context("failed to connect to the registry")?;
+        let mut client = Self::new(stream);
+        client
+            .handshake(version, api_key)
+            .context("failed to perform the handshake")?;
+        Ok(client)
+    }
+}
+
+impl<S: Read + Write> RegistryClient<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            next_request_id: 0,
+            ads_enabled: false,
+            pending: HashMap::new(),
+            completed: HashMap::new(),
+            partial: PartialPacket::new(),
+            disconnected: false,
+        }
+    }
+
+    pub fn ads_enabled(&self) -> bool {
+        self.ads_enabled
+    }
+
+    pub fn handshake(
+        &mut self,
+        version: impl Into<String>,
+        api_key: Option<String>,
+    ) -> Result<bool> {
+        let version = version.into();
+        let response = self.request(|request_id| ClientPacket::Handshake {
+            request_id,
+            version,
+            server_id: None,
+            api_key,
+            ads_opt_out: None,
+            locale: None,
+        })?;
+        match response.last() {
+            Some(ServerPacket::Handshake { ads_enabled, .. }) => {
+                self.ads_enabled = *ads_enabled;
+                Ok(*ads_enabled)
+            }
+            _ => bail!("the handshake wasn't answered with a handshake"),
+        }
+    }
+
+    pub fn select_plugin(
+        &mut self,
+        name: impl Into<String>,
+        authors: Option<String>,
+    ) -> Result<()> {
+        let name = name.into();
+        self.request(|request_id| ClientPacket::SelectPlugin {
+            request_id,
+            name,
+            authors,
+            plugin_id: None,
+            version: None,
+            website: None,
+            description: None,
+        })
+        .and_then(expect_allowed)
+    }
+
+    pub fn enable_plugin(&mut self) -> Result<()> {
+        self.request(|request_id| ClientPacket::EnablePlugin { request_id })
+            .and_then(expect_allowed)
+    }
+
+    pub fn disable_plugin(&mut self) -> Result<()> {
+        self.request(|request_id| ClientPacket::DisablePlugin { request_id })
+            .and_then(expect_allowed)
+    }
+
+    pub fn register(&mut self, cmd: impl Into<String>) -> Result<RegisterOutcome> {
+        let name = cmd.into();
+        let response = self.request(|request_id| ClientPacket::RegisterCmd { request_id, name })?;
+
+        let mut msgs = Vec::new();
+        let mut denied = false;
+        for packet in response {
+            match packet {
+                ServerPacket::Msg {
+                    log_level,
+                    contents,
+                    rich,
+                    ..
+                } => msgs.push(Msg {
+                    log_level,
+                    contents,
+                    rich,
+                }),
+                ServerPacket::Deny { .. } => denied = true,
+                ServerPacket::Done { .. } => {}
+                packet => bail!("unexpected packet in a register response ({packet:?})"),
+            }
+        }
+
+        let outcome = if denied {
+            RegisterOutcome::Denied(msgs)
+        } else {
+            RegisterOutcome::Allowed(msgs)
+        };
+        Ok(outcome)
+    }
+
+    pub fn owner(&mut self, cmd: impl Into<String>) -> Result<Option<String>> {
+        let cmd = cmd.into();
+        let response = self.request(|request_id| ClientPacket::QueryOwner { request_id, cmd })?;
+        response
+            .into_iter()
+            .find_map(|packet| match packet {
+                ServerPacket::Owner { owner, .. } => Some(owner),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("the owner query wasn't answered"))
+    }
+
+    pub fn plugin_cmds(&mut self, plugin: impl Into<String>) -> Result<CmdOwners> {
+        let plugin = plugin.into();
+        let response =
+            self.request(|request_id| ClientPacket::QueryPluginCmds { request_id, plugin })?;
+        CmdOwners::from_response(response)
+    }
+
+    pub fn prefix(&mut self, prefix: impl Into<String>) -> Result<CmdOwners> {
+        let prefix = prefix.into();
+        let response =
+            self.request(|request_id| ClientPacket::QueryPrefix { request_id, prefix })?;
+        CmdOwners::from_response(response)
+    }
+
+    pub fn ping(&mut self) -> Result<()> {
+        self.request(|request_id| ClientPacket::Ping { request_id })
+            .and_then(expect_allowed)
+    }
+
+    pub fn disconnect(
+        &mut self,
+        reason: DisconnectReason,
+        message: impl Into<String>,
+    ) -> Result<()> {
+        if self.disconnected {
+            return Ok(());
+        }
+        self.disconnected = true;
+        self.send(&ClientPacket::Disconnect {
+            reason,
+            message: message.into(),
+        })
+    }
+
+    // Fails with `RateLimited` if the server didn't handle the request
+    pub fn request(
+        &mut self,
+        create: impl FnOnce(RequestId) -> ClientPacket,
+    ) -> Result<Vec<ServerPacket>> {
+        let request_id = self.send_request(create)?;
+        let response = self.response(request_id)?;
+        if let Some(ServerPacket::RateLimited { retry_after_ms, .. }) = response.last() {
+            return Err(RateLimited {
+                retry_after: Duration::from_millis((*retry_after_ms).into()),
+            }
+            .into());
+        }
+        Ok(response)
+    }
+
+    pub fn send_request(
+        &mut self,
+        create: impl FnOnce(RequestId) -> ClientPacket,
+    ) -> Result<RequestId> {
+        let request_id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        self.send(&create(request_id))?;
+        self.pending.insert(request_id, Vec::new());
+        Ok(request_id)
+    }
+
+    pub fn response(&mut self, request_id: RequestId) -> Result<Vec<ServerPacket>> {
+        if let Some(response) = self.completed.remove(&request_id) {
+            return Ok(response);
+        }
+        if !self.pending.contains_key(&request_id) {
+            bail!("the request {request_id} isn't pending");
+        }
+        loop {
+            let packet = self.receive()?;
+            let Some(packet_request_id) = packet.request_id() else {
+                continue;
+            };
+            let terminal = matches!(
+                packet,
+                ServerPacket::Done { .. }
+                    | ServerPacket::Handshake { .. }
+                    | ServerPacket::Pong { .. }
+                    | ServerPacket::RateLimited { .. }
+            );
+            self.pending
+                .get_mut(&packet_request_id)
+                .ok_or_else(|| {
+                    anyhow!("received a response to an unknown request ({packet_request_id})")
+                })?
+                .push(packet);
+            if terminal {
+                let response = self.pending.remove(&packet_request_id).unwrap();
+                if packet_request_id == request_id {
+                    return Ok(response);
+                }
+                self.completed.insert(packet_request_id, response);
+            }
+        }
+    }
+
+    // Answers pings and logs ads while waiting for the next other packet
+    fn receive(&mut self) -> Result<ServerPacket> {
+        loop {
+            if self.disconnected {
+                bail!("the client is disconnected");
+            }
+            let packet = self
+                .stream
+                .resume_packet(&mut self.partial)
+                .context("failed to read the next packet")?;
+            let packet = match packet {
+                PacketOpResult::Ok(packet) => packet,
+                PacketOpResult::AppearsDisconnected => {
+                    self.disconnected = true;
+                    bail!("the registry server appears to have disconnected");
+                }
+                PacketOpResult::TimedOut => bail!("timed out while waiting for a packet"),
+            };
+            trace!("Received packet: {packet:?}");
+
+            match packet {
+                ServerPacket::Ping => self
+                    .send(&ClientPacket::Pong)
+                    .context("failed to answer a ping")?,
+                ServerPacket::Ad { contents, .. } => info!("[Ad] {contents}"),
+                ServerPacket::Disconnect { reason, message } => {
+                    self.disconnected = true;
+                    return Err(Disconnected { reason, message }.into());
+                }
+                packet => return Ok(packet),
+            }
+        }
+    }
+
+    fn send(&mut self, packet: &ClientPacket) -> Result<()> {
+        trace!("Sending packet: {packet:?}");
+        let result = self
+            .stream
+            .write_packet(packet)
+            .with_context(|| format!("failed to write the packet ({packet:?})"))?;
+        if let PacketOpResult::AppearsDisconnected = result {
+            self.disconnected = true;
+            bail!("the registry server appears to have disconnected");
+        }
+        Ok(())
+    }
+}
+
+// Requests other than registering commands are only denied along with a message explaining why
+fn expect_allowed(response: Vec<ServerPacket>) -> Result<()> {
+    if !response
+        .iter()
+        .any(|packet| matches!(packet, ServerPacket::Deny { .. }))
+    {
+        return Ok(());
+    }
+    let msgs = response
+        .into_iter()
+        .filter_map(|packet| match packet {
+            ServerPacket::Msg {
+                log_level,
+                contents,
+                rich,
+                ..
+            } => Some(Msg {
+                log_level,
+                contents,
+                rich,
+            }),
+            _ => None,
+        })
+        .collect();
+    Err(Denied { msgs }.into())
+}
+
+impl<S: Read + Write> Drop for RegistryClient<S> {
+    fn drop(&mut self) {
+        let _ = self.disconnect(DisconnectReason::Requested, "The client was dropped.");
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum RegisterOutcome {
+    Allowed(Vec<Msg>),
+    Denied(Vec<Msg>),
+}
+
+impl RegisterOutcome {
+    pub fn is_denied(&self) -> bool {
+        matches!(self, Self::Denied(_))
+    }
+
+    pub fn msgs(&self) -> &[Msg] {
+        match self {
+            Self::Allowed(msgs) | Self::Denied(msgs) => msgs,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Msg {
+    pub log_level: Level,
+    pub contents: String,
+    // MiniMessage markup for showing the message in-game
+    pub rich: Option<String>,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct CmdOwners {
+    pub cmds: Vec<CmdOwner>,
+    pub truncated: bool,
+}
+
+impl CmdOwners {
+    fn from_response(response: Vec<ServerPacket>) -> Result<Self> {
+        response
+            .into_iter()
+            .find_map(|packet| match packet {
+                ServerPacket::CmdOwners {
+                    cmds, truncated, ..
+                } => Some(Self { cmds, truncated }),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("the command query wasn't answered"))
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Disconnected {
+    pub reason: DisconnectReason,
+    pub message: String,
+}
+
+impl Display for Disconnected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the registry server disconnected us ({:?}): {}",
+            self.reason, self.message
+        )
+    }
+}
+
+impl error::Error for Disconnected {}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct RateLimited {
+    pub retry_after: Duration,
+}
+
+impl Display for RateLimited {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the registry server rate limited the request, retry after {}ms",
+            self.retry_after.as_millis()
+        )
+    }
+}
+
+impl error::Error for RateLimited {}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Denied {
+    pub msgs: Vec<Msg>,
+}
+
+impl Display for Denied {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "the registry server denied the request")?;
+        for msg in &self.msgs {
+            write!(f, ": {}", msg.contents)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Denied {}
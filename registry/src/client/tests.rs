@@ -0,0 +1,229 @@
+use super::*;
+use std::io::{self, Cursor};
+
+// Reads the scripted server packets and records what the client writes
+struct ScriptedStream {
+    input: Cursor<Vec<u8>>,
+    // Reading times out once when the input reaches this position
+    time_out_at: Option<u64>,
+    output: Vec<u8>,
+}
+
+impl Read for ScriptedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.time_out_at == Some(self.input.position()) {
+            self.time_out_at = None;
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.input.read(buf)
+    }
+}
+
+impl Write for ScriptedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn client(script: &[ServerPacket]) -> RegistryClient<ScriptedStream> {
+    let mut input = Vec::new();
+    for packet in script {
+        input.write_packet(packet).unwrap();
+    }
+    RegistryClient::new(ScriptedStream {
+        input: Cursor::new(input),
+        time_out_at: None,
+        output: Vec::new(),
+    })
+}
+
+fn sent(client: &RegistryClient<ScriptedStream>) -> Vec<ClientPacket> {
+    let mut output = Cursor::new(client.stream.output.as_slice());
+    let mut packets = Vec::new();
+    while let PacketOpResult::Ok(packet) = output.read_packet().unwrap() {
+        packets.push(packet);
+    }
+    packets
+}
+
+fn msg(request_id: RequestId, contents: &str) -> ServerPacket {
+    ServerPacket::Msg {
+        request_id,
+        log_level: Level::Error,
+        contents: contents.into(),
+        rich: None,
+    }
+}
+
+#[test]
+fn interleaved_responses_are_matched_to_their_requests() {
+    let mut client = client(&[
+        ServerPacket::Done { request_id: 1 },
+        ServerPacket::Owner {
+            request_id: 0,
+            owner: Some("Essentials".into()),
+        },
+        ServerPacket::Done { request_id: 0 },
+    ]);
+    let owner = client
+        .send_request(|request_id| ClientPacket::QueryOwner {
+            request_id,
+            cmd: "afk".into(),
+        })
+        .unwrap();
+    let enable = client
+        .send_request(|request_id| ClientPacket::EnablePlugin { request_id })
+        .unwrap();
+
+    assert_eq!(
+        client.response(owner).unwrap(),
+        [
+            ServerPacket::Owner {
+                request_id: 0,
+                owner: Some("Essentials".into()),
+            },
+            ServerPacket::Done { request_id: 0 },
+        ]
+    );
+    assert_eq!(
+        client.response(enable).unwrap(),
+        [ServerPacket::Done { request_id: 1 }]
+    );
+    assert!(client.response(enable).is_err());
+}
+
+#[test]
+fn packets_cut_off_by_a_timeout_are_resumed() {
+    let owner = ServerPacket::Owner {
+        request_id: 0,
+        owner: Some("Essentials".into()),
+    };
+    let mut client = client(&[owner.clone(), ServerPacket::Done { request_id: 0 }]);
+    // Between the length prefix and the payload
+    client.stream.time_out_at = Some(2);
+
+    let error = client.owner("afk").unwrap_err();
+    assert!(format!("{error:#}").contains("timed out"));
+    assert_eq!(
+        client.response(0).unwrap(),
+        [owner, ServerPacket::Done { request_id: 0 }]
+    );
+}
+
+#[test]
+fn pings_are_answered_and_ads_skipped_while_waiting() {
+    let mut client = client(&[
+        ServerPacket::Ping,
+        ServerPacket::Ad {
+            request_id: 0,
+            contents: "Your ad here!".into(),
+        },
+        ServerPacket::Ping,
+        ServerPacket::Pong { request_id: 0 },
+    ]);
+    client.ping().unwrap();
+    assert_eq!(
+        sent(&client),
+        [
+            ClientPacket::Ping { request_id: 0 },
+            ClientPacket::Pong,
+            ClientPacket::Pong,
+        ]
+    );
+}
+
+#[test]
+fn denied_requests_fail_with_their_messages() {
+    let mut client = client(&[
+        msg(
+            0,
+            "This server's API key doesn't allow the `register` scope.",
+        ),
+        ServerPacket::Deny { request_id: 0 },
+        ServerPacket::Done { request_id: 0 },
+        msg(1, "/afk is already registered to Essentials."),
+        ServerPacket::Deny { request_id: 1 },
+        ServerPacket::Done { request_id: 1 },
+    ]);
+    let error = client.enable_plugin().unwrap_err();
+    let denied = error.downcast_ref::<Denied>().unwrap();
+    assert_eq!(
+        denied.msgs[0].contents,
+        "This server's API key doesn't allow the `register` scope."
+    );
+
+    let outcome = client.register("afk").unwrap();
+    assert!(outcome.is_denied());
+    assert_eq!(
+        outcome.msgs()[0].contents,
+        "/afk is already registered to Essentials."
+    );
+}
+
+#[test]
+fn rate_limited_requests_fail_with_the_retry_delay() {
+    let mut client = client(&[
+        ServerPacket::RateLimited {
+            request_id: 0,
+            retry_after_ms: 1500,
+        },
+        ServerPacket::RateLimited {
+            request_id: 1,
+            retry_after_ms: 250,
+        },
+    ]);
+    let error = client.select_plugin("Test", None).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<RateLimited>(),
+        Some(&RateLimited {
+            retry_after: Duration::from_millis(1500),
+        })
+    );
+    assert!(error.to_string().contains("1500ms"));
+
+    let error = client.register("test").unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<RateLimited>(),
+        Some(&RateLimited {
+            retry_after: Duration::from_millis(250),
+        })
+    );
+}
+
+#[test]
+fn disconnects_in_the_middle_of_a_request_fail_it() {
+    let mut client = client(&[
+        msg(0, "Hey, Tester!"),
+        ServerPacket::Disconnect {
+            reason: DisconnectReason::Shutdown,
+            message: "The registry server is shutting down.".into(),
+        },
+    ]);
+    let error = client.register("test").unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<Disconnected>(),
+        Some(&Disconnected {
+            reason: DisconnectReason::Shutdown,
+            message: "The registry server is shutting down.".into(),
+        })
+    );
+    assert!(client.ping().is_err());
+
+    client
+        .disconnect(DisconnectReason::Requested, "Done.")
+        .unwrap();
+    assert_eq!(
+        sent(&client),
+        [
+            ClientPacket::RegisterCmd {
+                request_id: 0,
+                name: "test".into(),
+            },
+            ClientPacket::Ping { request_id: 1 },
+        ]
+    );
+}
@@ -0,0 +1,791 @@
+use crate::ads::{self, AdTarget};
+use crate::data::config::RateLimitScope;
+use crate::data::config::{AdPolicy, AdsConfig, Config, ConfigHandle};
+use crate::data::store::{DataStore, PluginMeta};
+use crate::keys::Scope;
+use crate::limits::{Buckets, Limiter};
+use crate::messages::{Catalog, CatalogHandle, Message, Text};
+use crate::metrics::{AdSuppression, Metrics, RegisterOutcome};
+use crate::net::packets::{
+    ClientPacket, CmdOwner, DisconnectReason, Packet, PartialPacket, RequestId, ServerPacket,
+};
+use crate::net::types::{NetReadExt, NetWriteExt, PacketOpResult, MAX_PAYLOAD_LEN};
+use crate::plugins::{GlobalCommandStatus, PluginInfo, Plugins};
+use crate::shutdown::Shutdown;
+use crate::suggest;
+use anyhow::{Context, Result};
+use log::{debug, error, info, trace, warn, Level};
+use rand::Rng;
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::io::{Read, Write};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+const MAX_QUERY_RESULTS: usize = 256;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Debug)]
+pub struct SharedState {
+    pub config: Arc<ConfigHandle>,
+    pub messages: Arc<CatalogHandle>,
+    pub data: Arc<RwLock<DataStore>>,
+    pub metrics: Arc<Metrics>,
+    pub limiter: Arc<Limiter>,
+    pub shutdown: Arc<Shutdown>,
+}
+
+impl SharedState {
+    pub fn config(&self) -> Arc<Config> {
+        self.config.get()
+    }
+
+    pub fn messages(&self) -> Arc<Catalog> {
+        self.messages.get()
+    }
+}
+
+pub struct Connection<S: Read + Write> {
+    stream: S,
+    peer: IpAddr,
+    shared: SharedState,
+    buckets: Buckets,
+
+    state: ConnectionState,
+    partial: PartialPacket,
+    last_received: Instant,
+    disconnect_reason: DisconnectReason,
+    disconnect_message: String,
+    // Negotiated in the handshake, until then the default locale
+    locale: String,
+
+    plugins: Plugins,
+    // From the API key if the server authenticated with one, otherwise whatever the server claims
+    server_id: Option<String>,
+    authenticated: bool,
+    scopes: BTreeSet<Scope>,
+    ads_opt_out: bool,
+    // For capping how often the connection gets ads
+    msgs_sent: u32,
+    last_ad_at: Option<u32>,
+    ads_sent: u32,
+}
+
+impl<S: Read + Write> Connection<S> {
+    pub fn new(stream: S, peer: IpAddr, shared: SharedState) -> Self {
+        shared.metrics.connection_opened();
+        let messages = shared.messages();
+        let locale = messages.default_locale().to_string();
+        Self {
+            stream,
+            peer,
+            shared,
+            buckets: Buckets::default(),
+            state: ConnectionState::Handshaking,
+            partial: PartialPacket::new(),
+            last_received: Instant::now(),
+            disconnect_reason: DisconnectReason::Requested,
+            disconnect_message: messages.render(&locale, Message::ConnectionClosed, &[]),
+            locale,
+            plugins: Plugins::new(),
+            server_id: None,
+            authenticated: false,
+            scopes: BTreeSet::new(),
+            ads_opt_out: false,
+            msgs_sent: 0,
+            last_ad_at: None,
+            ads_sent: 0,
+        }
+    }
+
+    pub fn run(&mut self) {
+        let mut errors = 0;
+        while self.state.is_open() {
+            let result = self.next_packet();
+            // Read the tolerance every time so reloading the config affects existing connections
+            let error_tolerance = self.shared.config().server.error_tolerance;
+            let error_tolerance_set = error_tolerance >= 0;
+            match result {
+                Err(error) => {
+                    warn!("Failed to handle a packet: {error:?}");
+                    if error_tolerance_set {
+                        if errors >= error_tolerance {
+                            error!("Failed to handle too many packets.");
+                            let message = self.text(Message::ErrorThreshold, &[]);
+                            self.disconnect(DisconnectReason::ErrorThreshold, message);
+                            break;
+                        }
+                        errors += 1;
+                    }
+                }
+                Ok(PacketResult::Disconnect) => break,
+                _ if error_tolerance_set => errors = 0,
+                _ => {}
+            }
+        }
+        self.close();
+    }
+
+    fn next_packet(&mut self) -> Result<PacketResult> {
+        let packet = self
+            .stream
+            .resume_packet(&mut self.partial)
+            .context("failed to read the next packet")?;
+        let packet = match packet {
+            _ if self.shared.shutdown.is_requested() => {
+                info!("Disconnecting the client because we're shutting down.");
+                let message = self.text(Message::ShuttingDown, &[]);
+                return Ok(self.disconnect(DisconnectReason::Shutdown, message));
+            }
+            PacketOpResult::Ok(packet) => packet,
+            PacketOpResult::AppearsDisconnected => {
+                warn!("The client forcefully disconnected.");
+                return Ok(self.peer_closed());
+            }
+            PacketOpResult::TimedOut => {
+                self.handle_silence()?;
+                return Ok(self.result_after_handling());
+            }
+        };
+        self.last_received = Instant::now();
+        trace!("Received packet: {packet:?}");
+        self.shared.metrics.packet_handled(&packet);
+
+        if let Some(request_id) = packet.request_id() {
+            if let Err(retry_after) = self.take_token(packet.name()) {
+                debug!(
+                    "Rate limiting a {} packet for {}ms.",
+                    packet.name(),
+                    retry_after.as_millis()
+                );
+                self.send_packet(&ServerPacket::RateLimited {
+                    request_id,
+                    retry_after_ms: retry_after.as_millis().try_into().unwrap_or(u32::MAX),
+                })
+                .context("failed to send the rate limited packet")?;
+                return Ok(self.result_after_handling());
+            }
+            if let Some(scope) = required_scope(&packet) {
+                if self.state == ConnectionState::Active && !self.scopes.contains(&scope) {
+                    self.deny_missing_scope(request_id, scope)?;
+                    return Ok(self.result_after_handling());
+                }
+            }
+        }
+
+        match packet {
+            ClientPacket::Handshake { .. } if self.state != ConnectionState::Handshaking => {
+                // The server ID, API key and scopes can't change after the handshake
+                warn!("The client sent another handshake.");
+                let message = self.text(Message::HandshakeRepeated, &[]);
+                return Ok(self.disconnect(DisconnectReason::ProtocolViolation, message));
+            }
+            ClientPacket::Handshake {
+                request_id,
+                version,
+                server_id,
+                api_key,
+                ads_opt_out,
+                locale,
+            } => {
+                self.locale = self.shared.messages().negotiate(locale.as_deref()).into();
+                self.disconnect_message = self.text(Message::ConnectionClosed, &[]);
+                // Bans and access lists may have changed since the client connected
+                if !self.shared.config().access.permits(self.peer) {
+                    warn!("Disconnecting the client because of the access lists.");
+                    let message = self.text(Message::NotAllowed, &[]);
+                    return Ok(self.disconnect(DisconnectReason::NotAllowed, message));
+                }
+                let key = api_key.map(|key| {
+                    let data = self.shared.data.read().unwrap();
+                    data.authenticate(&key)
+                        .map(|key| (key.name.clone(), key.scopes.clone()))
+                });
+                match key {
+                    Some(Some((name, scopes))) => {
+                        self.server_id = Some(name);
+                        self.authenticated = true;
+                        self.scopes = scopes;
+                    }
+                    Some(None) => {
+                        warn!("Disconnecting the client because its API key is invalid.");
+                        let message = self.text(Message::InvalidApiKey, &[]);
+                        return Ok(self.disconnect(DisconnectReason::Unauthorized, message));
+                    }
+                    None if self.shared.config().server.require_api_key => {
+                        warn!("Disconnecting the client because it has no API key.");
+                        let message = self.text(Message::ApiKeyRequired, &[]);
+                        return Ok(self.disconnect(DisconnectReason::Unauthorized, message));
+                    }
+                    None => {
+                        self.server_id = server_id;
+                        self.authenticated = false;
+                        self.scopes = Scope::ALL.into();
+                    }
+                }
+                match (&self.server_id, self.authenticated) {
+                    (Some(id), true) => {
+                        info!("The server `{id}` authenticated and is using `{version}`.")
+                    }
+                    (Some(id), false) => info!("The client `{id}` is using `{version}`."),
+                    (None, _) => info!("The client is using `{version}`."),
+                }
+
+                let ban_message = {
+                    let data = self.shared.data.read().unwrap();
+                    let server_id = self.server_id.as_deref();
+                    data.ip_ban(self.peer)
+                        .or_else(|| server_id.and_then(|id| data.server_ban(id)))
+                        .map(|ban| ban.message(&self.shared.messages(), &self.locale))
+                };
+                if let Some(message) = ban_message {
+                    warn!("Disconnecting the banned client: {message}");
+                    return Ok(self.disconnect(DisconnectReason::Banned, message));
+                }
+                self.ads_opt_out = ads_opt_out.unwrap_or(false);
+                let config = self.shared.config();
+                if self.ads_opt_out && !self.ad_policy(&config.ads).allow_opt_out {
+                    info!("Refusing the client's request to opt out of ads.");
+                }
+                self.send_packet(&ServerPacket::Handshake {
+                    request_id,
+                    ads_enabled: self.active_ad_policy(&config.ads).is_some(),
+                    locale: self.locale.clone(),
+                })
+                .context("failed to send a handshake response")?;
+                if self.state == ConnectionState::Handshaking {
+                    self.state = ConnectionState::Active;
+                }
+            }
+            ClientPacket::Ping { request_id } => self
+                .send_packet(&ServerPacket::Pong { request_id })
+                .context("failed to send the pong packet")?,
+            ClientPacket::Pong => trace!("The client responded to our ping."),
+            ClientPacket::Disconnect { reason, message } => {
+                info!("The client is gracefully disconnecting ({reason:?}): {message}");
+                return Ok(self.peer_closed());
+            }
+            _ if self.state == ConnectionState::Handshaking => {
+                warn!("The client sent a non-handshake packet before handshake.");
+                let message = self.text(Message::HandshakeRequired, &[]);
+                return Ok(self.disconnect(DisconnectReason::ProtocolViolation, message));
+            }
+            ClientPacket::EnablePlugin { .. }
+            | ClientPacket::DisablePlugin { .. }
+            | ClientPacket::RegisterCmd { .. }
+                if !self.plugins.is_selected() =>
+            {
+                warn!("The client sent a plugin packet before selecting a plugin.");
+                let message = self.text(Message::PluginRequired, &[]);
+                return Ok(self.disconnect(DisconnectReason::ProtocolViolation, message));
+            }
+            ClientPacket::SelectPlugin {
+                request_id,
+                name,
+                authors,
+                plugin_id,
+                version,
+                website,
+                description,
+            } => {
+                // An empty ID would own the commands of every plugin that sent one
+                let id = plugin_id.filter(|id| !id.trim().is_empty());
+                let ban_message = self
+                    .shared
+                    .data
+                    .read()
+                    .unwrap()
+                    .plugin_ban(id.as_deref(), &name)
+                    .map(|ban| ban.message(&self.shared.messages(), &self.locale));
+                if let Some(message) = ban_message {
+                    warn!("Disconnecting the client for selecting a banned plugin: {message}");
+                    return Ok(self.disconnect(DisconnectReason::Banned, message));
+                }
+                let website = website.filter(|website| {
+                    let http = website.starts_with("https://") || website.starts_with("http://");
+                    if !http {
+                        debug!("Ignoring the website of `{name}` because it isn't an HTTP URL.");
+                    }
+                    http
+                });
+                let meta = PluginMeta {
+                    name: name.clone(),
+                    version,
+                    website,
+                    description,
+                    recorded_by: None,
+                };
+                let mut first_select = false;
+                self.plugins
+                    .select(name.clone(), || {
+                        first_select = true;
+                        PluginInfo::from_optional_authors(authors)
+                            .map(|info| info.with_meta(id.clone(), meta.clone()))
+                    })
+                    .with_context(|| format!("failed to select `{name}`"))?;
+                // Anyone can claim a plugin ID, so only authenticated servers can move commands to
+                // it or describe it
+                let server = self
+                    .server_id
+                    .clone()
+                    .filter(|_| self.authenticated && self.scopes.contains(&Scope::Register));
+                match (id.filter(|_| first_select), server) {
+                    (Some(id), Some(server)) => {
+                        let mut data = self.shared.data.write().unwrap();
+                        let renamed_from = data
+                            .plugin(&id)
+                            .map(|recorded| recorded.name.clone())
+                            .filter(|previous| *previous != name);
+                        let result = data.record_plugin(id.clone(), &server, meta);
+                        drop(data);
+                        match (result, renamed_from) {
+                            (Ok(_), Some(previous)) => info!(
+                                "The plugin `{id}` was renamed from `{previous}` to `{name}`."
+                            ),
+                            (Ok(0), None) => {}
+                            (Ok(moved), None) => info!(
+                                "Moved {moved} commands from the plugin name `{name}` to its ID `{id}`."
+                            ),
+                            (Err(error), _) => warn!("Not recording the plugin `{id}`: {error}."),
+                        }
+                    }
+                    (Some(id), None) => {
+                        debug!("Not recording the plugin `{id}` because the server isn't authenticated.")
+                    }
+                    (None, _) => {}
+                }
+                self.send_done(request_id)?;
+            }
+            ClientPacket::EnablePlugin { request_id } => {
+                self.plugins
+                    .set_enabled(true)
+                    .context("failed to enable the selected plugin")?;
+                self.send_done(request_id)?;
+            }
+            ClientPacket::DisablePlugin { request_id } => {
+                self.plugins
+                    .set_enabled(false)
+                    .context("failed to disable the selected plugin")?;
+                self.send_done(request_id)?;
+            }
+            ClientPacket::RegisterCmd { request_id, name } => self
+                .handle_register(request_id, name)
+                .context("failed to handle command registration")?,
+            ClientPacket::QueryOwner { request_id, cmd } => {
+                let owner = {
+                    let data = self.shared.data.read().unwrap();
+                    data.check(&cmd)
+                        .map(|owner| data.owner_name(&owner).to_string())
+                };
+                debug!("Answering the owner query for `{cmd}` with `{owner:?}`.");
+                self.send_packet(&ServerPacket::Owner { request_id, owner })
+                    .context("failed to send the owner packet")?;
+                self.send_done(request_id)?;
+            }
+            ClientPacket::QueryPluginCmds { request_id, plugin } => {
+                let cmds = {
+                    let read_guard = self.shared.data.read().unwrap();
+                    let owner = read_guard.owner_key(&plugin);
+                    let name = read_guard.owner_name(owner);
+                    read_guard
+                        .owned_by(owner)
+                        .map(|cmd| CmdOwner {
+                            cmd: cmd.to_string(),
+                            owner: name.to_string(),
+                        })
+                        .collect()
+                };
+                debug!("Answering the command query for `{plugin}`.");
+                self.send_cmd_owners(request_id, cmds)?;
+            }
+            ClientPacket::QueryPrefix { request_id, prefix } => {
+                let cmds = {
+                    let read_guard = self.shared.data.read().unwrap();
+                    read_guard
+                        .starting_with(&prefix)
+                        .map(|(cmd, owner)| CmdOwner {
+                            cmd: cmd.to_string(),
+                            owner: read_guard.owner_name(owner).to_string(),
+                        })
+                        .collect()
+                };
+                debug!("Answering the prefix query for `{prefix}`.");
+                self.send_cmd_owners(request_id, cmds)?;
+            }
+        }
+        Ok(self.result_after_handling())
+    }
+
+    fn take_token(&mut self, packet: &'static str) -> Result<(), Duration> {
+        let config = self.shared.config();
+        let limiter = &self.shared.limiter;
+        let result = self
+            .buckets
+            .take(&config.server, RateLimitScope::Connection, packet)
+            .map_err(|retry_after| (RateLimitScope::Connection, retry_after))
+            .and_then(|()| match &self.server_id {
+                // Servers with an API key often share an IP, e.g. with a hosting provider
+                Some(id) if self.authenticated => limiter
+                    .take_server(id, &config.server, packet)
+                    .map_err(|retry_after| (RateLimitScope::Server, retry_after)),
+                _ => limiter
+                    .take(self.peer, &config.server, packet)
+                    .map_err(|retry_after| (RateLimitScope::Ip, retry_after)),
+            });
+        result.map_err(|(scope, retry_after)| {
+            self.shared.metrics.rate_limited(scope);
+            retry_after
+        })
+    }
+
+    fn ad_policy(&self, ads: &AdsConfig) -> AdPolicy {
+        ads.policy(self.server_id.as_deref().filter(|_| self.authenticated))
+    }
+
+    // Returns `None` if this connection doesn't get ads at all
+    fn active_ad_policy(&self, ads: &AdsConfig) -> Option<AdPolicy> {
+        let policy = self.ad_policy(ads);
+        let opted_out = self.ads_opt_out && policy.allow_opt_out;
+        (policy.enabled && !opted_out).then_some(policy)
+    }
+
+    fn deny_missing_scope(&mut self, request_id: RequestId, scope: Scope) -> Result<()> {
+        debug!("Denying a request that needs the `{scope}` scope.");
+        let message = self.rich_text(Message::MissingScope, &[("scope", &scope)], &[]);
+        self.send_msg(request_id, Level::Error, message)?;
+        self.send_packet(&ServerPacket::Deny { request_id })
+            .context("failed to send the deny packet")?;
+        self.send_done(request_id)
+    }
+
+    fn text(&self, message: Message, args: &[(&str, &dyn Display)]) -> String {
+        self.shared.messages().render(&self.locale, message, args)
+    }
+
+    fn rich_text(
+        &self,
+        message: Message,
+        args: &[(&str, &dyn Display)],
+        texts: &[(&str, &Text)],
+    ) -> Text {
+        self.shared
+            .messages()
+            .render_text(&self.locale, message, args, texts)
+    }
+
+    // Links to the owning plugin's website if it declared one
+    fn owner_text(&self, owner: &str) -> Text {
+        let data = self.shared.data.read().unwrap();
+        let name = data.owner_name(owner);
+        match data.plugin(owner).and_then(|meta| meta.website.as_ref()) {
+            Some(website) => self.rich_text(
+                Message::OwnerLink,
+                &[("name", &name), ("website", website)],
+                &[],
+            ),
+            None => self.rich_text(Message::Owner, &[("name", &name)], &[]),
+        }
+    }
+
+    fn result_after_handling(&self) -> PacketResult {
+        if self.state.is_open() {
+            PacketResult::Ok
+        } else {
+            PacketResult::Disconnect
+        }
+    }
+
+    fn peer_closed(&mut self) -> PacketResult {
+        self.state = ConnectionState::Closed;
+        PacketResult::Disconnect
+    }
+
+    fn disconnect(&mut self, reason: DisconnectReason, message: String) -> PacketResult {
+        if self.state != ConnectionState::Closed {
+            self.state = ConnectionState::Closing;
+            self.disconnect_reason = reason;
+            self.disconnect_message = message;
+        }
+        PacketResult::Disconnect
+    }
+
+    fn close(&mut self) {
+        match self.state {
+            ConnectionState::Closed => return,
+            ConnectionState::Closing => {}
+            ConnectionState::Handshaking | ConnectionState::Active => {
+                self.state = ConnectionState::Closing
+            }
+        }
+
+        let packet = ServerPacket::Disconnect {
+            reason: self.disconnect_reason,
+            message: self.disconnect_message.clone(),
+        };
+        if let Err(error) = self.send_packet(&packet) {
+            warn!("Failed to gracefully disconnect the client: {error:?}");
+        }
+        self.state = ConnectionState::Closed;
+    }
+
+    fn handle_silence(&mut self) -> Result<()> {
+        let idle_timeout = self.shared.config().server.idle_timeout;
+        if !idle_timeout.is_zero() && self.last_received.elapsed() >= idle_timeout {
+            warn!("The client has been silent for too long.");
+            let message = self.text(Message::TimedOut, &[]);
+            self.disconnect(DisconnectReason::TimedOut, message);
+            return Ok(());
+        }
+
+        trace!("Pinging the silent client.");
+        self.send_packet(&ServerPacket::Ping)
+            .context("failed to send the ping packet")
+    }
+
+    fn handle_register(&mut self, request_id: RequestId, cmd: String) -> Result<()> {
+        let owner = self.shared.data.read().unwrap().check(&cmd);
+        let current_plugin = self.plugins.selected_owner();
+        match owner {
+            Some(plugin) if *plugin == current_plugin => {
+                debug!("Allowing registered command `{cmd}`.");
+                let message = self.rich_text(
+                    Message::Registered,
+                    &[("authors", &self.plugins.current_authors()), ("cmd", &cmd)],
+                    &[],
+                );
+                self.send_msg(request_id, Level::Debug, message)
+                    .context("failed to send the message packet")?;
+                self.maybe_send_ad(request_id, Some(&cmd))?;
+                self.shared
+                    .metrics
+                    .register_outcome(RegisterOutcome::Registered);
+                self.plugins
+                    .register_cmd(cmd, GlobalCommandStatus::Registered)
+            }
+            Some(owner) => {
+                let suggestions = {
+                    let read_guard = self.shared.data.read().unwrap();
+                    suggest::gen(self.plugins.selected(), &cmd, |name| {
+                        read_guard.check(name).is_some()
+                    })
+                };
+                debug!(
+                    "Denying command `{cmd}` and suggesting `{}`.",
+                    suggestions.join(", ")
+                );
+                self.shared
+                    .metrics
+                    .register_outcome(RegisterOutcome::Denied);
+
+                let owner = self.owner_text(&owner);
+                let message = self.rich_text(
+                    Message::AlreadyRegistered,
+                    &[("cmd", &cmd)],
+                    &[("owner", &owner)],
+                );
+                self.send_msg(request_id, Level::Error, message)
+                    .context("failed to send the message packet")?;
+                let suggestions = self.shared.messages().render_list(
+                    &self.locale,
+                    Message::Suggestion,
+                    Message::SuggestionSeparator,
+                    "cmd",
+                    &suggestions,
+                );
+                let message =
+                    self.rich_text(Message::Suggestions, &[], &[("suggestions", &suggestions)]);
+                self.send_msg(request_id, Level::Error, message)
+                    .context("failed to send the suggestion message packet")?;
+                self.send_packet(&ServerPacket::Deny { request_id })
+                    .context("failed to send the deny packet")?;
+            }
+            None => {
+                debug!("Allowing unregistered command `{cmd}`.");
+                let message = self.rich_text(
+                    Message::Unregistered,
+                    &[
+                        ("authors", &self.plugins.current_authors()),
+                        ("cmd", &cmd),
+                        ("plugin", &self.plugins.selected_owner()),
+                    ],
+                    &[],
+                );
+                self.send_msg(request_id, Level::Warn, message)
+                    .context("failed to send the message packet")?;
+                self.maybe_send_ad(request_id, Some(&cmd))?;
+                self.shared
+                    .metrics
+                    .register_outcome(RegisterOutcome::Unregistered);
+                self.plugins
+                    .register_cmd(cmd, GlobalCommandStatus::Unregistered);
+            }
+        }
+        self.send_done(request_id)
+    }
+
+    pub fn send_packet(&mut self, packet: &ServerPacket) -> Result<()> {
+        if self.state == ConnectionState::Closed {
+            trace!("Not sending packet to a closed connection: {packet:?}");
+            return Ok(());
+        }
+
+        trace!("Sending packet: {packet:?}");
+        let result = self
+            .stream
+            .write_packet(packet)
+            .with_context(|| format!("failed to write the packet ({packet:?})"))?;
+        if let PacketOpResult::AppearsDisconnected = result {
+            warn!("The client disconnected while we were writing to it.");
+            self.state = ConnectionState::Closed;
+        }
+        Ok(())
+    }
+
+    fn send_cmd_owners(&mut self, request_id: RequestId, mut cmds: Vec<CmdOwner>) -> Result<()> {
+        cmds.sort_unstable_by(|a, b| a.cmd.cmp(&b.cmd));
+        // Long names can overflow the packet before the count limit does
+        let mut buf = Vec::new();
+        ServerPacket::CmdOwners {
+            request_id,
+            cmds: Vec::new(),
+            truncated: true,
+        }
+        .write(&mut buf)
+        .context("failed to measure the command owners packet")?;
+        let mut fitting = 0;
+        for cmd in cmds.iter().take(MAX_QUERY_RESULTS) {
+            cmd.write_to(&mut buf)
+                .context("failed to measure a command owner")?;
+            if buf.len() > MAX_PAYLOAD_LEN {
+                break;
+            }
+            fitting += 1;
+        }
+        let truncated = cmds.len() > fitting;
+        cmds.truncate(fitting);
+        self.send_packet(&ServerPacket::CmdOwners {
+            request_id,
+            cmds,
+            truncated,
+        })
+        .context("failed to send the command owners packet")?;
+        self.send_done(request_id)
+    }
+
+    pub fn send_done(&mut self, request_id: RequestId) -> Result<()> {
+        self.send_packet(&ServerPacket::Done { request_id })
+            .context("failed to send the done packet")
+    }
+
+    pub fn send_msg(&mut self, request_id: RequestId, log_level: Level, msg: Text) -> Result<()> {
+        self.send_packet(&ServerPacket::Msg {
+            request_id,
+            log_level,
+            contents: msg.plain,
+            rich: msg.rich,
+        })
+        .context("failed to send the message packet")?;
+        self.msgs_sent += 1;
+        Ok(())
+    }
+
+    // Follows the messages of requests that weren't denied. `cmd` is the command the messages
+    // were about, which ads can target.
+    fn maybe_send_ad(&mut self, request_id: RequestId, cmd: Option<&str>) -> Result<()> {
+        let config = self.shared.config();
+        let Some(policy) = self.active_ad_policy(&config.ads) else {
+            return Ok(());
+        };
+        let mut rng = rand::thread_rng();
+        if !rng.gen_ratio(1, policy.one_in_x_chance) {
+            return Ok(());
+        }
+        if policy.max_per_connection != 0 && self.ads_sent >= policy.max_per_connection {
+            trace!("Not sending an ad because the connection got the maximum.");
+            self.shared
+                .metrics
+                .ad_suppressed(AdSuppression::MaxPerConnection);
+            return Ok(());
+        }
+        if self
+            .last_ad_at
+            .is_some_and(|last_ad_at| self.msgs_sent - last_ad_at < policy.min_msgs_between)
+        {
+            trace!("Not sending an ad because the last one was too recent.");
+            self.shared
+                .metrics
+                .ad_suppressed(AdSuppression::MinMsgsBetween);
+            return Ok(());
+        }
+
+        let selected = self.plugins.is_selected();
+        let target = AdTarget {
+            plugin: selected.then(|| self.plugins.selected()),
+            authors: selected.then(|| self.plugins.current_authors()),
+            cmd,
+        };
+        let campaign = {
+            let mut data = self.shared.data.write().unwrap();
+            ads::choose(&config.ads.campaigns, &target, &mut data, &mut rng)
+        };
+        if let Some(campaign) = campaign {
+            debug!("Sending an ad from the `{}` campaign.", campaign.name);
+            self.send_packet(&ServerPacket::Ad {
+                request_id,
+                contents: campaign.text.clone(),
+            })
+            .context("failed to send the ad packet")?;
+            self.ads_sent += 1;
+            self.last_ad_at = Some(self.msgs_sent);
+            self.shared.metrics.ad_sent();
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Drop for Connection<S> {
+    fn drop(&mut self) {
+        self.close();
+        self.shared.metrics.connection_closed();
+        self.shared.metrics.connection_ads(self.ads_sent);
+        info!("The connection is being dropped.");
+    }
+}
+
+fn required_scope(packet: &ClientPacket) -> Option<Scope> {
+    match packet {
+        ClientPacket::SelectPlugin { .. }
+        | ClientPacket::EnablePlugin { .. }
+        | ClientPacket::DisablePlugin { .. }
+        | ClientPacket::RegisterCmd { .. } => Some(Scope::Register),
+        ClientPacket::QueryOwner { .. }
+        | ClientPacket::QueryPluginCmds { .. }
+        | ClientPacket::QueryPrefix { .. } => Some(Scope::Query),
+        ClientPacket::Handshake { .. }
+        | ClientPacket::Disconnect { .. }
+        | ClientPacket::Ping { .. }
+        | ClientPacket::Pong => None,
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum ConnectionState {
+    Handshaking,
+    Active,
+    Closing,
+    Closed,
+}
+
+impl ConnectionState {
+    fn is_open(self) -> bool {
+        matches!(self, Self::Handshaking | Self::Active)
+    }
+}
+
+enum PacketResult {
+    Ok,
+    Disconnect,
+}
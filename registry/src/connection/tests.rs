@@ -0,0 +1,1404 @@
+use super::*;
+use crate::data::config::{AdCampaign, AdTier, AdsConfig, RateLimit, RateLimitScope, ServerConfig};
+use crate::data::store::{Ban, BanTarget};
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::Ipv4Addr;
+use std::time::{Duration, SystemTime};
+use std::{io, iter};
+
+enum Step {
+    Send(ClientPacket),
+    Raw { id: u8, payload: Vec<u8> },
+    Silence,
+    Reset,
+    Shutdown,
+}
+
+enum Chunk {
+    Bytes(VecDeque<u8>),
+    Error(ErrorKind),
+    Shutdown,
+}
+
+struct ScriptedStream {
+    chunks: VecDeque<Chunk>,
+    shutdown: Arc<Shutdown>,
+    read_pos: usize,
+    reset_read_at: Option<usize>,
+    output: Vec<u8>,
+    writes: usize,
+    reset_write_at: Option<usize>,
+    writes_after_reset: usize,
+}
+
+impl ScriptedStream {
+    fn new(steps: Vec<Step>, shutdown: Arc<Shutdown>) -> Self {
+        let chunks = steps
+            .into_iter()
+            .map(|step| match step {
+                Step::Send(packet) => {
+                    let mut bytes = Vec::new();
+                    bytes.write_packet(&packet).unwrap();
+                    Chunk::Bytes(bytes.into())
+                }
+                Step::Raw { id, payload } => {
+                    let len = u16::try_from(payload.len()).unwrap().to_be_bytes();
+                    Chunk::Bytes(
+                        len.into_iter()
+                            .chain(iter::once(id))
+                            .chain(payload)
+                            .collect(),
+                    )
+                }
+                Step::Silence => Chunk::Error(ErrorKind::WouldBlock),
+                Step::Reset => Chunk::Error(ErrorKind::ConnectionReset),
+                Step::Shutdown => Chunk::Shutdown,
+            })
+            .collect();
+        Self {
+            chunks,
+            shutdown,
+            read_pos: 0,
+            reset_read_at: None,
+            output: Vec::new(),
+            writes: 0,
+            reset_write_at: None,
+            writes_after_reset: 0,
+        }
+    }
+
+    fn input_len(&self) -> usize {
+        self.chunks
+            .iter()
+            .map(|chunk| match chunk {
+                Chunk::Bytes(bytes) => bytes.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+impl Read for ScriptedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.reset_read_at == Some(self.read_pos) {
+            return Err(ErrorKind::ConnectionReset.into());
+        }
+        loop {
+            match self.chunks.front_mut() {
+                None => return Ok(0),
+                Some(Chunk::Bytes(bytes)) => {
+                    let Some(byte) = bytes.pop_front() else {
+                        self.chunks.pop_front();
+                        continue;
+                    };
+                    buf[0] = byte;
+                    self.read_pos += 1;
+                    return Ok(1);
+                }
+                Some(Chunk::Error(kind)) => {
+                    let kind = *kind;
+                    self.chunks.pop_front();
+                    return Err(kind.into());
+                }
+                Some(Chunk::Shutdown) => {
+                    self.chunks.pop_front();
+                    self.shutdown.request();
+                    return Ok(0);
+                }
+            }
+        }
+    }
+}
+
+impl Write for ScriptedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let write = self.writes;
+        self.writes += 1;
+        match self.reset_write_at {
+            Some(reset) if write > reset => {
+                self.writes_after_reset += 1;
+                Err(ErrorKind::BrokenPipe.into())
+            }
+            Some(reset) if write == reset => Err(ErrorKind::ConnectionReset.into()),
+            _ => {
+                self.output.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Harness {
+    config: Config,
+    data: DataStore,
+    messages: Catalog,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let mut data = DataStore::default();
+        data.register("afk", "Essentials").unwrap();
+        data.register("anvil", "CMI").unwrap();
+        Self {
+            config: Config {
+                server: ServerConfig {
+                    error_tolerance: 0,
+                    ..ServerConfig::default()
+                },
+                ads: AdsConfig {
+                    enabled: false,
+                    ..AdsConfig::default()
+                },
+                ..Config::default()
+            },
+            data,
+            messages: Catalog::builtin(),
+        }
+    }
+
+    fn config(mut self, configure: impl FnOnce(&mut Config)) -> Self {
+        configure(&mut self.config);
+        self
+    }
+
+    fn data(mut self, configure: impl FnOnce(&mut DataStore)) -> Self {
+        configure(&mut self.data);
+        self
+    }
+
+    fn messages(mut self, configure: impl FnOnce(&mut Catalog)) -> Self {
+        configure(&mut self.messages);
+        self
+    }
+
+    fn stream(&self, steps: Vec<Step>) -> ScriptedStream {
+        ScriptedStream::new(steps, Arc::new(Shutdown::new()))
+    }
+
+    fn run(&self, steps: Vec<Step>) -> Outcome {
+        self.run_stream(self.stream(steps))
+    }
+
+    fn run_stream(&self, stream: ScriptedStream) -> Outcome {
+        let shared = SharedState {
+            config: Arc::new(ConfigHandle::new(self.config.clone())),
+            messages: Arc::new(CatalogHandle::new(self.messages.clone())),
+            data: Arc::new(RwLock::new(self.data.clone())),
+            metrics: Arc::default(),
+            limiter: Arc::default(),
+            shutdown: Arc::clone(&stream.shutdown),
+        };
+        let mut connection = Connection::new(stream, Ipv4Addr::LOCALHOST.into(), shared);
+        connection.run();
+
+        let mut output = &connection.stream.output[..];
+        let mut packets = Vec::new();
+        while let PacketOpResult::Ok(packet) = output.read_packet().unwrap() {
+            packets.push(packet);
+        }
+        Outcome {
+            packets,
+            connection,
+        }
+    }
+}
+
+struct Outcome {
+    packets: Vec<ServerPacket>,
+    connection: Connection<ScriptedStream>,
+}
+
+impl Outcome {
+    fn assert_packets(&self, expected: &[ServerPacket]) {
+        assert_eq!(self.packets, expected);
+        assert_eq!(self.connection.state, ConnectionState::Closed);
+    }
+}
+
+fn handshake() -> Step {
+    Step::Send(ClientPacket::Handshake {
+        request_id: 0,
+        version: "test".into(),
+        server_id: None,
+        api_key: None,
+        ads_opt_out: None,
+        locale: None,
+    })
+}
+
+fn handshake_with_key(server_id: Option<&str>, api_key: &str) -> Step {
+    Step::Send(ClientPacket::Handshake {
+        request_id: 0,
+        version: "test".into(),
+        server_id: server_id.map(Into::into),
+        api_key: Some(api_key.into()),
+        ads_opt_out: None,
+        locale: None,
+    })
+}
+
+fn handshake_response(ads_enabled: bool) -> ServerPacket {
+    ServerPacket::Handshake {
+        request_id: 0,
+        ads_enabled,
+        locale: "en".into(),
+    }
+}
+
+fn select(request_id: RequestId, name: &str, authors: Option<&str>) -> Step {
+    Step::Send(ClientPacket::SelectPlugin {
+        request_id,
+        name: name.into(),
+        authors: authors.map(Into::into),
+        plugin_id: None,
+        version: None,
+        website: None,
+        description: None,
+    })
+}
+
+fn select_with_id(request_id: RequestId, name: &str, id: &str, website: Option<&str>) -> Step {
+    Step::Send(ClientPacket::SelectPlugin {
+        request_id,
+        name: name.into(),
+        authors: Some("Essentials Team".into()),
+        plugin_id: Some(id.into()),
+        version: Some("2.20.0".into()),
+        website: website.map(Into::into),
+        description: Some("Provides essential commands.".into()),
+    })
+}
+
+fn register(request_id: RequestId, name: &str) -> Step {
+    Step::Send(ClientPacket::RegisterCmd {
+        request_id,
+        name: name.into(),
+    })
+}
+
+fn msg(request_id: RequestId, log_level: Level, contents: &str) -> ServerPacket {
+    ServerPacket::Msg {
+        request_id,
+        log_level,
+        contents: contents.into(),
+        rich: None,
+    }
+}
+
+fn rich_msg(request_id: RequestId, log_level: Level, contents: &str, rich: &str) -> ServerPacket {
+    ServerPacket::Msg {
+        request_id,
+        log_level,
+        contents: contents.into(),
+        rich: Some(rich.into()),
+    }
+}
+
+fn afk_thanks(request_id: RequestId) -> ServerPacket {
+    rich_msg(
+        request_id,
+        Level::Debug,
+        "Essentials Team, thank you for registering /afk!",
+        "Essentials Team, thank you for registering <green>/afk</green>!",
+    )
+}
+
+fn ad(request_id: RequestId, contents: &str) -> ServerPacket {
+    ServerPacket::Ad {
+        request_id,
+        contents: contents.into(),
+    }
+}
+
+fn cmd_owner(cmd: &str, owner: &str) -> CmdOwner {
+    CmdOwner {
+        cmd: cmd.into(),
+        owner: owner.into(),
+    }
+}
+
+fn disconnect(reason: DisconnectReason, message: &str) -> ServerPacket {
+    ServerPacket::Disconnect {
+        reason,
+        message: message.into(),
+    }
+}
+
+#[test]
+fn handshake_is_answered() {
+    Harness::new()
+        .run(vec![handshake()])
+        .assert_packets(&[handshake_response(false)]);
+    Harness::new()
+        .config(|config| config.ads.enabled = true)
+        .run(vec![handshake()])
+        .assert_packets(&[handshake_response(true)]);
+}
+
+#[test]
+fn ping_is_answered_before_handshake() {
+    Harness::new()
+        .run(vec![
+            Step::Send(ClientPacket::Ping { request_id: 3 }),
+            Step::Send(ClientPacket::Pong),
+        ])
+        .assert_packets(&[ServerPacket::Pong { request_id: 3 }]);
+}
+
+#[test]
+fn packet_before_handshake_is_a_protocol_violation() {
+    Harness::new()
+        .run(vec![Step::Send(ClientPacket::EnablePlugin {
+            request_id: 1,
+        })])
+        .assert_packets(&[disconnect(
+            DisconnectReason::ProtocolViolation,
+            "A handshake is required before any other packet.",
+        )]);
+}
+
+#[test]
+fn banned_servers_are_disconnected_at_handshake() {
+    Harness::new()
+        .data(|data| {
+            data.ban(Ban {
+                target: BanTarget::Server("griefers".into()),
+                reason: "Scraping.".into(),
+                expires: None,
+            })
+        })
+        .run(vec![Step::Send(ClientPacket::Handshake {
+            request_id: 0,
+            version: "test".into(),
+            server_id: Some("griefers".into()),
+            api_key: None,
+            ads_opt_out: None,
+            locale: None,
+        })])
+        .assert_packets(&[disconnect(
+            DisconnectReason::Banned,
+            "This server `griefers` is banned: Scraping.",
+        )]);
+}
+
+#[test]
+fn api_keys_identify_servers() {
+    let mut key = String::new();
+    Harness::new()
+        .data(|data| {
+            key = data.add_api_key("survival", Scope::ALL.into()).unwrap();
+            data.ban(Ban {
+                target: BanTarget::Server("survival".into()),
+                reason: "Scraping.".into(),
+                expires: None,
+            })
+        })
+        .run(vec![handshake_with_key(Some("creative"), &key)])
+        .assert_packets(&[disconnect(
+            DisconnectReason::Banned,
+            "This server `survival` is banned: Scraping.",
+        )]);
+}
+
+#[test]
+fn invalid_and_revoked_api_keys_are_unauthorized() {
+    let unauthorized = || {
+        disconnect(
+            DisconnectReason::Unauthorized,
+            "The API key is invalid or was revoked.",
+        )
+    };
+    Harness::new()
+        .run(vec![handshake_with_key(None, "csk_wrong")])
+        .assert_packets(&[unauthorized()]);
+
+    let mut key = String::new();
+    Harness::new()
+        .data(|data| {
+            key = data.add_api_key("survival", Scope::ALL.into()).unwrap();
+            data.revoke_api_key("survival").unwrap();
+        })
+        .run(vec![handshake_with_key(None, &key)])
+        .assert_packets(&[unauthorized()]);
+}
+
+#[test]
+fn api_keys_can_be_required() {
+    let harness = Harness::new().config(|config| config.server.require_api_key = true);
+    harness.run(vec![handshake()]).assert_packets(&[disconnect(
+        DisconnectReason::Unauthorized,
+        "This registry requires an API key.",
+    )]);
+
+    let mut key = String::new();
+    harness
+        .data(|data| key = data.add_api_key("survival", Scope::ALL.into()).unwrap())
+        .run(vec![handshake_with_key(None, &key)])
+        .assert_packets(&[handshake_response(false)]);
+}
+
+#[test]
+fn requests_outside_the_key_scopes_are_denied() {
+    let mut key = String::new();
+    Harness::new()
+        .data(|data| key = data.add_api_key("survival", [Scope::Query].into()).unwrap())
+        .run(vec![
+            handshake_with_key(None, &key),
+            select(1, "Essentials", None),
+            Step::Send(ClientPacket::QueryOwner {
+                request_id: 2,
+                cmd: "afk".into(),
+            }),
+        ])
+        .assert_packets(&[
+            handshake_response(false),
+            msg(
+                1,
+                Level::Error,
+                "This server's API key doesn't allow the `register` scope.",
+            ),
+            ServerPacket::Deny { request_id: 1 },
+            ServerPacket::Done { request_id: 1 },
+            ServerPacket::Owner {
+                request_id: 2,
+                owner: Some("Essentials".into()),
+            },
+            ServerPacket::Done { request_id: 2 },
+        ]);
+}
+
+#[test]
+fn handshaking_again_keeps_the_key_scopes() {
+    let mut key = String::new();
+    let outcome = Harness::new()
+        .data(|data| key = data.add_api_key("survival", [Scope::Query].into()).unwrap())
+        .run(vec![
+            handshake_with_key(None, &key),
+            handshake(),
+            select(1, "Essentials", None),
+        ]);
+    outcome.assert_packets(&[
+        handshake_response(false),
+        disconnect(
+            DisconnectReason::ProtocolViolation,
+            "The handshake can only be sent once.",
+        ),
+    ]);
+    assert_eq!(outcome.connection.server_id.as_deref(), Some("survival"));
+    assert!(outcome.connection.authenticated);
+    assert_eq!(outcome.connection.scopes, [Scope::Query].into());
+    assert!(!outcome.connection.plugins.is_selected());
+}
+
+#[test]
+fn expired_bans_are_ignored() {
+    Harness::new()
+        .data(|data| {
+            data.ban(Ban {
+                target: BanTarget::Ip("127.0.0.0/8".parse().unwrap()),
+                reason: "Scraping.".into(),
+                expires: Some(SystemTime::UNIX_EPOCH),
+            })
+        })
+        .run(vec![handshake()])
+        .assert_packets(&[handshake_response(false)]);
+}
+
+fn handshake_in(locale: &str) -> Step {
+    Step::Send(ClientPacket::Handshake {
+        request_id: 0,
+        version: "test".into(),
+        server_id: None,
+        api_key: None,
+        ads_opt_out: None,
+        locale: Some(locale.into()),
+    })
+}
+
+fn german(catalog: &mut Catalog) {
+    catalog
+        .add_locale(
+            "de",
+            r#"
+registered = "{authors}, danke für die Registrierung von /{cmd}!"
+banned_plugin = "Das Plugin `{target}` ist gesperrt: {reason}{expiry}"
+"#,
+        )
+        .unwrap();
+}
+
+#[test]
+fn messages_are_sent_in_the_negotiated_locale() {
+    Harness::new()
+        .messages(german)
+        .run(vec![
+            handshake_in("de-AT"),
+            select(1, "Essentials", Some("Essentials Team")),
+            register(2, "afk"),
+        ])
+        .assert_packets(&[
+            ServerPacket::Handshake {
+                request_id: 0,
+                ads_enabled: false,
+                locale: "de".into(),
+            },
+            ServerPacket::Done { request_id: 1 },
+            msg(
+                2,
+                Level::Debug,
+                "Essentials Team, danke für die Registrierung von /afk!",
+            ),
+            ServerPacket::Done { request_id: 2 },
+        ]);
+}
+
+#[test]
+fn unknown_locales_get_the_default_messages() {
+    Harness::new()
+        .messages(german)
+        .data(|data| {
+            data.ban(Ban {
+                target: BanTarget::Plugin("Essentials".into()),
+                reason: "Malware.".into(),
+                expires: None,
+            })
+        })
+        .run(vec![handshake_in("fr"), select(1, "Essentials", None)])
+        .assert_packets(&[
+            handshake_response(false),
+            disconnect(
+                DisconnectReason::Banned,
+                "This plugin `Essentials` is banned: Malware.",
+            ),
+        ]);
+}
+
+#[test]
+fn selecting_a_banned_plugin_disconnects() {
+    Harness::new()
+        .data(|data| {
+            data.ban(Ban {
+                target: BanTarget::Plugin("Essentials".into()),
+                reason: "Malware.".into(),
+                expires: None,
+            })
+        })
+        .run(vec![handshake(), select(1, "Essentials", None)])
+        .assert_packets(&[
+            handshake_response(false),
+            disconnect(
+                DisconnectReason::Banned,
+                "This plugin `Essentials` is banned: Malware.",
+            ),
+        ]);
+}
+
+#[test]
+fn client_disconnect_is_not_answered() {
+    Harness::new()
+        .run(vec![
+            handshake(),
+            Step::Send(ClientPacket::Disconnect {
+                reason: DisconnectReason::Shutdown,
+                message: "goodbye!".into(),
+            }),
+            Step::Send(ClientPacket::Ping { request_id: 1 }),
+        ])
+        .assert_packets(&[handshake_response(false)]);
+}
+
+#[test]
+fn plugin_selection_and_toggling() {
+    let outcome = Harness::new().run(vec![
+        handshake(),
+        select(1, "Test", Some("Tester")),
+        Step::Send(ClientPacket::EnablePlugin { request_id: 2 }),
+        Step::Send(ClientPacket::DisablePlugin { request_id: 3 }),
+        select(4, "Test", None),
+    ]);
+    outcome.assert_packets(&[
+        handshake_response(false),
+        ServerPacket::Done { request_id: 1 },
+        ServerPacket::Done { request_id: 2 },
+        ServerPacket::Done { request_id: 3 },
+        ServerPacket::Done { request_id: 4 },
+    ]);
+    assert_eq!(outcome.connection.plugins.selected(), "Test");
+    assert_eq!(outcome.connection.plugins.current_authors(), "Tester");
+}
+
+#[test]
+fn failed_requests_count_towards_the_error_tolerance() {
+    let error_threshold = disconnect(
+        DisconnectReason::ErrorThreshold,
+        "Too many packets couldn't be handled.",
+    );
+    Harness::new()
+        .run(vec![handshake(), select(1, "Unknown", None)])
+        .assert_packets(&[handshake_response(false), error_threshold.clone()]);
+    Harness::new()
+        .run(vec![
+            handshake(),
+            select(1, "Test", Some("Tester")),
+            Step::Send(ClientPacket::DisablePlugin { request_id: 2 }),
+        ])
+        .assert_packets(&[
+            handshake_response(false),
+            ServerPacket::Done { request_id: 1 },
+            error_threshold.clone(),
+        ]);
+    Harness::new()
+        .run(vec![Step::Raw {
+            id: 0xff,
+            payload: Vec::new(),
+        }])
+        .assert_packets(&[error_threshold]);
+
+    Harness::new()
+        .config(|config| config.server.error_tolerance = -1)
+        .run(vec![
+            handshake(),
+            select(1, "Unknown", None),
+            select(2, "Test", Some("Tester")),
+        ])
+        .assert_packets(&[
+            handshake_response(false),
+            ServerPacket::Done { request_id: 2 },
+        ]);
+}
+
+#[test]
+fn registering_an_owned_command_is_allowed() {
+    Harness::new()
+        .run(vec![
+            handshake(),
+            select(1, "Essentials", Some("Essentials Team")),
+            register(2, "afk"),
+        ])
+        .assert_packets(&[
+            handshake_response(false),
+            ServerPacket::Done { request_id: 1 },
+            afk_thanks(2),
+            ServerPacket::Done { request_id: 2 },
+        ]);
+}
+
+fn essentials(website: Option<&str>) -> PluginMeta {
+    PluginMeta {
+        name: "Essentials".into(),
+        version: Some("2.20.0".into()),
+        website: website.map(Into::into),
+        description: Some("Provides essential commands.".into()),
+        recorded_by: None,
+    }
+}
+
+fn recorded_by(server: &str, meta: PluginMeta) -> PluginMeta {
+    PluginMeta {
+        recorded_by: Some(server.into()),
+        ..meta
+    }
+}
+
+fn query_owner(request_id: RequestId, cmd: &str) -> Step {
+    Step::Send(ClientPacket::QueryOwner {
+        request_id,
+        cmd: cmd.into(),
+    })
+}
+
+#[test]
+fn plugin_ids_take_over_commands_owned_by_name() {
+    let mut key = String::new();
+    let outcome = Harness::new()
+        .data(|data| key = data.add_api_key("survival", Scope::ALL.into()).unwrap())
+        .run(vec![
+            handshake_with_key(None, &key),
+            select_with_id(1, "Essentials", "essentials", None),
+            register(2, "afk"),
+        ]);
+    outcome.assert_packets(&[
+        handshake_response(false),
+        ServerPacket::Done { request_id: 1 },
+        afk_thanks(2),
+        ServerPacket::Done { request_id: 2 },
+    ]);
+    let data = outcome.connection.shared.data.read().unwrap();
+    assert_eq!(*data.check("afk").unwrap(), "essentials");
+    assert_eq!(
+        data.plugin("essentials"),
+        Some(&recorded_by("survival", essentials(None)))
+    );
+}
+
+#[test]
+fn unauthenticated_servers_cannot_take_over_commands() {
+    let outcome = Harness::new().run(vec![
+        handshake(),
+        select_with_id(1, "Essentials", "essentials", Some("https://example.com")),
+        register(2, "afk"),
+    ]);
+    assert!(outcome
+        .packets
+        .contains(&ServerPacket::Deny { request_id: 2 }));
+    let data = outcome.connection.shared.data.read().unwrap();
+    assert_eq!(*data.check("afk").unwrap(), "Essentials");
+    assert_eq!(data.plugin("essentials"), None);
+}
+
+#[test]
+fn plugins_recorded_by_another_server_are_kept() {
+    let mut key = String::new();
+    let outcome = Harness::new()
+        .data(|data| {
+            key = data.add_api_key("survival", Scope::ALL.into()).unwrap();
+            data.record_plugin("essentials", "creative", essentials(None))
+                .unwrap();
+        })
+        .run(vec![
+            handshake_with_key(None, &key),
+            select_with_id(1, "Evil", "essentials", Some("https://example.com")),
+        ]);
+    let data = outcome.connection.shared.data.read().unwrap();
+    assert_eq!(
+        data.plugin("essentials"),
+        Some(&recorded_by("creative", essentials(None)))
+    );
+}
+
+#[test]
+fn plugin_names_belong_to_one_id() {
+    let mut key = String::new();
+    let outcome = Harness::new()
+        .data(|data| {
+            key = data.add_api_key("survival", Scope::ALL.into()).unwrap();
+            data.record_plugin("essentials", "survival", essentials(None))
+                .unwrap();
+        })
+        .run(vec![
+            handshake_with_key(None, &key),
+            select_with_id(1, "Essentials", "essentials-fork", None),
+        ]);
+    let data = outcome.connection.shared.data.read().unwrap();
+    assert_eq!(data.plugin("essentials-fork"), None);
+    assert_eq!(data.owner_key("Essentials"), "essentials");
+}
+
+#[test]
+fn registering_before_selecting_is_a_protocol_violation() {
+    Harness::new()
+        .run(vec![handshake(), register(1, "afk")])
+        .assert_packets(&[
+            handshake_response(false),
+            disconnect(
+                DisconnectReason::ProtocolViolation,
+                "A plugin must be selected before enabling, disabling or registering commands.",
+            ),
+        ]);
+}
+
+#[test]
+fn renamed_plugins_keep_their_commands() {
+    let mut key = String::new();
+    Harness::new()
+        .data(|data| {
+            key = data.add_api_key("survival", Scope::ALL.into()).unwrap();
+            data.record_plugin("essentials", "survival", essentials(None))
+                .unwrap();
+        })
+        .run(vec![
+            handshake_with_key(None, &key),
+            select_with_id(1, "EssentialsX", "essentials", None),
+            register(2, "afk"),
+            query_owner(3, "afk"),
+        ])
+        .assert_packets(&[
+            handshake_response(false),
+            ServerPacket::Done { request_id: 1 },
+            afk_thanks(2),
+            ServerPacket::Done { request_id: 2 },
+            ServerPacket::Owner {
+                request_id: 3,
+                owner: Some("EssentialsX".into()),
+            },
+            ServerPacket::Done { request_id: 3 },
+        ]);
+}
+
+#[test]
+fn denials_link_to_the_owners_website() {
+    let outcome = Harness::new()
+        .data(|data| {
+            data.record_plugin(
+                "essentials",
+                "survival",
+                essentials(Some("https://essentialsx.net")),
+            )
+            .unwrap();
+        })
+        .run(vec![
+            handshake(),
+            select(1, "Test", Some("Tester")),
+            register(2, "afk"),
+        ]);
+    assert_eq!(
+        outcome.packets[2],
+        rich_msg(
+            2,
+            Level::Error,
+            "/afk is already registered to Essentials. Please choose a different name.",
+            "<hover:show_text:'Registered to Essentials'><red>/afk</red></hover> \
+                is already registered to <click:open_url:'https://essentialsx.net'>\
+                <hover:show_text:'https://essentialsx.net'><u>Essentials</u></hover></click>. \
+                Please choose a different name.",
+        )
+    );
+}
+
+#[test]
+fn plugins_can_be_banned_by_id() {
+    Harness::new()
+        .data(|data| {
+            data.ban(Ban {
+                target: BanTarget::Plugin("essentials".into()),
+                reason: "Malware.".into(),
+                expires: None,
+            })
+        })
+        .run(vec![
+            handshake(),
+            select_with_id(1, "EssentialsX", "essentials", None),
+        ])
+        .assert_packets(&[
+            handshake_response(false),
+            disconnect(
+                DisconnectReason::Banned,
+                "This plugin `essentials` is banned: Malware.",
+            ),
+        ]);
+}
+
+#[test]
+fn websites_must_be_http_urls() {
+    let mut key = String::new();
+    let outcome = Harness::new()
+        .data(|data| key = data.add_api_key("survival", Scope::ALL.into()).unwrap())
+        .run(vec![
+            handshake_with_key(None, &key),
+            select_with_id(1, "Essentials", "essentials", Some("javascript:alert(1)")),
+        ]);
+    let data = outcome.connection.shared.data.read().unwrap();
+    assert_eq!(
+        data.plugin("essentials"),
+        Some(&recorded_by("survival", essentials(None)))
+    );
+}
+
+#[test]
+fn registering_an_unowned_command_is_allowed() {
+    let outcome = Harness::new().run(vec![
+        handshake(),
+        select(1, "Test", Some("Tester")),
+        register(2, "test"),
+    ]);
+    outcome.assert_packets(&[
+        handshake_response(false),
+        ServerPacket::Done { request_id: 1 },
+        rich_msg(
+            2,
+            Level::Warn,
+            "Hey, Tester! Your command /test is unregistered. \
+                Please register it with \"/register test Test\".",
+            "Hey, Tester! Your command <yellow>/test</yellow> is unregistered. \
+                Please register it with <click:copy_to_clipboard:'/register test Test'>\
+                <hover:show_text:'Click to copy'>\"/register test Test\"</hover></click>.",
+        ),
+        ServerPacket::Done { request_id: 2 },
+    ]);
+}
+
+#[test]
+fn registering_a_taken_command_is_denied() {
+    let outcome = Harness::new().run(vec![
+        handshake(),
+        select(1, "Test", Some("Tester")),
+        register(2, "afk"),
+    ]);
+    let [handshake, selected, taken, suggestions, deny, done] = &outcome.packets[..] else {
+        panic!("unexpected packets: {:?}", outcome.packets);
+    };
+    assert_eq!(*handshake, handshake_response(false));
+    assert_eq!(*selected, ServerPacket::Done { request_id: 1 });
+    assert_eq!(
+        *taken,
+        rich_msg(
+            2,
+            Level::Error,
+            "/afk is already registered to Essentials. Please choose a different name.",
+            "<hover:show_text:'Registered to Essentials'><red>/afk</red></hover> \
+                is already registered to Essentials. Please choose a different name.",
+        )
+    );
+    assert!(matches!(
+        suggestions,
+        ServerPacket::Msg {
+            request_id: 2,
+            log_level: Level::Error,
+            contents,
+            rich: Some(_),
+        } if contents.starts_with("Try one of these instead: ")
+    ));
+    assert_eq!(*deny, ServerPacket::Deny { request_id: 2 });
+    assert_eq!(*done, ServerPacket::Done { request_id: 2 });
+}
+
+#[test]
+fn ads_follow_messages() {
+    Harness::new()
+        .config(|config| config.ads = every_time(vec![campaign("things", "Buy things!")]))
+        .run(vec![
+            handshake(),
+            select(1, "Essentials", Some("Essentials Team")),
+            register(2, "afk"),
+        ])
+        .assert_packets(&[
+            handshake_response(true),
+            ServerPacket::Done { request_id: 1 },
+            afk_thanks(2),
+            ad(2, "Buy things!"),
+            ServerPacket::Done { request_id: 2 },
+        ]);
+}
+
+// Sends an ad after every message, without any frequency caps
+fn every_time(campaigns: Vec<AdCampaign>) -> AdsConfig {
+    AdsConfig {
+        enabled: true,
+        one_in_x_chance: 1,
+        max_per_connection: 0,
+        min_msgs_between: 0,
+        campaigns,
+        ..AdsConfig::default()
+    }
+}
+
+fn campaign(name: &str, text: &str) -> AdCampaign {
+    AdCampaign {
+        name: name.into(),
+        text: text.into(),
+        ..AdCampaign::default()
+    }
+}
+
+#[test]
+fn ads_are_chosen_from_targeted_campaigns() {
+    let mut economy = campaign("economy", "Host your economy with us!");
+    economy.cmds = vec!["eco*".into()];
+    let mut capped = campaign("capped", "Buy things!");
+    capped.max_impressions = Some(0);
+    let outcome = Harness::new()
+        .config(|config| config.ads = every_time(vec![economy, capped]))
+        .run(vec![
+            handshake(),
+            select(1, "Essentials", Some("Essentials Team")),
+            register(2, "afk"),
+            register(3, "eco"),
+        ]);
+    assert!(!outcome
+        .packets
+        .iter()
+        .any(|packet| matches!(packet, ServerPacket::Ad { request_id: 2, .. })));
+    assert!(outcome
+        .packets
+        .contains(&ad(3, "Host your economy with us!")));
+    assert_eq!(
+        outcome
+            .connection
+            .shared
+            .data
+            .read()
+            .unwrap()
+            .ad_impressions("economy"),
+        1
+    );
+}
+
+fn ad_request_ids(ads: AdsConfig, steps: Vec<Step>) -> Vec<RequestId> {
+    Harness::new()
+        .config(|config| config.ads = ads)
+        .run(steps)
+        .packets
+        .iter()
+        .filter_map(|packet| match packet {
+            ServerPacket::Ad { request_id, .. } => Some(*request_id),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn ads_are_frequency_capped() {
+    let ads = every_time(vec![campaign("things", "Buy things!")]);
+    let steps = || {
+        let mut steps = vec![
+            handshake(),
+            select(1, "Essentials", Some("Essentials Team")),
+        ];
+        steps.extend((2..8).map(|request_id| register(request_id, "afk")));
+        steps
+    };
+
+    let capped = AdsConfig {
+        max_per_connection: 2,
+        ..ads.clone()
+    };
+    assert_eq!(ad_request_ids(capped, steps()), [2, 3]);
+
+    let spaced = AdsConfig {
+        min_msgs_between: 2,
+        ..ads
+    };
+    assert_eq!(ad_request_ids(spaced, steps()), [2, 4, 6]);
+}
+
+#[test]
+fn denied_registrations_get_no_ads() {
+    let ads = every_time(vec![campaign("things", "Buy things!")]);
+    let steps = vec![
+        handshake(),
+        select(1, "Essentials", Some("Essentials Team")),
+        register(2, "anvil"),
+        register(3, "afk"),
+    ];
+    assert_eq!(ad_request_ids(ads, steps), [3]);
+}
+
+fn ads_config(allow_opt_out: bool) -> AdsConfig {
+    AdsConfig {
+        allow_opt_out,
+        tiers: [(
+            "partners".into(),
+            AdTier {
+                enabled: Some(false),
+                ..AdTier::default()
+            },
+        )]
+        .into(),
+        servers: [("survival".into(), "partners".into())].into(),
+        ..every_time(vec![campaign("things", "Buy things!")])
+    }
+}
+
+fn handshake_opting_out() -> Step {
+    Step::Send(ClientPacket::Handshake {
+        request_id: 0,
+        version: "test".into(),
+        server_id: None,
+        api_key: None,
+        ads_opt_out: Some(true),
+        locale: None,
+    })
+}
+
+fn registered_afk(ad_sent: bool) -> Vec<ServerPacket> {
+    let mut packets = vec![ServerPacket::Done { request_id: 1 }, afk_thanks(2)];
+    packets.extend(ad_sent.then(|| ad(2, "Buy things!")));
+    packets.push(ServerPacket::Done { request_id: 2 });
+    packets
+}
+
+#[test]
+fn ad_opt_out_is_honored_if_allowed() {
+    let steps = || {
+        vec![
+            handshake_opting_out(),
+            select(1, "Essentials", Some("Essentials Team")),
+            register(2, "afk"),
+        ]
+    };
+
+    let mut expected = vec![handshake_response(false)];
+    expected.extend(registered_afk(false));
+    Harness::new()
+        .config(|config| config.ads = ads_config(true))
+        .run(steps())
+        .assert_packets(&expected);
+
+    let mut expected = vec![handshake_response(true)];
+    expected.extend(registered_afk(true));
+    Harness::new()
+        .config(|config| config.ads = ads_config(false))
+        .run(steps())
+        .assert_packets(&expected);
+}
+
+#[test]
+fn ad_tiers_apply_to_authenticated_servers() {
+    let mut key = String::new();
+    let mut expected = vec![handshake_response(false)];
+    expected.extend(registered_afk(false));
+    Harness::new()
+        .config(|config| config.ads = ads_config(false))
+        .data(|data| key = data.add_api_key("survival", Scope::ALL.into()).unwrap())
+        .run(vec![
+            handshake_with_key(None, &key),
+            select(1, "Essentials", Some("Essentials Team")),
+            register(2, "afk"),
+        ])
+        .assert_packets(&expected);
+
+    // Claiming the ID without the key doesn't exempt the server
+    Harness::new()
+        .config(|config| config.ads = ads_config(false))
+        .run(vec![Step::Send(ClientPacket::Handshake {
+            request_id: 0,
+            version: "test".into(),
+            server_id: Some("survival".into()),
+            api_key: None,
+            ads_opt_out: None,
+            locale: None,
+        })])
+        .assert_packets(&[handshake_response(true)]);
+}
+
+#[test]
+fn queries_are_answered() {
+    Harness::new()
+        .run(vec![
+            handshake(),
+            Step::Send(ClientPacket::QueryOwner {
+                request_id: 1,
+                cmd: "afk".into(),
+            }),
+            Step::Send(ClientPacket::QueryOwner {
+                request_id: 2,
+                cmd: "nobody".into(),
+            }),
+            Step::Send(ClientPacket::QueryPluginCmds {
+                request_id: 3,
+                plugin: "CMI".into(),
+            }),
+            Step::Send(ClientPacket::QueryPrefix {
+                request_id: 4,
+                prefix: "a".into(),
+            }),
+        ])
+        .assert_packets(&[
+            handshake_response(false),
+            ServerPacket::Owner {
+                request_id: 1,
+                owner: Some("Essentials".into()),
+            },
+            ServerPacket::Done { request_id: 1 },
+            ServerPacket::Owner {
+                request_id: 2,
+                owner: None,
+            },
+            ServerPacket::Done { request_id: 2 },
+            ServerPacket::CmdOwners {
+                request_id: 3,
+                cmds: vec![cmd_owner("anvil", "CMI")],
+                truncated: false,
+            },
+            ServerPacket::Done { request_id: 3 },
+            ServerPacket::CmdOwners {
+                request_id: 4,
+                cmds: vec![cmd_owner("afk", "Essentials"), cmd_owner("anvil", "CMI")],
+                truncated: false,
+            },
+            ServerPacket::Done { request_id: 4 },
+        ]);
+}
+
+#[test]
+fn requests_over_the_rate_limit_are_answered_with_rate_limited() {
+    let outcome = Harness::new()
+        .config(|config| {
+            config.server.rate_limits = vec![RateLimit {
+                packet: "QueryOwner".into(),
+                scope: RateLimitScope::Connection,
+                per_minute: 1,
+                burst: 1,
+            }]
+        })
+        .run(vec![
+            handshake(),
+            Step::Send(ClientPacket::QueryOwner {
+                request_id: 1,
+                cmd: "afk".into(),
+            }),
+            Step::Send(ClientPacket::QueryOwner {
+                request_id: 2,
+                cmd: "afk".into(),
+            }),
+            Step::Send(ClientPacket::Ping { request_id: 3 }),
+        ]);
+    let [_, _, _, rate_limited, pong] = &outcome.packets[..] else {
+        panic!("unexpected packets: {:?}", outcome.packets);
+    };
+    assert!(matches!(
+        rate_limited,
+        ServerPacket::RateLimited {
+            request_id: 2,
+            retry_after_ms: 59_000..=60_000,
+        }
+    ));
+    assert_eq!(*pong, ServerPacket::Pong { request_id: 3 });
+}
+
+#[test]
+fn large_query_results_are_truncated() {
+    let outcome = Harness::new()
+        .data(|data| {
+            for i in 0..MAX_QUERY_RESULTS + 1 {
+                data.register(format!("cmd{i:03}"), "Big").unwrap();
+            }
+        })
+        .run(vec![
+            handshake(),
+            Step::Send(ClientPacket::QueryPluginCmds {
+                request_id: 1,
+                plugin: "Big".into(),
+            }),
+        ]);
+    let ServerPacket::CmdOwners {
+        cmds, truncated, ..
+    } = &outcome.packets[1]
+    else {
+        panic!("unexpected packets: {:?}", outcome.packets);
+    };
+    assert_eq!(cmds.len(), MAX_QUERY_RESULTS);
+    assert_eq!(cmds[0].cmd, "cmd000");
+    assert!(truncated);
+}
+
+#[test]
+fn query_results_are_truncated_to_fit_in_a_packet() {
+    let outcome = Harness::new()
+        .data(|data| {
+            for i in 0..200 {
+                data.register(format!("{i:03}{}", "x".repeat(600)), "Long")
+                    .unwrap();
+            }
+        })
+        .run(vec![
+            handshake(),
+            Step::Send(ClientPacket::QueryPluginCmds {
+                request_id: 1,
+                plugin: "Long".into(),
+            }),
+        ]);
+    let ServerPacket::CmdOwners {
+        cmds, truncated, ..
+    } = &outcome.packets[1]
+    else {
+        panic!("unexpected packets: {:?}", outcome.packets);
+    };
+    assert!(truncated);
+    assert!(!cmds.is_empty() && cmds.len() < 200);
+    let mut payload = Vec::new();
+    outcome.packets[1].write(&mut payload).unwrap();
+    assert!(payload.len() <= MAX_PAYLOAD_LEN);
+    assert_eq!(outcome.packets[2], ServerPacket::Done { request_id: 1 });
+}
+
+#[test]
+fn silence_is_pinged_then_timed_out() {
+    Harness::new()
+        .config(|config| config.server.idle_timeout = Duration::from_secs(3600))
+        .run(vec![handshake(), Step::Silence])
+        .assert_packets(&[handshake_response(false), ServerPacket::Ping]);
+    Harness::new()
+        .config(|config| config.server.idle_timeout = Duration::from_nanos(1))
+        .run(vec![handshake(), Step::Silence, handshake()])
+        .assert_packets(&[
+            handshake_response(false),
+            disconnect(DisconnectReason::TimedOut, "The connection timed out."),
+        ]);
+}
+
+#[test]
+fn reset_closes_without_disconnect_packet() {
+    Harness::new()
+        .run(vec![handshake(), Step::Reset, handshake()])
+        .assert_packets(&[handshake_response(false)]);
+}
+
+#[test]
+fn shutdown_disconnects() {
+    Harness::new()
+        .run(vec![handshake(), Step::Shutdown, handshake()])
+        .assert_packets(&[
+            handshake_response(false),
+            disconnect(
+                DisconnectReason::Shutdown,
+                "The registry server is shutting down.",
+            ),
+        ]);
+}
+
+#[test]
+fn closing_an_open_connection_sends_disconnect() {
+    let harness = Harness::new();
+    let shared = SharedState {
+        config: Arc::new(ConfigHandle::new(harness.config.clone())),
+        messages: Arc::default(),
+        data: Arc::new(RwLock::new(harness.data.clone())),
+        metrics: Arc::default(),
+        limiter: Arc::default(),
+        shutdown: Arc::new(Shutdown::new()),
+    };
+    let mut connection = Connection::new(
+        harness.stream(Vec::new()),
+        Ipv4Addr::LOCALHOST.into(),
+        shared,
+    );
+    connection.close();
+    assert_eq!(connection.state, ConnectionState::Closed);
+
+    let mut expected = Vec::new();
+    expected
+        .write_packet(&disconnect(
+            DisconnectReason::Requested,
+            "The connection was closed.",
+        ))
+        .unwrap();
+    assert_eq!(connection.stream.output, expected);
+}
+
+#[test]
+fn resets_at_every_stage_close_cleanly() {
+    let harness = Harness::new();
+    let script = || {
+        vec![
+            handshake(),
+            select(1, "Test", Some("Tester")),
+            register(2, "test"),
+            register(3, "other"),
+        ]
+    };
+    let full = harness.run(script());
+    let full_output = &full.connection.stream.output;
+
+    for reset_read_at in 0..=harness.stream(script()).input_len() {
+        let mut stream = harness.stream(script());
+        stream.reset_read_at = Some(reset_read_at);
+        let outcome = harness.run_stream(stream);
+        assert_eq!(outcome.connection.state, ConnectionState::Closed);
+        assert!(full_output.starts_with(&outcome.connection.stream.output));
+    }
+
+    for reset_write_at in 0..full.connection.stream.writes {
+        let mut stream = harness.stream(script());
+        stream.reset_write_at = Some(reset_write_at);
+        let outcome = harness.run_stream(stream);
+        assert_eq!(outcome.connection.state, ConnectionState::Closed);
+        assert_eq!(outcome.connection.stream.writes_after_reset, 0);
+        assert_eq!(outcome.packets.len(), reset_write_at);
+    }
+}
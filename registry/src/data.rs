@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use log::warn;
+use log::{info, warn};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::borrow::Cow;
@@ -7,6 +7,7 @@ use std::fs;
 use std::path::Path;
 
 pub mod config;
+pub mod lock;
 pub mod store;
 
 pub trait PersistentData: DeserializeOwned {
@@ -14,10 +15,52 @@ pub trait PersistentData: DeserializeOwned {
     const DEFAULT: &'static str;
     const SAVE_DEFAULT: bool;
 
+    fn from_toml(contents: &str) -> Result<Self> {
+        toml::from_str(contents)
+            .with_context(|| format!("failed to deserialize the {}", Self::DESCRIPTION_LOWERCASE))
+    }
+
+    // Brings an older file up to date, returning the new contents if anything changed
+    fn upgrade(_contents: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).with_context(|| {
+            format!(
+                "failed to read from the {} at `{}`",
+                Self::DESCRIPTION_LOWERCASE,
+                path.display()
+            )
+        })?;
+        match Self::upgrade(&contents)? {
+            Some(upgraded) => Self::from_toml(&upgraded),
+            None => Self::from_toml(&contents),
+        }
+    }
+
     fn load_or_default(path: impl AsRef<Path>) -> Result<Self> {
         let path = path.as_ref();
         let contents = match fs::read_to_string(path) {
-            Ok(contents) => Cow::Owned(contents),
+            Ok(contents) => match Self::upgrade(&contents)? {
+                Some(upgraded) => {
+                    fs::write(path, &upgraded).with_context(|| {
+                        format!(
+                            "failed to write the upgraded {} to `{}`",
+                            Self::DESCRIPTION_LOWERCASE,
+                            path.display()
+                        )
+                    })?;
+                    info!(
+                        "Upgraded the {} at `{}`.",
+                        Self::DESCRIPTION_LOWERCASE,
+                        path.display()
+                    );
+                    Cow::Owned(upgraded)
+                }
+                None => Cow::Owned(contents),
+            },
             Err(_) if !path.exists() => {
                 warn!("Creating a new {}.", Self::DESCRIPTION_LOWERCASE);
                 if Self::SAVE_DEFAULT {
@@ -42,8 +85,7 @@ pub trait PersistentData: DeserializeOwned {
             }
         };
 
-        toml::from_str(&contents)
-            .with_context(|| format!("failed to deserialize the {}", Self::DESCRIPTION_LOWERCASE))
+        Self::from_toml(&contents)
     }
 
     fn save(&self, path: impl AsRef<Path>) -> Result<()>
@@ -53,10 +95,21 @@ pub trait PersistentData: DeserializeOwned {
         let path = path.as_ref();
         let serialized = toml::to_string(self)
             .with_context(|| format!("failed to serialize the {}", Self::DESCRIPTION_LOWERCASE))?;
-        fs::write(path, serialized).with_context(|| {
+
+        // Write to a temporary file first so readers never see a partially written file
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        fs::write(&temp_path, serialized).with_context(|| {
             format!(
                 "failed to write the {} to `{}`",
                 Self::DESCRIPTION_LOWERCASE,
+                Path::new(&temp_path).display()
+            )
+        })?;
+        fs::rename(&temp_path, path).with_context(|| {
+            format!(
+                "failed to move the {} to `{}`",
+                Self::DESCRIPTION_LOWERCASE,
                 path.display()
             )
         })?;
@@ -1,32 +1,332 @@
+use crate::access::Cidr;
 use crate::data::PersistentData;
+use anyhow::{bail, Context, Result};
+use log::{debug, warn, LevelFilter};
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use std::{env, mem};
+use toml::{Table, Value};
+
+mod migrate;
+mod validate;
+
+pub use validate::Issue;
+
+pub const ENV_PREFIX: &str = "CARDSTOCK_REGISTRY__";
+
+#[cfg(test)]
+mod tests;
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub server: ServerConfig,
     pub save: SaveConfig,
     pub ads: AdsConfig,
+    pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+    pub access: AccessConfig,
+    pub messages: MessagesConfig,
 }
 
-#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ServerConfig {
     pub bind_addr: String,
     pub error_tolerance: i32,
+    #[serde(with = "humantime_serde")]
+    pub read_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub idle_timeout: Duration,
+    pub max_connections_per_ip: u32,
+    pub require_api_key: bool,
+    pub rate_limits: Vec<RateLimit>,
 }
 
-#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let limit = |scope, per_minute, burst| RateLimit {
+            packet: "*".into(),
+            scope,
+            per_minute,
+            burst,
+        };
+        Self {
+            bind_addr: "0.0.0.0:15656".into(),
+            error_tolerance: 5,
+            read_timeout: Duration::from_secs(15),
+            idle_timeout: Duration::from_secs(60),
+            max_connections_per_ip: 8,
+            require_api_key: false,
+            rate_limits: vec![
+                limit(RateLimitScope::Connection, 1200, 2000),
+                limit(RateLimitScope::Ip, 2400, 4000),
+                limit(RateLimitScope::Server, 2400, 4000),
+            ],
+        }
+    }
+}
+
+impl ServerConfig {
+    // A limit for the packet itself takes precedence over one for every packet (`*`)
+    pub fn rate_limit(&self, scope: RateLimitScope, packet: &str) -> Option<&RateLimit> {
+        let limits = self.rate_limits.iter().filter(|limit| limit.scope == scope);
+        let limits_for = |name: &str| limits.clone().find(|limit| limit.packet == name);
+        limits_for(packet).or_else(|| limits_for("*"))
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub packet: String,
+    pub scope: RateLimitScope,
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitScope {
+    Connection,
+    Ip,
+    Server,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SaveConfig {
     pub enabled: bool,
     #[serde(with = "humantime_serde")]
     pub interval: Duration,
 }
 
-#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+impl Default for SaveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: Duration::from_secs(20),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AdsConfig {
     pub enabled: bool,
     pub one_in_x_chance: u32,
-    pub list: Vec<String>,
+    pub allow_opt_out: bool,
+    pub max_per_connection: u32,
+    pub min_msgs_between: u32,
+    pub tiers: BTreeMap<String, AdTier>,
+    pub servers: BTreeMap<String, String>,
+    pub campaigns: Vec<AdCampaign>,
+}
+
+// Without campaigns, unlike the default config with its placeholder ones
+impl Default for AdsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            one_in_x_chance: 10,
+            allow_opt_out: true,
+            max_per_connection: 10,
+            min_msgs_between: 5,
+            tiers: BTreeMap::new(),
+            servers: BTreeMap::new(),
+            campaigns: Vec::new(),
+        }
+    }
+}
+
+impl AdsConfig {
+    // Tiers only apply to servers with an API key, since anyone can claim a server ID
+    pub fn policy(&self, authenticated_server: Option<&str>) -> AdPolicy {
+        let tier = authenticated_server
+            .and_then(|server| self.servers.get(server))
+            .and_then(|tier| self.tiers.get(tier));
+        AdPolicy {
+            enabled: tier.and_then(|tier| tier.enabled).unwrap_or(self.enabled),
+            one_in_x_chance: tier
+                .and_then(|tier| tier.one_in_x_chance)
+                .unwrap_or(self.one_in_x_chance),
+            allow_opt_out: tier
+                .and_then(|tier| tier.allow_opt_out)
+                .unwrap_or(self.allow_opt_out),
+            max_per_connection: tier
+                .and_then(|tier| tier.max_per_connection)
+                .unwrap_or(self.max_per_connection),
+            min_msgs_between: tier
+                .and_then(|tier| tier.min_msgs_between)
+                .unwrap_or(self.min_msgs_between),
+        }
+    }
+}
+
+// Overrides the `[ads]` settings for the servers in the tier
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdTier {
+    pub enabled: Option<bool>,
+    pub one_in_x_chance: Option<u32>,
+    pub allow_opt_out: Option<bool>,
+    pub max_per_connection: Option<u32>,
+    pub min_msgs_between: Option<u32>,
+}
+
+// Empty targeting lists match everything
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdCampaign {
+    pub name: String,
+    pub text: String,
+    pub weight: u32,
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub start: Option<SystemTime>,
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub end: Option<SystemTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_impressions: Option<u64>,
+    pub plugins: Vec<String>,
+    pub authors: Vec<String>,
+    pub cmds: Vec<String>,
+}
+
+impl Default for AdCampaign {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            text: String::new(),
+            weight: 1,
+            start: None,
+            end: None,
+            max_impressions: None,
+            plugins: Vec::new(),
+            authors: Vec::new(),
+            cmds: Vec::new(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct AdPolicy {
+    pub enabled: bool,
+    pub one_in_x_chance: u32,
+    pub allow_opt_out: bool,
+    pub max_per_connection: u32,
+    pub min_msgs_between: u32,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: LevelFilter,
+    pub targets: BTreeMap<String, LevelFilter>,
+    pub format: LogFormat,
+    pub terminal: bool,
+    pub file: LogFileConfig,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Debug,
+            targets: BTreeMap::new(),
+            format: LogFormat::Plain,
+            terminal: true,
+            file: LogFileConfig::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Plain,
+    Json,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogFileConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub max_files: u32,
+}
+
+impl Default for LogFileConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "logs/registry.log".into(),
+            max_size: 10_000_000,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub bind_addr: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_addr: "127.0.0.1:9464".into(),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessagesConfig {
+    pub dir: PathBuf,
+    pub default_locale: String,
+}
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        Self {
+            dir: "messages".into(),
+            default_locale: "en".into(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConfigHandle {
+    current: RwLock<Arc<Config>>,
+}
+
+impl ConfigHandle {
+    pub fn new(config: Config) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    pub fn replace(&self, config: Config) -> Arc<Config> {
+        mem::replace(&mut self.current.write().unwrap(), Arc::new(config))
+    }
 }
 
 impl PersistentData for Config {
@@ -34,4 +334,87 @@ impl PersistentData for Config {
     const DEFAULT: &'static str =
         include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/res/config.toml"));
     const SAVE_DEFAULT: bool = true;
+
+    fn upgrade(contents: &str) -> Result<Option<String>> {
+        migrate::upgrade(contents)
+    }
+
+    fn from_toml(contents: &str) -> Result<Self> {
+        let mut table: Table = toml::from_str(contents).context("failed to parse the config")?;
+        let overrides = apply_env_overrides(&mut table, env::vars());
+        let config: Self = Value::Table(table)
+            .try_into()
+            .context("failed to deserialize the config")?;
+
+        let issues = config.validate();
+        if !issues.is_empty() {
+            bail!("{}", validate::report(&issues, contents, &overrides));
+        }
+        Ok(config)
+    }
+}
+
+// `CARDSTOCK_REGISTRY__SERVER__BIND_ADDR` overrides `bind_addr` in the `[server]` table, even if
+// the file leaves it out
+fn apply_env_overrides(
+    table: &mut Table,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<(String, String)> {
+    let defaults = Value::try_from(Config::default()).expect("the default config is serializable");
+    let mut overrides = Vec::new();
+    'vars: for (name, raw) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let key = key.to_ascii_lowercase().replace("__", ".");
+
+        // The value in the file or else the default decides how the variable is parsed
+        let current = lookup(table, &key)
+            .or_else(|| {
+                defaults
+                    .as_table()
+                    .and_then(|defaults| lookup(defaults, &key))
+            })
+            .cloned();
+        let Some(current) = current else {
+            warn!("Ignoring `{name}` because the config has no `{key}` key.");
+            continue;
+        };
+
+        let segments: Vec<_> = key.split('.').collect();
+        let (last, parents) = segments.split_last().unwrap();
+        let mut slot = &mut *table;
+        for segment in parents {
+            let Value::Table(child) = slot
+                .entry(*segment)
+                .or_insert_with(|| Value::Table(Table::new()))
+            else {
+                warn!("Ignoring `{name}` because `{segment}` in the config isn't a table.");
+                continue 'vars;
+            };
+            slot = child;
+        }
+
+        debug!("Overriding `{key}` in the config with `{name}`.");
+        slot.insert(last.to_string(), parse_env_value(&current, raw));
+        overrides.push((key, name));
+    }
+    overrides
+}
+
+fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let mut segments = key.split('.');
+    let first = table.get(segments.next()?)?;
+    segments.try_fold(first, |value, segment| value.get(segment))
+}
+
+fn parse_env_value(current: &Value, raw: String) -> Value {
+    if current.is_str() {
+        return Value::String(raw);
+    }
+    // Anything else is written like a TOML value, e.g. `true`, `5` or `["a", "b"]`
+    toml::from_str::<Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or(Value::String(raw))
 }
@@ -0,0 +1,148 @@
+use crate::data::config::Config;
+use crate::data::PersistentData;
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use toml_edit::{value, Array, ArrayOfTables, Document, Item, Table, Value};
+
+#[cfg(test)]
+mod tests;
+
+pub const VERSION: i64 = 3;
+
+// `MIGRATIONS[n]` upgrades a config from version `n` to `n + 1`. Keys that are only added don't
+// need a migration, they're filled in from the default config afterwards.
+const MIGRATIONS: [fn(&mut Document); VERSION as usize] = [
+    // Version 0 predates the `version` key
+    |_| {},
+    // Version 2 limits servers with API keys per key instead of per IP, with the same limits
+    |doc| {
+        let Some(limits) = doc
+            .get_mut("server")
+            .and_then(|server| server.get_mut("rate_limits"))
+            .and_then(Item::as_array_of_tables_mut)
+        else {
+            return;
+        };
+        let server_limits: Vec<Table> = limits
+            .iter()
+            .filter(|limit| limit.get("scope").and_then(Item::as_str) == Some("ip"))
+            .map(|limit| {
+                let mut limit = limit.clone();
+                limit["scope"] = value("server");
+                limit
+            })
+            .collect();
+        for limit in server_limits {
+            limits.push(limit);
+        }
+    },
+    // Version 3 replaces the list of ads with campaigns
+    |doc| {
+        let Some(ads) = doc.get_mut("ads").and_then(Item::as_table_mut) else {
+            return;
+        };
+        let Some(list) = ads.remove("list") else {
+            return;
+        };
+        let texts = list
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str);
+        let mut campaigns = ArrayOfTables::new();
+        for (i, text) in texts.enumerate() {
+            let mut campaign = Table::new();
+            campaign["name"] = value(format!("ad-{}", i + 1));
+            campaign["text"] = value(text);
+            campaigns.push(campaign);
+        }
+        // An empty array of tables isn't written, so the defaults would be filled in next time
+        if campaigns.is_empty() {
+            ads["campaigns"] = value(Array::new());
+        } else {
+            ads["campaigns"] = Item::ArrayOfTables(campaigns);
+        }
+    },
+];
+
+pub fn upgrade(contents: &str) -> Result<Option<String>> {
+    let mut doc: Document = contents.parse().context("failed to parse the config")?;
+    let defaults: Document = Config::DEFAULT
+        .parse()
+        .context("failed to parse the default config")?;
+
+    let version = match doc.get("version") {
+        Some(version) => version
+            .as_integer()
+            .context("the config `version` must be an integer")?,
+        None => 0,
+    };
+    if !(0..=VERSION).contains(&version) {
+        bail!("the config is version {version}, but this registry only supports up to version {VERSION}");
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut doc);
+    }
+    if let Some(current) = doc.get_mut("version").and_then(Item::as_value_mut) {
+        let decor = current.decor().clone();
+        *current = Value::from(VERSION);
+        *current.decor_mut() = decor;
+    }
+
+    fill_defaults(doc.as_table_mut(), defaults.as_table(), "");
+    warn_unknown(doc.as_table(), defaults.as_table(), "");
+
+    let upgraded = doc.to_string();
+    Ok((upgraded != contents).then_some(upgraded))
+}
+
+fn fill_defaults(table: &mut Table, defaults: &Table, prefix: &str) {
+    for (key, default) in defaults.iter() {
+        let path = format!("{prefix}{key}");
+        match table.get_mut(key) {
+            Some(item) => {
+                if let (Some(table), Some(defaults)) = (item.as_table_mut(), default.as_table()) {
+                    fill_defaults(table, defaults, &format!("{path}."));
+                }
+            }
+            None => {
+                info!("Adding `{path}` to the config with its default value.");
+                let (key, default) = defaults.get_key_value(key).unwrap();
+                let mut default = default.clone();
+                move_to_end(&mut default);
+                table.insert_formatted(key, default);
+            }
+        }
+    }
+}
+
+// Tables keep their position from the default config, which would interleave them with the
+// tables already in the document
+fn move_to_end(item: &mut Item) {
+    let tables: Vec<&mut Table> = match item {
+        Item::Table(table) => vec![table],
+        Item::ArrayOfTables(array) => array.iter_mut().collect(),
+        _ => return,
+    };
+    for table in tables {
+        table.set_position(usize::MAX);
+        for (_, item) in table.iter_mut() {
+            move_to_end(item);
+        }
+    }
+}
+
+fn warn_unknown(table: &Table, defaults: &Table, prefix: &str) {
+    for (key, item) in table.iter() {
+        let path = format!("{prefix}{key}");
+        match defaults.get(key) {
+            Some(default) => {
+                if let (Some(table), Some(defaults)) = (item.as_table(), default.as_table()) {
+                    warn_unknown(table, defaults, &format!("{path}."));
+                }
+            }
+            None => warn!("Ignoring `{path}` because it isn't a config key."),
+        }
+    }
+}
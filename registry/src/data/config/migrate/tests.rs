@@ -0,0 +1,106 @@
+use super::*;
+use crate::data::config::RateLimitScope;
+
+const VERSION_0: &str = r#"# Only listen locally
+[server]
+bind_addr = "127.0.0.1:15656" # the default port
+error_tolerance = 5
+read_timeout = "15s"
+idle_timeout = "1m"
+
+[ads]
+enabled = false
+one_in_x_chance = 10
+list = []
+"#;
+
+#[test]
+fn current_config_is_unchanged() {
+    assert_eq!(upgrade(Config::DEFAULT).unwrap(), None);
+}
+
+#[test]
+fn missing_keys_are_filled_in_and_comments_are_kept() {
+    let upgraded = upgrade(VERSION_0).unwrap().unwrap();
+    assert!(upgraded.contains("# Only listen locally\n[server]"));
+    assert!(upgraded.contains("bind_addr = \"127.0.0.1:15656\" # the default port"));
+    assert!(upgraded.contains("[save]\nenabled = true\ninterval = \"20s\""));
+    assert!(upgraded.find("[ads]") < upgraded.find("[save]"));
+
+    let config = Config::from_toml(&upgraded).unwrap();
+    assert_eq!(config.server.bind_addr, "127.0.0.1:15656");
+    assert!(!config.ads.enabled);
+    assert!(config.save.enabled);
+    assert_eq!(upgrade(&upgraded).unwrap(), None);
+}
+
+#[test]
+fn version_is_added_before_the_tables() {
+    let upgraded = upgrade(VERSION_0).unwrap().unwrap();
+    let version = upgraded.find(&format!("version = {VERSION}")).unwrap();
+    assert!(version < upgraded.find("[server]").unwrap());
+}
+
+#[test]
+fn newer_versions_are_rejected() {
+    let newer = format!("version = {}\n{VERSION_0}", VERSION + 1);
+    assert!(upgrade(&newer).is_err());
+}
+
+#[test]
+fn unknown_keys_are_kept() {
+    let unknown = format!("{VERSION_0}removed = true\n");
+    let upgraded = upgrade(&unknown).unwrap().unwrap();
+    assert!(upgraded.contains("removed = true"));
+}
+
+#[test]
+fn ip_rate_limits_are_copied_for_servers() {
+    let version_1 = r#"version = 1
+
+[server]
+bind_addr = "0.0.0.0:15656"
+
+[[server.rate_limits]]
+packet = "RegisterCmd"
+scope = "ip"
+per_minute = 60
+burst = 10
+
+[save]
+enabled = true
+"#;
+    let upgraded = upgrade(version_1).unwrap().unwrap();
+    let config = Config::from_toml(&upgraded).unwrap();
+    let limit = config
+        .server
+        .rate_limit(RateLimitScope::Server, "RegisterCmd")
+        .unwrap();
+    assert_eq!((limit.per_minute, limit.burst), (60, 10));
+    assert!(upgraded.find("scope = \"server\"") < upgraded.find("[save]"));
+}
+
+#[test]
+fn ad_lists_become_campaigns() {
+    let with_ads = VERSION_0.replace("list = []", "list = [\"Buy things!\", \"Sell things!\"]");
+    let upgraded = upgrade(&with_ads).unwrap().unwrap();
+    let config = Config::from_toml(&upgraded).unwrap();
+    let campaigns: Vec<_> = config
+        .ads
+        .campaigns
+        .iter()
+        .map(|campaign| (campaign.name.as_str(), campaign.text.as_str()))
+        .collect();
+    assert_eq!(
+        campaigns,
+        [("ad-1", "Buy things!"), ("ad-2", "Sell things!")]
+    );
+
+    // Without any ads, the default campaigns aren't added either
+    let upgraded = upgrade(VERSION_0).unwrap().unwrap();
+    assert!(Config::from_toml(&upgraded)
+        .unwrap()
+        .ads
+        .campaigns
+        .is_empty());
+}
@@ -0,0 +1,106 @@
+use super::*;
+
+#[test]
+fn defaults_match_the_default_config() {
+    let mut config = Config::from_toml(Config::DEFAULT).unwrap();
+    config.ads.campaigns.clear();
+    assert_eq!(config, Config::default());
+}
+
+#[test]
+fn missing_keys_get_their_defaults() {
+    let config = Config::from_toml(
+        "[server]\nbind_addr = \"127.0.0.1:15656\"\n\n[logging.file]\nenabled = true\n",
+    )
+    .unwrap();
+    assert_eq!(config.server.bind_addr, "127.0.0.1:15656");
+    assert_eq!(config.server.read_timeout, Duration::from_secs(15));
+    assert_eq!(config.server.rate_limits.len(), 3);
+    assert!(config.logging.file.enabled);
+    assert_eq!(config.logging.file.max_files, 5);
+    assert_eq!(config.save, SaveConfig::default());
+    assert_eq!(config.messages.default_locale, "en");
+}
+
+fn env_overrides(contents: &str, vars: &[(&str, &str)]) -> (Table, Vec<(String, String)>) {
+    let mut table = toml::from_str(contents).unwrap();
+    let vars = vars
+        .iter()
+        .map(|(name, raw)| (format!("{ENV_PREFIX}{name}"), raw.to_string()));
+    let overrides = apply_env_overrides(&mut table, vars);
+    (table, overrides)
+}
+
+#[test]
+fn env_overrides_replace_keys_in_the_file() {
+    let (table, overrides) = env_overrides(
+        "[server]\nbind_addr = \"0.0.0.0:15656\"\n",
+        &[("SERVER__BIND_ADDR", "127.0.0.1:15656")],
+    );
+    assert_eq!(
+        table["server"]["bind_addr"],
+        Value::String("127.0.0.1:15656".into())
+    );
+    assert_eq!(
+        overrides,
+        [(
+            "server.bind_addr".to_string(),
+            format!("{ENV_PREFIX}SERVER__BIND_ADDR")
+        )]
+    );
+}
+
+#[test]
+fn env_overrides_add_keys_missing_from_the_file() {
+    let (table, overrides) = env_overrides(
+        "[server]\nbind_addr = \"0.0.0.0:15656\"\n",
+        &[
+            ("SERVER__MAX_CONNECTIONS_PER_IP", "3"),
+            ("SERVER__READ_TIMEOUT", "30s"),
+            ("LOGGING__FILE__ENABLED", "true"),
+        ],
+    );
+    assert_eq!(overrides.len(), 3);
+    assert_eq!(table["logging"]["file"]["enabled"], Value::Boolean(true));
+
+    let config: Config = Value::Table(table).try_into().unwrap();
+    assert_eq!(config.server.bind_addr, "0.0.0.0:15656");
+    assert_eq!(config.server.max_connections_per_ip, 3);
+    assert_eq!(config.server.read_timeout, Duration::from_secs(30));
+    assert!(config.logging.file.enabled);
+}
+
+#[test]
+fn env_overrides_ignore_unknown_keys() {
+    let (table, overrides) = env_overrides(
+        "",
+        &[("SERVER__BIND_PORT", "15656"), ("NOPE__ENABLED", "true")],
+    );
+    assert!(table.is_empty());
+    assert!(overrides.is_empty());
+}
+
+#[test]
+fn env_values_are_parsed_like_the_value_they_replace() {
+    let string = Value::String("en".into());
+    assert_eq!(
+        parse_env_value(&string, "5".into()),
+        Value::String("5".into())
+    );
+    assert_eq!(
+        parse_env_value(&string, "\"de\"".into()),
+        Value::String("\"de\"".into())
+    );
+
+    let integer = Value::Integer(8);
+    assert_eq!(parse_env_value(&integer, "5".into()), Value::Integer(5));
+    assert_eq!(
+        parse_env_value(&Value::Array(Vec::new()), "[\"a\", \"b\"]".into()),
+        Value::Array(vec![Value::String("a".into()), Value::String("b".into())])
+    );
+    // Invalid TOML is kept as a string for deserialization to report
+    assert_eq!(
+        parse_env_value(&integer, "five".into()),
+        Value::String("five".into())
+    );
+}
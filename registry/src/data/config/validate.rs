@@ -0,0 +1,296 @@
+use crate::data::config::Config;
+use crate::net::packets::ClientPacket;
+use serde::de::{self, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Formatter, Write};
+use std::net::Ipv6Addr;
+use std::ops::Range;
+use toml::Spanned;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Issue {
+    pub key: &'static str,
+    pub message: String,
+}
+
+impl Issue {
+    fn new(key: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            key,
+            message: message.into(),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        let bind_addr = &self.server.bind_addr;
+        if bind_addr.is_empty() {
+            issues.push(Issue::new("server.bind_addr", "must not be empty"));
+        } else if let Err(error) = check_addr(bind_addr) {
+            issues.push(Issue::new(
+                "server.bind_addr",
+                format!("must be an address with a port like `0.0.0.0:15656` ({error})"),
+            ));
+        }
+
+        let server = &self.server;
+        if !server.idle_timeout.is_zero() {
+            // Silence is only noticed when a read times out
+            if server.read_timeout.is_zero() {
+                issues.push(Issue::new(
+                    "server.idle_timeout",
+                    "has no effect without a `read_timeout`",
+                ));
+            } else if server.idle_timeout < server.read_timeout {
+                issues.push(Issue::new(
+                    "server.idle_timeout",
+                    "must not be shorter than `read_timeout`",
+                ));
+            }
+        }
+
+        for limit in &self.server.rate_limits {
+            if limit.packet != "*" && !ClientPacket::NAMES.contains(&limit.packet.as_str()) {
+                issues.push(Issue::new(
+                    "server.rate_limits",
+                    format!("has a limit for the unknown packet `{}`", limit.packet),
+                ));
+            }
+            if limit.per_minute == 0 || limit.burst == 0 {
+                issues.push(Issue::new(
+                    "server.rate_limits",
+                    format!(
+                        "has a limit for `{}` without a positive `per_minute` and `burst`",
+                        limit.packet
+                    ),
+                ));
+            }
+        }
+
+        if self.save.interval.is_zero() {
+            issues.push(Issue::new("save.interval", "must be longer than zero"));
+        }
+
+        if self.ads.one_in_x_chance == 0 {
+            issues.push(Issue::new("ads.one_in_x_chance", "must be at least 1"));
+        }
+        for (name, tier) in &self.ads.tiers {
+            if tier.one_in_x_chance == Some(0) {
+                issues.push(Issue::new(
+                    "ads.tiers",
+                    format!("has the tier `{name}` with a `one_in_x_chance` below 1"),
+                ));
+            }
+        }
+        let mut names = HashSet::new();
+        for campaign in &self.ads.campaigns {
+            let name = &campaign.name;
+            if name.is_empty() {
+                issues.push(Issue::new("ads.campaigns", "has a campaign without a name"));
+            } else if !names.insert(name) {
+                issues.push(Issue::new(
+                    "ads.campaigns",
+                    format!("has more than one campaign named `{name}`"),
+                ));
+            }
+            if campaign.text.is_empty() {
+                issues.push(Issue::new(
+                    "ads.campaigns",
+                    format!("has the campaign `{name}` without any text"),
+                ));
+            }
+            if campaign.weight == 0 {
+                issues.push(Issue::new(
+                    "ads.campaigns",
+                    format!("has the campaign `{name}` with a `weight` below 1"),
+                ));
+            }
+            if let (Some(start), Some(end)) = (campaign.start, campaign.end) {
+                if end <= start {
+                    issues.push(Issue::new(
+                        "ads.campaigns",
+                        format!("has the campaign `{name}` ending before it starts"),
+                    ));
+                }
+            }
+        }
+        for (server, tier) in &self.ads.servers {
+            if !self.ads.tiers.contains_key(tier) {
+                issues.push(Issue::new(
+                    "ads.servers",
+                    format!("puts `{server}` in the unknown tier `{tier}`"),
+                ));
+            }
+        }
+
+        let metrics_addr = &self.metrics.bind_addr;
+        if self.metrics.enabled {
+            if let Err(error) = check_addr(metrics_addr) {
+                issues.push(Issue::new(
+                    "metrics.bind_addr",
+                    format!("must be an address with a port like `127.0.0.1:9464` ({error})"),
+                ));
+            }
+        }
+
+        let file = &self.logging.file;
+        if file.enabled && file.path.as_os_str().is_empty() {
+            issues.push(Issue::new("logging.file.path", "must not be empty"));
+        }
+        if file.enabled && file.max_size == 0 {
+            issues.push(Issue::new("logging.file.max_size", "must be at least 1"));
+        }
+        if file.enabled && file.max_files == 0 {
+            issues.push(Issue::new("logging.file.max_files", "must be at least 1"));
+        }
+
+        if self.messages.dir.as_os_str().is_empty() {
+            issues.push(Issue::new("messages.dir", "must not be empty"));
+        }
+        if self.messages.default_locale.trim().is_empty() {
+            issues.push(Issue::new("messages.default_locale", "must not be empty"));
+        }
+
+        issues
+    }
+}
+
+// Only checks the syntax, since resolving host names would block every reload on DNS
+fn check_addr(addr: &str) -> Result<(), String> {
+    let Some((host, port)) = addr.rsplit_once(':') else {
+        return Err("the port is missing".into());
+    };
+    if port.parse::<u16>().is_err() {
+        return Err(format!("`{port}` isn't a port"));
+    }
+    match host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+    {
+        Some(ipv6) if ipv6.parse::<Ipv6Addr>().is_err() => {
+            Err(format!("`{ipv6}` isn't an IPv6 address"))
+        }
+        Some(_) => Ok(()),
+        None if host.is_empty() => Err("the host is missing".into()),
+        None if host.contains(':') => Err("IPv6 addresses must be in brackets".into()),
+        None if host.contains(|c: char| c.is_whitespace() || "[]/".contains(c)) => {
+            Err(format!("`{host}` isn't a host name or IP address"))
+        }
+        None => Ok(()),
+    }
+}
+
+// Lists every issue with the location of the offending value, either in the source or the
+// environment variable that overrode it
+pub fn report(issues: &[Issue], source: &str, overrides: &[(String, String)]) -> String {
+    let spans = toml::from_str::<Node>(source).ok();
+    let mut report = match issues.len() {
+        1 => "the config has a problem:".to_string(),
+        len => format!("the config has {len} problems:"),
+    };
+    for issue in issues {
+        let _ = write!(report, "\n- `{}` {}", issue.key, issue.message);
+        if let Some((_, var)) = overrides.iter().find(|(key, _)| key == issue.key) {
+            let _ = write!(report, " (set by `{var}`)");
+        } else if let Some(span) = spans.as_ref().and_then(|spans| spans.span_of(issue.key)) {
+            report.push_str(&snippet(source, span));
+        }
+    }
+    report
+}
+
+fn snippet(source: &str, span: Range<usize>) -> String {
+    let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[span.start..]
+        .find('\n')
+        .map_or(source.len(), |i| span.start + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+    let line_number = source[..span.start].matches('\n').count() + 1;
+    let column = source[line_start..span.start].chars().count() + 1;
+    let width = source[span.start..span.end.min(line_end)]
+        .chars()
+        .count()
+        .max(1);
+    format!(
+        " (line {line_number}, column {column})\n    {line}\n    {}{}",
+        " ".repeat(column - 1),
+        "^".repeat(width)
+    )
+}
+
+enum Node {
+    Table(HashMap<String, Spanned<Node>>),
+    Leaf,
+}
+
+impl Node {
+    fn span_of(&self, key: &str) -> Option<Range<usize>> {
+        let mut node = self;
+        let mut span = None;
+        for segment in key.split('.') {
+            let Self::Table(table) = node else {
+                return None;
+            };
+            let child = table.get(segment)?;
+            span = Some(child.span());
+            node = child.get_ref();
+        }
+        span
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("any TOML value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<Node, E> {
+        Ok(Node::Leaf)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(Node::Leaf)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+        let mut table = HashMap::new();
+        while let Some((key, value)) = map.next_entry::<String, Spanned<Node>>()? {
+            table.insert(key, value);
+        }
+        Ok(Node::Table(table))
+    }
+}
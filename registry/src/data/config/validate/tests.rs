@@ -0,0 +1,337 @@
+use super::*;
+use crate::data::config::{AdCampaign, AdTier, RateLimit, RateLimitScope};
+use crate::data::PersistentData;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+fn issues(configure: impl FnOnce(&mut Config)) -> Vec<Issue> {
+    let mut config = Config::from_toml(Config::DEFAULT).unwrap();
+    configure(&mut config);
+    config.validate()
+}
+
+fn assert_issue(configure: impl FnOnce(&mut Config), key: &'static str, message: &str) {
+    assert_eq!(issues(configure), [Issue::new(key, message)]);
+}
+
+fn campaign(name: &str) -> AdCampaign {
+    AdCampaign {
+        name: name.into(),
+        text: "Your ad here!".into(),
+        ..AdCampaign::default()
+    }
+}
+
+fn rate_limit(packet: &str, per_minute: u32) -> RateLimit {
+    RateLimit {
+        packet: packet.into(),
+        scope: RateLimitScope::Connection,
+        per_minute,
+        burst: 10,
+    }
+}
+
+#[test]
+fn default_config_is_valid() {
+    assert_eq!(issues(|_| {}), []);
+}
+
+#[test]
+fn bind_addr_must_not_be_empty() {
+    assert_issue(
+        |config| config.server.bind_addr.clear(),
+        "server.bind_addr",
+        "must not be empty",
+    );
+}
+
+#[test]
+fn bind_addr_must_have_a_port() {
+    let issues = issues(|config| config.server.bind_addr = "localhost".into());
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].key, "server.bind_addr");
+    assert!(issues[0]
+        .message
+        .starts_with("must be an address with a port like `0.0.0.0:15656`"));
+}
+
+#[test]
+fn bind_addrs_are_checked_without_resolving_them() {
+    for valid in ["0.0.0.0:15656", "[::]:15656", "registry.invalid:15656"] {
+        assert_eq!(issues(|config| config.server.bind_addr = valid.into()), []);
+    }
+    for (invalid, error) in [
+        ("0.0.0.0", "the port is missing"),
+        ("0.0.0.0:http", "`http` isn't a port"),
+        ("0.0.0.0:65536", "`65536` isn't a port"),
+        (":15656", "the host is missing"),
+        ("::1:15656", "IPv6 addresses must be in brackets"),
+        ("[::g]:15656", "`::g` isn't an IPv6 address"),
+        (
+            "registry host:15656",
+            "`registry host` isn't a host name or IP address",
+        ),
+    ] {
+        assert_issue(
+            |config| config.server.bind_addr = invalid.into(),
+            "server.bind_addr",
+            &format!("must be an address with a port like `0.0.0.0:15656` ({error})"),
+        );
+    }
+}
+
+#[test]
+fn idle_timeout_needs_a_shorter_read_timeout() {
+    assert_issue(
+        |config| config.server.read_timeout = Duration::ZERO,
+        "server.idle_timeout",
+        "has no effect without a `read_timeout`",
+    );
+    assert_issue(
+        |config| config.server.read_timeout = Duration::from_secs(90),
+        "server.idle_timeout",
+        "must not be shorter than `read_timeout`",
+    );
+    assert_eq!(
+        issues(|config| {
+            config.server.read_timeout = Duration::ZERO;
+            config.server.idle_timeout = Duration::ZERO;
+        }),
+        []
+    );
+    assert_eq!(
+        issues(|config| config.server.read_timeout = config.server.idle_timeout),
+        []
+    );
+}
+
+#[test]
+fn rate_limits_must_name_known_packets() {
+    assert_issue(
+        |config| config.server.rate_limits.push(rate_limit("Teleport", 60)),
+        "server.rate_limits",
+        "has a limit for the unknown packet `Teleport`",
+    );
+}
+
+#[test]
+fn rate_limits_must_be_positive() {
+    assert_issue(
+        |config| config.server.rate_limits.push(rate_limit("RegisterCmd", 0)),
+        "server.rate_limits",
+        "has a limit for `RegisterCmd` without a positive `per_minute` and `burst`",
+    );
+}
+
+#[test]
+fn save_interval_must_not_be_zero() {
+    assert_issue(
+        |config| config.save.interval = Duration::ZERO,
+        "save.interval",
+        "must be longer than zero",
+    );
+}
+
+#[test]
+fn ad_chance_must_be_at_least_one() {
+    assert_issue(
+        |config| config.ads.one_in_x_chance = 0,
+        "ads.one_in_x_chance",
+        "must be at least 1",
+    );
+}
+
+#[test]
+fn tier_ad_chance_must_be_at_least_one() {
+    assert_issue(
+        |config| {
+            let tier = AdTier {
+                one_in_x_chance: Some(0),
+                ..AdTier::default()
+            };
+            config.ads.tiers.insert("gold".into(), tier);
+        },
+        "ads.tiers",
+        "has the tier `gold` with a `one_in_x_chance` below 1",
+    );
+}
+
+#[test]
+fn campaigns_must_have_a_name() {
+    assert_issue(
+        |config| config.ads.campaigns.push(campaign("")),
+        "ads.campaigns",
+        "has a campaign without a name",
+    );
+}
+
+#[test]
+fn campaign_names_must_be_unique() {
+    assert_issue(
+        |config| config.ads.campaigns.push(campaign("donations")),
+        "ads.campaigns",
+        "has more than one campaign named `donations`",
+    );
+}
+
+#[test]
+fn campaigns_must_have_text() {
+    assert_issue(
+        |config| {
+            config.ads.campaigns.push(AdCampaign {
+                text: String::new(),
+                ..campaign("empty")
+            })
+        },
+        "ads.campaigns",
+        "has the campaign `empty` without any text",
+    );
+}
+
+#[test]
+fn campaign_weights_must_be_at_least_one() {
+    assert_issue(
+        |config| {
+            config.ads.campaigns.push(AdCampaign {
+                weight: 0,
+                ..campaign("never")
+            })
+        },
+        "ads.campaigns",
+        "has the campaign `never` with a `weight` below 1",
+    );
+}
+
+#[test]
+fn campaigns_must_end_after_they_start() {
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    assert_issue(
+        |config| {
+            config.ads.campaigns.push(AdCampaign {
+                start: Some(start),
+                end: Some(start),
+                ..campaign("backwards")
+            })
+        },
+        "ads.campaigns",
+        "has the campaign `backwards` ending before it starts",
+    );
+}
+
+#[test]
+fn servers_must_be_in_known_tiers() {
+    assert_issue(
+        |config| {
+            config.ads.servers.insert("survival".into(), "gold".into());
+        },
+        "ads.servers",
+        "puts `survival` in the unknown tier `gold`",
+    );
+}
+
+#[test]
+fn metrics_bind_addr_is_only_checked_when_enabled() {
+    assert_eq!(
+        issues(|config| config.metrics.bind_addr = "localhost".into()),
+        []
+    );
+    let issues = issues(|config| {
+        config.metrics.enabled = true;
+        config.metrics.bind_addr = "localhost".into();
+    });
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].key, "metrics.bind_addr");
+    assert!(issues[0]
+        .message
+        .starts_with("must be an address with a port like `127.0.0.1:9464`"));
+}
+
+#[test]
+fn enabled_log_files_must_have_a_path() {
+    assert_issue(
+        |config| {
+            config.logging.file.enabled = true;
+            config.logging.file.path = PathBuf::new();
+        },
+        "logging.file.path",
+        "must not be empty",
+    );
+}
+
+#[test]
+fn enabled_log_files_must_have_a_max_size() {
+    assert_issue(
+        |config| {
+            config.logging.file.enabled = true;
+            config.logging.file.max_size = 0;
+        },
+        "logging.file.max_size",
+        "must be at least 1",
+    );
+}
+
+#[test]
+fn enabled_log_files_must_keep_an_old_file() {
+    assert_issue(
+        |config| {
+            config.logging.file.enabled = true;
+            config.logging.file.max_files = 0;
+        },
+        "logging.file.max_files",
+        "must be at least 1",
+    );
+}
+
+#[test]
+fn messages_dir_must_not_be_empty() {
+    assert_issue(
+        |config| config.messages.dir = PathBuf::new(),
+        "messages.dir",
+        "must not be empty",
+    );
+}
+
+#[test]
+fn default_locale_must_not_be_blank() {
+    assert_issue(
+        |config| config.messages.default_locale = " ".into(),
+        "messages.default_locale",
+        "must not be empty",
+    );
+}
+
+#[test]
+fn report_points_at_the_offending_value() {
+    let source = "[server]\nbind_addr = \"\"\n\n[save]\ninterval = \"0s\"\n";
+    let issues = [
+        Issue::new("server.bind_addr", "must not be empty"),
+        Issue::new("save.interval", "must be longer than zero"),
+    ];
+    assert_eq!(
+        report(&issues, source, &[]),
+        "the config has 2 problems:\n\
+            - `server.bind_addr` must not be empty (line 2, column 13)\n    \
+            bind_addr = \"\"\n                \
+            ^^\n\
+            - `save.interval` must be longer than zero (line 5, column 12)\n    \
+            interval = \"0s\"\n               \
+            ^^^^"
+    );
+}
+
+#[test]
+fn report_names_the_overriding_environment_variable() {
+    let overrides = [(
+        "server.bind_addr".to_string(),
+        "CARDSTOCK_REGISTRY__SERVER__BIND_ADDR".to_string(),
+    )];
+    assert_eq!(
+        report(
+            &[Issue::new("server.bind_addr", "must not be empty")],
+            "[server]\nbind_addr = \"0.0.0.0:15656\"\n",
+            &overrides,
+        ),
+        "the config has a problem:\n\
+            - `server.bind_addr` must not be empty (set by `CARDSTOCK_REGISTRY__SERVER__BIND_ADDR`)"
+    );
+}
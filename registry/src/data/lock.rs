@@ -0,0 +1,39 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct DataLock {
+    _file: File,
+    path: PathBuf,
+}
+
+impl DataLock {
+    pub fn acquire(data_path: impl AsRef<Path>) -> Result<Self> {
+        let mut path = data_path.as_ref().as_os_str().to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("failed to open the lock file at `{}`", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => bail!(
+                "the data store is locked through `{}`, is the registry server running?",
+                path.display()
+            ),
+            Err(TryLockError::Error(error)) => {
+                return Err(error).with_context(|| format!("failed to lock `{}`", path.display()));
+            }
+        }
+        Ok(Self { _file: file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
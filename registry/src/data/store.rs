@@ -1,13 +1,127 @@
+use crate::access::Cidr;
 use crate::data::PersistentData;
-use anyhow::{bail, Result};
+use crate::keys::{self, Scope};
+use crate::messages::{Catalog, Message};
+use anyhow::{anyhow, bail, Result};
+use humantime_serde::re::humantime;
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 #[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct DataStore {
     cmds: HashMap<String, Arc<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    bans: Vec<Ban>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    api_keys: Vec<ApiKey>,
+    // By campaign name, so the impression caps last across restarts
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    ad_impressions: HashMap<String, u64>,
+    // By plugin ID, as declared the last time each plugin was selected
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    plugins: HashMap<String, PluginMeta>,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
+pub struct PluginMeta {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // The API key name of the server that first recorded the plugin, the only one allowed to
+    // update it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_by: Option<String>,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct Ban {
+    #[serde(flatten)]
+    pub target: BanTarget,
+    pub reason: String,
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expires: Option<SystemTime>,
+}
+
+impl Ban {
+    pub fn is_active(&self, now: SystemTime) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+
+    // Sent to banned clients in the disconnect packet
+    pub fn message(&self, catalog: &Catalog, locale: &str) -> String {
+        let expiry = self.expires.map_or(String::new(), |expires| {
+            let until = humantime::format_rfc3339_seconds(expires);
+            catalog.render(locale, Message::BanExpiry, &[("until", &until)])
+        });
+        let (message, target): (_, &dyn Display) = match &self.target {
+            BanTarget::Ip(cidr) => (Message::BannedIp, cidr),
+            BanTarget::Plugin(name) => (Message::BannedPlugin, name),
+            BanTarget::Server(id) => (Message::BannedServer, id),
+        };
+        catalog.render(
+            locale,
+            message,
+            &[
+                ("target", target),
+                ("reason", &self.reason),
+                ("expiry", &expiry),
+            ],
+        )
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BanTarget {
+    Ip(Cidr),
+    Plugin(String),
+    Server(String),
+}
+
+impl Display for BanTarget {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Ip(cidr) => write!(f, "IP `{cidr}`"),
+            Self::Plugin(name) => write!(f, "plugin `{name}`"),
+            Self::Server(id) => write!(f, "server `{id}`"),
+        }
+    }
+}
+
+// Only the hash of the key is stored, the key itself is shown once when it's created
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    pub hash: String,
+    pub scopes: BTreeSet<Scope>,
+    #[serde(with = "humantime_serde")]
+    pub created: SystemTime,
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub revoked: Option<SystemTime>,
+}
+
+impl ApiKey {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked.is_some()
+    }
 }
 
 impl DataStore {
@@ -15,17 +129,236 @@ impl DataStore {
         self.cmds.get(name).cloned()
     }
 
+    pub fn len(&self) -> usize {
+        self.cmds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cmds.is_empty()
+    }
+
+    pub fn cmds(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cmds
+            .iter()
+            .map(|(name, owner)| (name.as_str(), owner.as_str()))
+    }
+
+    pub fn owned_by<'a>(&'a self, plugin: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.cmds
+            .iter()
+            .filter(move |(_, owner)| owner.as_str() == plugin)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn starting_with<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.cmds
+            .iter()
+            .filter(move |(name, _)| name.starts_with(prefix))
+            .map(|(name, owner)| (name.as_str(), owner.as_str()))
+    }
+
     pub fn register(&mut self, name: impl Into<String>, plugin: impl Into<String>) -> Result<()> {
         let name = name.into();
         let plugin = plugin.into();
         match self.cmds.entry(name.clone()) {
-            Entry::Occupied(_) => bail!("the command `{name}` is already registered to `{plugin}`"),
+            Entry::Occupied(occupied) => bail!(
+                "the command `{name}` is already registered to `{}`",
+                occupied.get()
+            ),
             Entry::Vacant(vacant) => {
                 vacant.insert(Arc::new(plugin));
                 Ok(())
             }
         }
     }
+
+    pub fn release(&mut self, name: &str) -> Result<Arc<String>> {
+        self.cmds
+            .remove(name)
+            .ok_or_else(|| anyhow!("the command `{name}` isn't registered"))
+    }
+
+    pub fn bans(&self) -> impl Iterator<Item = &Ban> {
+        self.bans.iter()
+    }
+
+    // Replaces any ban of the same target and drops expired bans
+    pub fn ban(&mut self, ban: Ban) {
+        let now = SystemTime::now();
+        self.bans
+            .retain(|existing| existing.target != ban.target && existing.is_active(now));
+        self.bans.push(ban);
+    }
+
+    pub fn unban(&mut self, target: &BanTarget) -> Result<Ban> {
+        let index = self
+            .bans
+            .iter()
+            .position(|ban| ban.target == *target)
+            .ok_or_else(|| anyhow!("the {target} isn't banned"))?;
+        Ok(self.bans.remove(index))
+    }
+
+    pub fn ip_ban(&self, ip: IpAddr) -> Option<&Ban> {
+        self.active_ban(|target| matches!(target, BanTarget::Ip(cidr) if cidr.contains(ip)))
+    }
+
+    // Plugins can be banned by their ID or their name
+    pub fn plugin_ban(&self, id: Option<&str>, name: &str) -> Option<&Ban> {
+        self.active_ban(|target| {
+            matches!(target, BanTarget::Plugin(plugin) if plugin == name || Some(plugin.as_str()) == id)
+        })
+    }
+
+    pub fn server_ban(&self, server_id: &str) -> Option<&Ban> {
+        self.active_ban(|target| matches!(target, BanTarget::Server(id) if id == server_id))
+    }
+
+    fn active_ban(&self, matches: impl Fn(&BanTarget) -> bool) -> Option<&Ban> {
+        let now = SystemTime::now();
+        self.bans
+            .iter()
+            .find(|ban| matches(&ban.target) && ban.is_active(now))
+    }
+
+    pub fn api_keys(&self) -> impl Iterator<Item = &ApiKey> {
+        self.api_keys.iter()
+    }
+
+    // Returns the new key, which can't be recovered from the data store later
+    pub fn add_api_key(
+        &mut self,
+        name: impl Into<String>,
+        scopes: BTreeSet<Scope>,
+    ) -> Result<String> {
+        let name = name.into();
+        if self.active_api_key(&name).is_some() {
+            bail!("an API key named `{name}` already exists");
+        }
+        let key = keys::generate();
+        self.api_keys.push(ApiKey {
+            name,
+            hash: keys::hash(&key),
+            scopes,
+            created: SystemTime::now(),
+            revoked: None,
+        });
+        Ok(key)
+    }
+
+    // Revoked keys are kept so their names still identify the servers that used them
+    pub fn revoke_api_key(&mut self, name: &str) -> Result<()> {
+        let key = self
+            .api_keys
+            .iter_mut()
+            .find(|key| key.name == name && !key.is_revoked())
+            .ok_or_else(|| anyhow!("there's no API key named `{name}`"))?;
+        key.revoked = Some(SystemTime::now());
+        Ok(())
+    }
+
+    pub fn authenticate(&self, key: &str) -> Option<&ApiKey> {
+        let hash = keys::hash(key);
+        self.api_keys
+            .iter()
+            .find(|api_key| api_key.hash == hash && !api_key.is_revoked())
+    }
+
+    fn active_api_key(&self, name: &str) -> Option<&ApiKey> {
+        self.api_keys
+            .iter()
+            .find(|key| key.name == name && !key.is_revoked())
+    }
+
+    pub fn plugins(&self) -> impl Iterator<Item = (&str, &PluginMeta)> {
+        self.plugins.iter().map(|(id, meta)| (id.as_str(), meta))
+    }
+
+    pub fn plugin(&self, id: &str) -> Option<&PluginMeta> {
+        self.plugins.get(id)
+    }
+
+    // Commands are owned by plugin IDs, or by plugin names from before plugins declared IDs. The
+    // first time a plugin declares its ID, the commands owned by its name move to the ID, which
+    // returns how many did. Only the server that first recorded an ID can update it, and each name
+    // belongs to a single ID so it resolves to the same owner every time.
+    pub fn record_plugin(
+        &mut self,
+        id: impl Into<String>,
+        server: &str,
+        mut meta: PluginMeta,
+    ) -> Result<usize> {
+        let id = id.into();
+        if let Some(recorded) = self.plugins.get(&id) {
+            if let Some(recorded_by) = recorded.recorded_by.as_deref().filter(|by| *by != server) {
+                bail!("the plugin `{id}` was recorded by the server `{recorded_by}`");
+            }
+        }
+        if let Some((other, _)) = self
+            .plugins
+            .iter()
+            .find(|(other, recorded)| **other != id && recorded.name == meta.name)
+        {
+            bail!(
+                "the plugin name `{}` is already used by the plugin `{other}`",
+                meta.name
+            );
+        }
+
+        let mut moved = 0;
+        if !self.plugins.contains_key(&id) && id != meta.name {
+            let owner = Arc::new(id.clone());
+            for cmd_owner in self.cmds.values_mut() {
+                if **cmd_owner == meta.name {
+                    *cmd_owner = Arc::clone(&owner);
+                    moved += 1;
+                }
+            }
+        }
+        meta.recorded_by = Some(server.to_string());
+        self.plugins.insert(id, meta);
+        Ok(moved)
+    }
+
+    // Resolves a plugin ID or name to the owner of its commands. Names are unique across IDs, but
+    // the smallest ID wins if a hand-edited data store has duplicates.
+    pub fn owner_key<'a>(&'a self, plugin: &'a str) -> &'a str {
+        if self.plugins.contains_key(plugin) {
+            return plugin;
+        }
+        self.plugins
+            .iter()
+            .filter(|(_, meta)| meta.name == plugin)
+            .map(|(id, _)| id.as_str())
+            .min()
+            .unwrap_or(plugin)
+    }
+
+    // The name to show for an owner, which may be a plugin ID
+    pub fn owner_name<'a>(&'a self, owner: &'a str) -> &'a str {
+        self.plugins
+            .get(owner)
+            .map_or(owner, |meta| meta.name.as_str())
+    }
+
+    pub fn ad_impressions(&self, campaign: &str) -> u64 {
+        self.ad_impressions.get(campaign).copied().unwrap_or(0)
+    }
+
+    pub fn record_ad_impression(&mut self, campaign: &str) {
+        *self.ad_impressions.entry(campaign.to_string()).or_default() += 1;
+    }
+
+    pub fn transfer(&mut self, name: &str, plugin: impl Into<String>) -> Result<Arc<String>> {
+        let owner = self
+            .cmds
+            .get_mut(name)
+            .ok_or_else(|| anyhow!("the command `{name}` isn't registered"))?;
+        Ok(mem::replace(owner, Arc::new(plugin.into())))
+    }
 }
 
 impl PersistentData for DataStore {
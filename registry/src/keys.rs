@@ -0,0 +1,55 @@
+use clap::ValueEnum;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Display, Formatter, Write};
+
+#[cfg(test)]
+mod tests;
+
+// Makes keys recognizable when they end up somewhere they shouldn't, like a log
+const KEY_PREFIX: &str = "csk_";
+const KEY_BYTES: usize = 24;
+
+// What a server can do with its API key
+#[derive(
+    Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Selecting plugins and registering their commands
+    Register,
+    /// Looking up command owners
+    Query,
+}
+
+impl Scope {
+    pub const ALL: [Self; 2] = [Self::Register, Self::Query];
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Register => "register",
+            Self::Query => "query",
+        })
+    }
+}
+
+pub fn generate() -> String {
+    let mut bytes = [0; KEY_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{KEY_PREFIX}{}", to_hex(&bytes))
+}
+
+// Keys are random enough that they don't need a salt or a slow hash
+pub fn hash(key: &str) -> String {
+    to_hex(&Sha256::digest(key.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
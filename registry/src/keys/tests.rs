@@ -0,0 +1,17 @@
+use super::*;
+
+#[test]
+fn generated_keys_are_unique_and_prefixed() {
+    let key = generate();
+    assert!(key.starts_with(KEY_PREFIX));
+    assert_eq!(key.len(), KEY_PREFIX.len() + KEY_BYTES * 2);
+    assert_ne!(key, generate());
+}
+
+#[test]
+fn hashes_are_hex_sha256() {
+    assert_eq!(
+        hash("abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
@@ -1,54 +1,163 @@
-use crate::data::config::Config;
+use crate::connection::{Connection, SharedState};
+use crate::data::config::{Config, ConfigHandle};
+use crate::data::lock::DataLock;
 use crate::data::store::DataStore;
 use crate::data::PersistentData;
-use crate::net::packets::{ClientPacket, ServerPacket};
-use crate::net::types::{NetReadExt, NetWriteExt, PacketOpResult};
-use crate::plugins::{GlobalCommandStatus, PluginInfo, Plugins};
-use anyhow::{bail, Context, Result};
-use log::{debug, error, info, trace, warn, Level};
-use rand::seq::SliceRandom;
-use rand::Rng;
+use crate::messages::{Catalog, CatalogHandle, Message};
+use crate::metrics::Metrics;
+use crate::net::packets::{DisconnectReason, ServerPacket};
+use crate::net::types::NetWriteExt;
+use crate::shutdown::Shutdown;
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
 use std::borrow::Cow;
 use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::sync::{Arc, RwLock};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, RwLock};
+use std::time::{Duration, Instant};
 use std::{io, thread};
 
-const CONFIG_PATH: &str = "config.toml";
-const DATA_PATH: &str = "data.toml";
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+pub const DATA_FILE_NAME: &str = "data.toml";
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
+pub mod access;
+pub mod ads;
+pub mod cli;
+pub mod client;
+pub mod connection;
 pub mod data;
+pub mod keys;
+pub mod limits;
+pub mod logging;
+pub mod messages;
+pub mod metrics;
 pub mod net;
 pub mod plugins;
+pub mod reload;
+pub mod shutdown;
 pub mod suggest;
 
-pub fn run() -> Result<()> {
-    let config = Config::load_or_default(CONFIG_PATH).context("failed to load the config")?;
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Paths {
+    pub config: PathBuf,
+    pub data: PathBuf,
+}
+
+impl Paths {
+    pub fn in_dir(dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        Self {
+            config: dir.join(CONFIG_FILE_NAME),
+            data: dir.join(DATA_FILE_NAME),
+        }
+    }
+}
+
+pub fn run(paths: &Paths) -> Result<()> {
+    let config = Config::load_or_default(&paths.config).context("failed to load the config")?;
+    logging::configure(&config.logging, config_dir(&paths.config))
+        .context("failed to configure logging")?;
     debug!("Using config: {config:?}");
-    let config = Arc::new(config);
+    let messages = Catalog::load(
+        config_dir(&paths.config).join(&config.messages.dir),
+        &config.messages.default_locale,
+    )
+    .context("failed to load the messages")?;
+    let config = Arc::new(ConfigHandle::new(config));
+    let messages = Arc::new(CatalogHandle::new(messages));
 
-    let data = DataStore::load_or_default(DATA_PATH).context("failed to load the data store")?;
+    let lock = DataLock::acquire(&paths.data)?;
+    debug!("Locked the data store through `{}`.", lock.path().display());
+    let data = DataStore::load_or_default(&paths.data).context("failed to load the data store")?;
     debug!("Using data store: {data:?}");
     let data = Arc::new(RwLock::new(data));
+    let metrics = Arc::new(Metrics::default());
 
-    if config.save.enabled {
-        let save_config = Arc::clone(&config);
-        let save_data = Arc::clone(&data);
+    let save_config = Arc::clone(&config);
+    let save_data = Arc::clone(&data);
+    let save_metrics = Arc::clone(&metrics);
+    let save_path = paths.data.clone();
+    thread::Builder::new()
+        .name("save".into())
+        .spawn(|| save_periodically(save_config, save_data, save_metrics, save_path))
+        .context("failed to spawn the save thread")?;
+
+    let metrics_config = config.get().metrics.clone();
+    if metrics_config.enabled {
+        let metrics = Arc::clone(&metrics);
+        let data = Arc::clone(&data);
         thread::Builder::new()
-            .name("save".into())
-            .spawn(|| save_periodically(save_config, save_data))
-            .context("failed to spawn the save thread")?;
+            .name("metrics".into())
+            .spawn(move || {
+                if let Err(error) = metrics::serve(&metrics_config.bind_addr, metrics, data) {
+                    error!("Failed to serve metrics: {error:?}");
+                }
+            })
+            .context("failed to spawn the metrics thread")?;
     }
 
+    let reload_config = Arc::clone(&config);
+    let reload_messages = Arc::clone(&messages);
+    let reload_path = paths.config.clone();
+    thread::Builder::new()
+        .name("reload".into())
+        .spawn(|| reload::watch_config(reload_path, reload_config, reload_messages))
+        .context("failed to spawn the reload thread")?;
+
+    let shutdown = Arc::new(Shutdown::new());
+    let (events_tx, events_rx) = mpsc::channel();
+    let signal_events = events_tx.clone();
+    ctrlc::set_handler(move || {
+        let _ = signal_events.send(RunEvent::ShutdownRequested);
+    })
+    .context("failed to set the shutdown handler")?;
+
+    let shared = SharedState {
+        config: Arc::clone(&config),
+        messages,
+        data: Arc::clone(&data),
+        metrics,
+        limiter: Arc::default(),
+        shutdown: Arc::clone(&shutdown),
+    };
     thread::Builder::new()
         .name("listen".into())
-        .spawn(|| listen(config, data))
-        .context("failed to spawn the listen thread")?
-        .join()
-        .unwrap()
+        .spawn(move || {
+            let result = listen(shared);
+            let _ = events_tx.send(RunEvent::ListenStopped(result));
+        })
+        .context("failed to spawn the listen thread")?;
+
+    match events_rx.recv().unwrap() {
+        RunEvent::ListenStopped(result) => return result,
+        RunEvent::ShutdownRequested => info!("Shutting down..."),
+    }
+    shutdown.request();
+    if !shutdown.wait_for_connections(SHUTDOWN_TIMEOUT) {
+        warn!("Some connections didn't close in time.");
+    }
+    if config.get().save.enabled {
+        data.write()
+            .unwrap()
+            .save(&paths.data)
+            .context("failed to save before shutting down")?;
+        debug!("Saved successfully.");
+    }
+    Ok(())
 }
 
-fn listen(config: Arc<Config>, data: Arc<RwLock<DataStore>>) -> Result<()> {
-    let bind_addr = &config.server.bind_addr;
+pub fn config_dir(config_path: &Path) -> &Path {
+    config_path.parent().unwrap_or(Path::new("."))
+}
+
+enum RunEvent {
+    ListenStopped(Result<()>),
+    ShutdownRequested,
+}
+
+fn listen(shared: SharedState) -> Result<()> {
+    let bind_addr = &shared.config().server.bind_addr;
     let listener = TcpListener::bind(bind_addr)
         .with_context(|| format!("failed to bind to `{}`", bind_addr))?;
     info!(
@@ -57,7 +166,7 @@ fn listen(config: Arc<Config>, data: Arc<RwLock<DataStore>>) -> Result<()> {
     );
 
     for stream in listener.incoming() {
-        let stream = match stream {
+        let mut stream = match stream {
             Ok(stream) => stream,
             Err(error) => {
                 warn!("Failed to accept a connection request: {error:?}");
@@ -66,237 +175,120 @@ fn listen(config: Arc<Config>, data: Arc<RwLock<DataStore>>) -> Result<()> {
         };
 
         let formatted_addr = format_socket_addr(stream.peer_addr(), "<unknown>");
-        info!("Accepted a connection request from {formatted_addr}.");
-
-        let connection_config = Arc::clone(&config);
-        let connection_data = Arc::clone(&data);
-        let result = thread::Builder::new()
-            .name(format!("conn/{formatted_addr}"))
-            .spawn(|| Connection::new(stream, connection_config, connection_data).run())
-            .context("failed to spawn the connection handle thread");
-        if let Err(error) = result {
-            warn!("Failed to start connection handling for {formatted_addr}: {error:?}");
-        }
-    }
-
-    Ok(())
-}
-
-struct Connection {
-    stream: TcpStream,
-    config: Arc<Config>,
-    data: Arc<RwLock<DataStore>>,
-
-    did_handshake: bool,
-    plugins: Plugins,
-}
-
-impl Connection {
-    pub fn new(stream: TcpStream, config: Arc<Config>, data: Arc<RwLock<DataStore>>) -> Self {
-        Self {
-            stream,
-            config,
-            data,
-            did_handshake: false,
-            plugins: Plugins::new(),
+        if shared.shutdown.is_requested() {
+            info!("Ignoring a connection request from {formatted_addr} during shutdown.");
+            continue;
         }
-    }
-
-    pub fn run(&mut self) {
-        let error_tolerance_set = self.config.server.error_tolerance >= 0;
-        let mut errors = 0;
-        loop {
-            match self.next_packet() {
-                Err(error) => {
-                    warn!("Failed to handle a packet: {error:?}");
-                    if error_tolerance_set {
-                        if errors == self.config.server.error_tolerance {
-                            error!("Failed to handle too many packets.");
-                            break;
-                        }
-                        errors += 1;
-                    }
-                }
-                Ok(PacketResult::Disconnect) => break,
-                _ if error_tolerance_set => errors = 0,
-                _ => {}
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer.ip(),
+            Err(error) => {
+                warn!("Ignoring a connection request without a peer address: {error:?}");
+                continue;
             }
+        };
+        let config = shared.config();
+        let messages = shared.messages();
+        let locale = messages.default_locale();
+        if !config.access.permits(peer) {
+            warn!(
+                "Rejecting a connection request from {formatted_addr} because of the access lists."
+            );
+            reject(
+                &mut stream,
+                DisconnectReason::NotAllowed,
+                messages.render(locale, Message::NotAllowed, &[]),
+            );
+            continue;
         }
-    }
-
-    fn next_packet(&mut self) -> Result<PacketResult> {
-        let packet = self
-            .stream
-            .read_packet()
-            .context("failed to read the next packet")?;
-        let packet = match packet {
-            PacketOpResult::Ok(packet) => packet,
-            PacketOpResult::AppearsDisconnected => {
-                warn!("The client forcefully disconnected.");
-                return Ok(PacketResult::Disconnect);
-            }
+        let ban_message = shared
+            .data
+            .read()
+            .unwrap()
+            .ip_ban(peer)
+            .map(|ban| ban.message(&messages, locale));
+        if let Some(message) = ban_message {
+            warn!("Rejecting a connection request from banned {formatted_addr}.");
+            reject(&mut stream, DisconnectReason::Banned, message);
+            continue;
+        }
+        let max_connections = config.server.max_connections_per_ip;
+        let Some(permit) = shared.limiter.acquire(peer, max_connections) else {
+            warn!("Rejecting a connection request from {formatted_addr} because {peer} has too many connections.");
+            shared.metrics.connection_rejected();
+            reject(
+                &mut stream,
+                DisconnectReason::TooManyConnections,
+                messages.render(
+                    locale,
+                    Message::TooManyConnections,
+                    &[("max", &max_connections)],
+                ),
+            );
+            continue;
         };
-        trace!("Received packet: {packet:?}");
+        info!("Accepted a connection request from {formatted_addr}.");
 
-        match packet {
-            ClientPacket::Handshake { version } => {
-                info!("The client is using `{version}`.");
-                self.send_packet(&ServerPacket::Handshake {
-                    ads_enabled: self.config.ads.enabled,
-                })
-                .context("failed to send a handshake response")?;
-                self.did_handshake = true;
-            }
-            _ if !self.did_handshake => bail!("received a non-handshake packet before handshake"),
-            ClientPacket::SelectPlugin { name, authors } => self
-                .plugins
-                .select(name.clone(), || PluginInfo::from_optional_authors(authors))
-                .with_context(|| format!("failed to select `{name}`"))?,
-            ClientPacket::EnablePlugin => self
-                .plugins
-                .set_enabled(true)
-                .context("failed to enable the selected plugin")?,
-            ClientPacket::DisablePlugin => self
-                .plugins
-                .set_enabled(false)
-                .context("failed to disable the selected plugin")?,
-            ClientPacket::RegisterCmd(name) => self
-                .handle_register(name)
-                .context("failed to handle command registration")?,
-            ClientPacket::Disconnect => {
-                info!("The client is gracefully disconnecting.");
-                return Ok(PacketResult::Disconnect);
-            }
+        let read_timeout = config.server.read_timeout;
+        if let Err(error) =
+            stream.set_read_timeout((!read_timeout.is_zero()).then_some(read_timeout))
+        {
+            warn!("Failed to set the read timeout for {formatted_addr}: {error:?}");
         }
-        Ok(PacketResult::Ok)
-    }
 
-    fn handle_register(&mut self, cmd: String) -> Result<()> {
-        let owner = self.data.read().unwrap().check(&cmd);
-        let current_plugin = self.plugins.selected();
-        match owner {
-            Some(plugin) if *plugin == current_plugin => {
-                debug!("Allowing registered command `{cmd}`.");
-                self.send_msg(
-                    Level::Debug,
-                    format!(
-                        "{}, thank you for registering /{cmd}!",
-                        self.plugins.current_authors()
-                    ),
-                )
-                .context("failed to send the message packet")?;
-                self.plugins
-                    .register_cmd(cmd, GlobalCommandStatus::Registered)
-            }
-            Some(owner) => {
-                let suggestions = {
-                    let read_guard = self.data.read().unwrap();
-                    suggest::gen(self.plugins.selected(), &cmd, |name| {
-                        read_guard.check(name).is_some()
-                    })
+        let connection_shared = shared.clone();
+        let tracking_id = shared.shutdown.track(&stream);
+        let result = thread::Builder::new()
+            .name(format!("conn/{formatted_addr}"))
+            .spawn(move || {
+                let shutdown = Arc::clone(&connection_shared.shutdown);
+                Connection::new(stream, peer, connection_shared).run();
+                if let Some(id) = tracking_id {
+                    shutdown.untrack(id);
                 }
-                .join(", ");
-                debug!("Denying command `{cmd}` and suggesting `{suggestions}`.");
-
-                self.send_msg(
-                    Level::Error,
-                    format!(
-                        "/{cmd} is already registered to {owner}. Please choose a different name."
-                    ),
-                )
-                .context("failed to send the message packet")?;
-                self.send_msg(
-                    Level::Error,
-                    format!("Try one of these instead: {suggestions}"),
-                )
-                .context("failed to send the suggestion message packet")?;
-                self.send_packet(&ServerPacket::Deny)
-                    .context("failed to send the deny packet")?;
-            }
-            None => {
-                debug!("Allowing unregistered command `{cmd}`.");
-                self.send_msg(
-                    Level::Warn,
-                    format!(
-                        concat!(
-                            "Hey, {}! Your command /{cmd} is unregistered. ",
-                            "Please register it with \"/register {cmd} {}\"."
-                        ),
-                        self.plugins.current_authors(),
-                        self.plugins.selected(),
-                        cmd = cmd,
-                    ),
-                )
-                .context("failed to send the message packet")?;
-                self.plugins
-                    .register_cmd(cmd, GlobalCommandStatus::Unregistered);
+                drop(permit);
+            })
+            .context("failed to spawn the connection handle thread");
+        if let Err(error) = result {
+            if let Some(id) = tracking_id {
+                shared.shutdown.untrack(id);
             }
+            warn!("Failed to start connection handling for {formatted_addr}: {error:?}");
         }
-        self.send_packet(&ServerPacket::Done)
-            .context("failed to send the done packet")?;
-        Ok(())
-    }
-
-    pub fn send_packet(&mut self, packet: &ServerPacket) -> Result<()> {
-        trace!("Sending packet: {packet:?}");
-        self.stream
-            .write_packet(packet)
-            .with_context(|| format!("failed to write the packet ({packet:?})"))?;
-        // Cardstock TODO: Handle client disconnections
-        Ok(())
     }
 
-    pub fn send_msg(&mut self, log_level: Level, msg: impl ToString) -> Result<()> {
-        self.send_packet(&ServerPacket::Msg {
-            log_level,
-            contents: msg.to_string(),
-        })
-        .context("failed to send the message packet")?;
-
-        if self.config.ads.enabled {
-            let mut rng = rand::thread_rng();
-            let send_ad = rng.gen_ratio(1, self.config.ads.one_in_x_chance);
-            if send_ad {
-                if let Some(ad) = self.config.ads.list.choose(&mut rng) {
-                    debug!("Sending an ad.");
-                    self.send_packet(&ServerPacket::Msg {
-                        log_level: Level::Info,
-                        contents: format!("[Ad] {ad}"),
-                    })
-                    .context("failed to send the ad message packet")?;
-                }
-            }
-        }
-        Ok(())
-    }
+    Ok(())
 }
 
-impl Drop for Connection {
-    fn drop(&mut self) {
-        if self.send_packet(&ServerPacket::Disconnect).is_err() {
-            warn!("Failed to gracefully disconnect the client.");
-        }
-        info!("The connection is being dropped.");
+fn reject(stream: &mut TcpStream, reason: DisconnectReason, message: String) {
+    if let Err(error) = stream.write_packet(&ServerPacket::Disconnect { reason, message }) {
+        debug!("Failed to tell the client why it was rejected: {error:?}");
     }
 }
 
-enum PacketResult {
-    Ok,
-    Disconnect,
-}
-
-fn save_periodically(config: Arc<Config>, data: Arc<RwLock<DataStore>>) {
+fn save_periodically(
+    config: Arc<ConfigHandle>,
+    data: Arc<RwLock<DataStore>>,
+    metrics: Arc<Metrics>,
+    path: PathBuf,
+) {
     loop {
-        let result = { data.write().unwrap().save(DATA_PATH) };
-        if let Err(error) = result {
-            error!("Failed to save: {error:?}");
-        } else {
-            debug!("Saved successfully.");
+        // Saving can be enabled or disabled by reloading the config
+        let config = config.get();
+        if config.save.enabled {
+            let start = Instant::now();
+            let result = { data.write().unwrap().save(&path) };
+            if let Err(error) = result {
+                metrics.save_failed();
+                error!("Failed to save: {error:?}");
+            } else {
+                metrics.save_succeeded(start.elapsed());
+                debug!("Saved successfully.");
+            }
         }
         thread::sleep(config.save.interval);
     }
 }
 
-fn format_socket_addr(addr: io::Result<SocketAddr>, default: &str) -> Cow<str> {
+fn format_socket_addr(addr: io::Result<SocketAddr>, default: &str) -> Cow<'_, str> {
     addr.map_or(Cow::Borrowed(default), |addr| Cow::Owned(addr.to_string()))
 }
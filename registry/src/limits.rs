@@ -0,0 +1,150 @@
+use crate::data::config::{RateLimit, RateLimitScope, ServerConfig};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How long an IP without connections keeps its buckets, so reconnecting doesn't refill them
+const IP_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            refilled: now,
+        }
+    }
+
+    // Returns how long to wait for the next token if there are none left
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let per_second = limit.per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(limit.burst as f64);
+        // Going back in time would count the same time again when refilling next
+        self.refilled = self.refilled.max(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Buckets(HashMap<&'static str, TokenBucket>);
+
+impl Buckets {
+    pub fn take(
+        &mut self,
+        config: &ServerConfig,
+        scope: RateLimitScope,
+        packet: &'static str,
+    ) -> Result<(), Duration> {
+        let Some(limit) = config.rate_limit(scope, packet) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        self.0
+            .entry(packet)
+            .or_insert_with(|| TokenBucket::new(limit, now))
+            .take(limit, now)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Limiter {
+    ips: Mutex<HashMap<IpAddr, IpState>>,
+    // There are only as many servers as API keys, so these never expire
+    servers: Mutex<HashMap<String, Buckets>>,
+}
+
+#[derive(Debug)]
+struct IpState {
+    connections: u32,
+    buckets: Buckets,
+    last_seen: Instant,
+}
+
+impl Limiter {
+    // Returns `None` if the IP already has `max` connections, unless `max` is 0
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr, max: u32) -> Option<IpPermit> {
+        self.acquire_at(ip, max, Instant::now())
+    }
+
+    fn acquire_at(self: &Arc<Self>, ip: IpAddr, max: u32, now: Instant) -> Option<IpPermit> {
+        let mut ips = self.ips.lock().unwrap();
+        ips.retain(|_, state| {
+            state.connections > 0 || now.saturating_duration_since(state.last_seen) < IP_EXPIRY
+        });
+
+        let state = ips.entry(ip).or_insert_with(|| IpState {
+            connections: 0,
+            buckets: Buckets::default(),
+            last_seen: now,
+        });
+        if max != 0 && state.connections >= max {
+            return None;
+        }
+        state.connections += 1;
+        state.last_seen = now;
+        Some(IpPermit {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+
+    pub fn take(
+        &self,
+        ip: IpAddr,
+        config: &ServerConfig,
+        packet: &'static str,
+    ) -> Result<(), Duration> {
+        let mut ips = self.ips.lock().unwrap();
+        let Some(state) = ips.get_mut(&ip) else {
+            return Ok(());
+        };
+        state.last_seen = Instant::now();
+        state.buckets.take(config, RateLimitScope::Ip, packet)
+    }
+
+    pub fn take_server(
+        &self,
+        server: &str,
+        config: &ServerConfig,
+        packet: &'static str,
+    ) -> Result<(), Duration> {
+        self.servers
+            .lock()
+            .unwrap()
+            .entry(server.to_string())
+            .or_default()
+            .take(config, RateLimitScope::Server, packet)
+    }
+}
+
+#[derive(Debug)]
+pub struct IpPermit {
+    limiter: Arc<Limiter>,
+    ip: IpAddr,
+}
+
+impl Drop for IpPermit {
+    fn drop(&mut self) {
+        let mut ips = self.limiter.ips.lock().unwrap();
+        if let Some(state) = ips.get_mut(&self.ip) {
+            state.connections -= 1;
+            state.last_seen = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::*;
+use std::net::Ipv4Addr;
+
+const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+const OTHER_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+fn limit(scope: RateLimitScope, packet: &str, per_minute: u32, burst: u32) -> RateLimit {
+    RateLimit {
+        packet: packet.into(),
+        scope,
+        per_minute,
+        burst,
+    }
+}
+
+fn config(rate_limits: Vec<RateLimit>) -> ServerConfig {
+    ServerConfig {
+        rate_limits,
+        ..ServerConfig::default()
+    }
+}
+
+fn drain(bucket: &mut TokenBucket, limit: &RateLimit, now: Instant) {
+    for _ in 0..limit.burst {
+        bucket.take(limit, now).unwrap();
+    }
+}
+
+#[test]
+fn buckets_start_full_and_never_exceed_the_burst() {
+    let limit = limit(RateLimitScope::Connection, "*", 60, 3);
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(&limit, start);
+    drain(&mut bucket, &limit, start);
+    assert_eq!(bucket.take(&limit, start), Err(Duration::from_secs(1)));
+
+    let later = start + Duration::from_secs(60 * 60);
+    drain(&mut bucket, &limit, later);
+    assert_eq!(bucket.take(&limit, later), Err(Duration::from_secs(1)));
+}
+
+#[test]
+fn partial_refills_shorten_the_wait() {
+    let limit = limit(RateLimitScope::Connection, "*", 120, 4);
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(&limit, start);
+    drain(&mut bucket, &limit, start);
+    assert_eq!(bucket.take(&limit, start), Err(Duration::from_millis(500)));
+
+    let quarter = start + Duration::from_millis(250);
+    assert_eq!(
+        bucket.take(&limit, quarter),
+        Err(Duration::from_millis(250))
+    );
+    let half = start + Duration::from_millis(500);
+    assert_eq!(bucket.take(&limit, half), Ok(()));
+    assert_eq!(bucket.take(&limit, half), Err(Duration::from_millis(500)));
+}
+
+#[test]
+fn going_back_in_time_refills_nothing() {
+    let limit = limit(RateLimitScope::Connection, "*", 60, 2);
+    let start = Instant::now();
+    let now = start + Duration::from_secs(10);
+    let mut bucket = TokenBucket::new(&limit, now);
+    drain(&mut bucket, &limit, now);
+    assert_eq!(bucket.take(&limit, start), Err(Duration::from_secs(1)));
+    // The time between `start` and `now` must not be counted as refilled
+    assert_eq!(bucket.take(&limit, now), Err(Duration::from_secs(1)));
+    assert_eq!(bucket.take(&limit, now + Duration::from_secs(1)), Ok(()));
+}
+
+#[test]
+fn connection_buckets_are_per_packet() {
+    let config = config(vec![
+        limit(RateLimitScope::Connection, "*", 60, 1),
+        limit(RateLimitScope::Connection, "Ping", 60, 2),
+    ]);
+    let mut buckets = Buckets::default();
+    let mut take = |scope, packet| buckets.take(&config, scope, packet).is_ok();
+    assert!(take(RateLimitScope::Connection, "RegisterCmd"));
+    assert!(!take(RateLimitScope::Connection, "RegisterCmd"));
+    assert!(take(RateLimitScope::Connection, "QueryOwner"));
+    assert!(take(RateLimitScope::Connection, "Ping"));
+    assert!(take(RateLimitScope::Connection, "Ping"));
+    assert!(!take(RateLimitScope::Connection, "Ping"));
+    assert!(take(RateLimitScope::Server, "Ping"));
+}
+
+#[test]
+fn ips_are_limited_to_max_connections() {
+    let limiter = Arc::new(Limiter::default());
+    let first = limiter.acquire(IP, 2).unwrap();
+    let second = limiter.acquire(IP, 2).unwrap();
+    assert!(limiter.acquire(IP, 2).is_none());
+    assert!(limiter.acquire(OTHER_IP, 2).is_some());
+
+    drop(first);
+    let third = limiter.acquire(IP, 2).unwrap();
+    assert!(limiter.acquire(IP, 2).is_none());
+    drop((second, third));
+    assert_eq!(limiter.ips.lock().unwrap()[&IP].connections, 0);
+
+    let unlimited: Vec<_> = (0..10).map(|_| limiter.acquire(IP, 0).unwrap()).collect();
+    assert_eq!(unlimited.len(), 10);
+}
+
+#[test]
+fn ip_buckets_are_shared_by_connections() {
+    let config = config(vec![limit(RateLimitScope::Ip, "*", 60, 2)]);
+    let limiter = Arc::new(Limiter::default());
+    // IPs without connections aren't tracked
+    assert!(limiter.take(IP, &config, "Ping").is_ok());
+
+    let _first = limiter.acquire(IP, 0).unwrap();
+    let _second = limiter.acquire(IP, 0).unwrap();
+    let _other = limiter.acquire(OTHER_IP, 0).unwrap();
+    assert!(limiter.take(IP, &config, "Ping").is_ok());
+    assert!(limiter.take(IP, &config, "Ping").is_ok());
+    assert!(limiter.take(IP, &config, "Ping").is_err());
+    assert!(limiter.take(OTHER_IP, &config, "Ping").is_ok());
+}
+
+#[test]
+fn server_buckets_are_shared_by_server_id() {
+    let config = config(vec![limit(RateLimitScope::Server, "*", 60, 1)]);
+    let limiter = Limiter::default();
+    assert!(limiter.take_server("survival", &config, "Ping").is_ok());
+    assert!(limiter.take_server("survival", &config, "Ping").is_err());
+    assert!(limiter.take_server("creative", &config, "Ping").is_ok());
+}
+
+#[test]
+fn idle_ips_keep_their_buckets_until_they_expire() {
+    let config = config(vec![limit(RateLimitScope::Ip, "*", 1, 1)]);
+    let limiter = Arc::new(Limiter::default());
+    let now = Instant::now();
+
+    let permit = limiter.acquire_at(IP, 0, now).unwrap();
+    assert!(limiter.take(IP, &config, "Ping").is_ok());
+    drop(permit);
+    let permit = limiter.acquire_at(IP, 0, now).unwrap();
+    assert!(limiter.take(IP, &config, "Ping").is_err());
+    drop(permit);
+
+    let expired = Instant::now() + IP_EXPIRY;
+    let _other = limiter.acquire_at(OTHER_IP, 0, expired).unwrap();
+    assert!(!limiter.ips.lock().unwrap().contains_key(&IP));
+}
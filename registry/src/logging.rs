@@ -0,0 +1,283 @@
+use crate::data::config::{LogFormat, LoggingConfig};
+use anyhow::{anyhow, Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::thread;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{OffsetDateTime, UtcOffset};
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[cfg(test)]
+mod tests;
+
+struct Logger {
+    offset: UtcOffset,
+    // Either stream may be redirected to a file while the other is still a terminal
+    stdout_colors: bool,
+    stderr_colors: bool,
+    outputs: RwLock<Outputs>,
+}
+
+struct Outputs {
+    level: LevelFilter,
+    // Sorted by descending length so the most specific target matches first
+    targets: Vec<(String, LevelFilter)>,
+    format: LogFormat,
+    terminal: bool,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Outputs {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.level, |&(_, level)| level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.level, Ord::max)
+    }
+}
+
+// Logs everything at debug level to the terminal until the config is loaded
+pub fn init() -> Result<()> {
+    // The local offset can only be determined soundly while there's a single thread
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+    let logger = LOGGER.get_or_init(|| Logger {
+        offset,
+        stdout_colors: io::stdout().is_terminal(),
+        stderr_colors: io::stderr().is_terminal(),
+        outputs: RwLock::new(Outputs {
+            level: LevelFilter::Debug,
+            targets: Vec::new(),
+            format: LogFormat::Plain,
+            terminal: true,
+            file: None,
+        }),
+    });
+    log::set_logger(logger).map_err(|_| anyhow!("a logger is already set"))?;
+    log::set_max_level(LevelFilter::Debug);
+    Ok(())
+}
+
+// Relative log file paths are resolved against `base_dir`
+pub fn configure(config: &LoggingConfig, base_dir: &Path) -> Result<()> {
+    let Some(logger) = LOGGER.get() else {
+        return Ok(());
+    };
+
+    let file = if config.file.enabled {
+        let path = base_dir.join(&config.file.path);
+        let file = RotatingFile::open(path, config.file.max_size, config.file.max_files)?;
+        Some(Mutex::new(file))
+    } else {
+        None
+    };
+    let outputs = Outputs {
+        level: config.level,
+        targets: most_specific_first(&config.targets),
+        format: config.format,
+        terminal: config.terminal,
+        file,
+    };
+    log::set_max_level(outputs.max_level());
+    *logger.outputs.write().unwrap() = outputs;
+    Ok(())
+}
+
+fn most_specific_first(targets: &BTreeMap<String, LevelFilter>) -> Vec<(String, LevelFilter)> {
+    let mut targets: Vec<_> = targets
+        .iter()
+        .map(|(target, &level)| (target.clone(), level))
+        .collect();
+    targets.sort_unstable_by_key(|(target, _)| usize::MAX - target.len());
+    targets
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.outputs.read().unwrap().level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        let outputs = self.outputs.read().unwrap();
+        if record.level() > outputs.level_for(record.target()) {
+            return;
+        }
+
+        let now = OffsetDateTime::now_utc().to_offset(self.offset);
+        if outputs.terminal {
+            // Like most terminal loggers, problems go to stderr so they stand out
+            let stderr = matches!(record.level(), Level::Error | Level::Warn);
+            let colors = if stderr {
+                self.stderr_colors
+            } else {
+                self.stdout_colors
+            };
+            let line = match outputs.format {
+                LogFormat::Plain => plain_line(record, now, colors),
+                LogFormat::Json => json_line(record, now),
+            };
+            let _ = if stderr {
+                io::stderr().lock().write_all(line.as_bytes())
+            } else {
+                io::stdout().lock().write_all(line.as_bytes())
+            };
+        }
+        if let Some(file) = &outputs.file {
+            let line = match outputs.format {
+                LogFormat::Plain => plain_line(record, now, false),
+                LogFormat::Json => json_line(record, now),
+            };
+            let _ = file.lock().unwrap().write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+fn plain_line(record: &Record, now: OffsetDateTime, colors: bool) -> String {
+    let time = now
+        .format(format_description!("[hour]:[minute]:[second]"))
+        .unwrap_or_default();
+    let level = match (colors, record.level()) {
+        (false, level) => format!("[{level}]"),
+        (true, Level::Error) => format!("\x1b[31m[{}]\x1b[0m", Level::Error),
+        (true, Level::Warn) => format!("\x1b[33m[{}]\x1b[0m", Level::Warn),
+        (true, Level::Info) => format!("\x1b[34m[{}]\x1b[0m", Level::Info),
+        (true, Level::Debug) => format!("\x1b[36m[{}]\x1b[0m", Level::Debug),
+        (true, Level::Trace) => format!("[{}]", Level::Trace),
+    };
+    let mut line = format!("{time} {level} ({}) ", thread_name());
+    if record.level() == Level::Trace {
+        let _ = write!(line, "{}: ", record.target());
+    }
+    let _ = writeln!(line, "{}", record.args());
+    line
+}
+
+fn json_line(record: &Record, now: OffsetDateTime) -> String {
+    let mut line = String::from("{\"time\":");
+    push_json_str(&mut line, &now.format(&Rfc3339).unwrap_or_default());
+    line.push_str(",\"level\":");
+    push_json_str(&mut line, record.level().as_str());
+    line.push_str(",\"target\":");
+    push_json_str(&mut line, record.target());
+    line.push_str(",\"thread\":");
+    push_json_str(&mut line, &thread_name());
+    line.push_str(",\"message\":");
+    push_json_str(&mut line, &record.args().to_string());
+    line.push_str("}\n");
+    line
+}
+
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn thread_name() -> String {
+    let thread = thread::current();
+    match thread.name() {
+        Some(name) => name.to_string(),
+        None => format!("{:?}", thread.id()),
+    }
+}
+
+// Once the file would grow past `max_size`, it's renamed to `<path>.1`, the previous `<path>.1`
+// to `<path>.2` and so on, keeping at most `max_files` old files, which validation keeps above 0
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: u32) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("failed to create the log directory `{}`", parent.display())
+            })?;
+        }
+        let file = Self::open_file(&path)?;
+        let size = file
+            .metadata()
+            .with_context(|| format!("failed to read the size of `{}`", path.display()))?
+            .len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn open_file(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open the log file `{}`", path.display()))
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file
+            .write_all(line.as_bytes())
+            .context("failed to write to the log file")?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |n: u32| {
+            let mut path = self.path.as_os_str().to_owned();
+            path.push(format!(".{n}"));
+            PathBuf::from(path)
+        };
+
+        let _ = fs::remove_file(rotated(self.max_files));
+        for n in (1..self.max_files).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+        fs::rename(&self.path, rotated(1)).context("failed to rotate the log file")?;
+        self.file = Self::open_file(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
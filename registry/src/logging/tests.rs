@@ -0,0 +1,148 @@
+use super::*;
+use std::{env, process};
+
+fn outputs(level: LevelFilter, targets: &[(&str, LevelFilter)]) -> Outputs {
+    let targets = targets
+        .iter()
+        .map(|&(target, level)| (target.to_string(), level))
+        .collect();
+    Outputs {
+        level,
+        targets: most_specific_first(&targets),
+        format: LogFormat::Plain,
+        terminal: false,
+        file: None,
+    }
+}
+
+#[test]
+fn the_most_specific_target_decides_the_level() {
+    let outputs = outputs(
+        LevelFilter::Info,
+        &[
+            ("cardstock_registry", LevelFilter::Debug),
+            ("cardstock_registry::connection", LevelFilter::Trace),
+        ],
+    );
+    assert_eq!(
+        outputs.level_for("cardstock_registry::connection"),
+        LevelFilter::Trace
+    );
+    assert_eq!(
+        outputs.level_for("cardstock_registry::connection::tests"),
+        LevelFilter::Trace
+    );
+    assert_eq!(
+        outputs.level_for("cardstock_registry::reload"),
+        LevelFilter::Debug
+    );
+    assert_eq!(outputs.level_for("toml_edit"), LevelFilter::Info);
+}
+
+#[test]
+fn targets_only_match_whole_path_segments() {
+    let outputs = outputs(LevelFilter::Warn, &[("cardstock", LevelFilter::Trace)]);
+    assert_eq!(outputs.level_for("cardstock"), LevelFilter::Trace);
+    assert_eq!(outputs.level_for("cardstock_registry"), LevelFilter::Warn);
+}
+
+#[test]
+fn max_level_covers_every_target() {
+    assert_eq!(
+        outputs(LevelFilter::Info, &[]).max_level(),
+        LevelFilter::Info
+    );
+    let outputs = outputs(
+        LevelFilter::Info,
+        &[
+            ("rand", LevelFilter::Error),
+            ("cardstock_registry::limits", LevelFilter::Trace),
+        ],
+    );
+    assert_eq!(outputs.max_level(), LevelFilter::Trace);
+}
+
+#[test]
+fn json_strings_are_escaped() {
+    let mut out = String::new();
+    push_json_str(
+        &mut out,
+        "a \"quoted\" \\path\\\nline\r\tend\u{1b}[0m\u{7f}é",
+    );
+    assert_eq!(
+        out,
+        r#""a \"quoted\" \\path\\\nline\r\tend\u001b[0m\u007fé""#
+    );
+}
+
+#[test]
+fn json_lines_have_every_field() {
+    let line = json_line(
+        &Record::builder()
+            .args(format_args!("Reloaded \"config.toml\"."))
+            .level(Level::Info)
+            .target("cardstock_registry::reload")
+            .build(),
+        OffsetDateTime::UNIX_EPOCH,
+    );
+    assert_eq!(
+        line,
+        format!(
+            concat!(
+                r#"{{"time":"1970-01-01T00:00:00Z","level":"INFO","#,
+                r#""target":"cardstock_registry::reload","thread":"{}","#,
+                r#""message":"Reloaded \"config.toml\"."}}"#,
+                "\n"
+            ),
+            thread_name()
+        )
+    );
+}
+
+fn log_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("cardstock-registry-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn read(path: &Path, suffix: &str) -> String {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    fs::read_to_string(path).unwrap_or_default()
+}
+
+#[test]
+fn full_log_files_are_rotated() {
+    let dir = log_dir("rotation");
+    let path = dir.join("logs/registry.log");
+    let mut file = RotatingFile::open(path.clone(), 8, 2).unwrap();
+    for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+        file.write_line(line).unwrap();
+    }
+    let files = [read(&path, ""), read(&path, ".1"), read(&path, ".2")];
+    let rotated_too_far = dir.join("logs/registry.log.3").exists();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(files, ["five\n", "four\n", "three\n"]);
+    assert!(!rotated_too_far);
+}
+
+#[test]
+fn rotation_continues_from_the_existing_size() {
+    let dir = log_dir("reopen");
+    let path = dir.join("registry.log");
+    RotatingFile::open(path.clone(), 8, 1)
+        .unwrap()
+        .write_line("one\n")
+        .unwrap();
+    RotatingFile::open(path.clone(), 8, 1)
+        .unwrap()
+        .write_line("two\n")
+        .unwrap();
+    let mut file = RotatingFile::open(path.clone(), 8, 1).unwrap();
+    file.write_line("three\n").unwrap();
+    let files = [read(&path, ""), read(&path, ".1")];
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(files, ["three\n", "one\ntwo\n"]);
+}
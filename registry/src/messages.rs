@@ -0,0 +1,393 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::fs;
+use std::io::ErrorKind;
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+pub const BUILTIN_LOCALE: &str = "en";
+const BUILTIN: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/res/messages/en.toml"));
+
+#[cfg(test)]
+mod tests;
+
+// A message as plain text, and as MiniMessage markup if its template has any tags
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Text {
+    pub plain: String,
+    pub rich: Option<String>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Message {
+    Registered,
+    AlreadyRegistered,
+    Owner,
+    OwnerLink,
+    Suggestions,
+    Suggestion,
+    SuggestionSeparator,
+    Unregistered,
+    MissingScope,
+    ConnectionClosed,
+    ShuttingDown,
+    ErrorThreshold,
+    TimedOut,
+    HandshakeRequired,
+    HandshakeRepeated,
+    PluginRequired,
+    NotAllowed,
+    TooManyConnections,
+    InvalidApiKey,
+    ApiKeyRequired,
+    BannedIp,
+    BannedPlugin,
+    BannedServer,
+    BanExpiry,
+}
+
+impl Message {
+    pub const ALL: [Self; 24] = [
+        Self::Registered,
+        Self::AlreadyRegistered,
+        Self::Owner,
+        Self::OwnerLink,
+        Self::Suggestions,
+        Self::Suggestion,
+        Self::SuggestionSeparator,
+        Self::Unregistered,
+        Self::MissingScope,
+        Self::ConnectionClosed,
+        Self::ShuttingDown,
+        Self::ErrorThreshold,
+        Self::TimedOut,
+        Self::HandshakeRequired,
+        Self::HandshakeRepeated,
+        Self::PluginRequired,
+        Self::NotAllowed,
+        Self::TooManyConnections,
+        Self::InvalidApiKey,
+        Self::ApiKeyRequired,
+        Self::BannedIp,
+        Self::BannedPlugin,
+        Self::BannedServer,
+        Self::BanExpiry,
+    ];
+
+    pub fn key(self) -> &'static str {
+        match self {
+            Self::Registered => "registered",
+            Self::AlreadyRegistered => "already_registered",
+            Self::Owner => "owner",
+            Self::OwnerLink => "owner_link",
+            Self::Suggestions => "suggestions",
+            Self::Suggestion => "suggestion",
+            Self::SuggestionSeparator => "suggestion_separator",
+            Self::Unregistered => "unregistered",
+            Self::MissingScope => "missing_scope",
+            Self::ConnectionClosed => "connection_closed",
+            Self::ShuttingDown => "shutting_down",
+            Self::ErrorThreshold => "error_threshold",
+            Self::TimedOut => "timed_out",
+            Self::HandshakeRequired => "handshake_required",
+            Self::HandshakeRepeated => "handshake_repeated",
+            Self::PluginRequired => "plugin_required",
+            Self::NotAllowed => "not_allowed",
+            Self::TooManyConnections => "too_many_connections",
+            Self::InvalidApiKey => "invalid_api_key",
+            Self::ApiKeyRequired => "api_key_required",
+            Self::BannedIp => "banned_ip",
+            Self::BannedPlugin => "banned_plugin",
+            Self::BannedServer => "banned_server",
+            Self::BanExpiry => "ban_expiry",
+        }
+    }
+}
+
+// Messages missing from a locale fall back to the default locale, then to the built-in English
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Catalog {
+    default_locale: String,
+    locales: BTreeMap<String, HashMap<String, String>>,
+    builtin: HashMap<String, String>,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl Catalog {
+    pub fn builtin() -> Self {
+        Self::new(BUILTIN_LOCALE)
+    }
+
+    // Only has the built-in messages until locales are added
+    pub fn new(default_locale: &str) -> Self {
+        let builtin: HashMap<String, String> =
+            toml::from_str(BUILTIN).expect("the built-in messages should be valid");
+        Self {
+            default_locale: normalize(default_locale),
+            locales: BTreeMap::from([(BUILTIN_LOCALE.into(), builtin.clone())]),
+            builtin,
+        }
+    }
+
+    // Reads every `<locale>.toml` in the directory, creating it with the built-in messages if it
+    // doesn't exist so operators have something to start from
+    pub fn load(dir: impl AsRef<Path>, default_locale: &str) -> Result<Self> {
+        let dir = dir.as_ref();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                fs::create_dir_all(dir).with_context(|| {
+                    format!(
+                        "failed to create the messages directory `{}`",
+                        dir.display()
+                    )
+                })?;
+                let path = dir.join(format!("{BUILTIN_LOCALE}.toml"));
+                fs::write(&path, BUILTIN)
+                    .with_context(|| format!("failed to write `{}`", path.display()))?;
+                info!("Wrote the built-in messages to `{}`.", path.display());
+                fs::read_dir(dir).with_context(|| format!("failed to read `{}`", dir.display()))?
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to read `{}`", dir.display()))
+            }
+        };
+
+        let mut catalog = Self::new(default_locale);
+        for entry in entries {
+            let path = entry
+                .with_context(|| format!("failed to read `{}`", dir.display()))?
+                .path();
+            if path.extension().is_none_or(|extension| extension != "toml") {
+                continue;
+            }
+            let Some(locale) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let contents = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read `{}`", path.display()))?;
+            catalog
+                .add_locale(locale, &contents)
+                .with_context(|| format!("failed to load `{}`", path.display()))?;
+        }
+        if !catalog.locales.contains_key(&catalog.default_locale) {
+            warn!(
+                "There are no messages for the default locale `{}`, so the built-in ones are used.",
+                catalog.default_locale
+            );
+        }
+        Ok(catalog)
+    }
+
+    // Replaces the messages of the locale, which can leave out some of them
+    pub fn add_locale(&mut self, locale: &str, contents: &str) -> Result<()> {
+        let locale = normalize(locale);
+        let messages: HashMap<String, String> =
+            toml::from_str(contents).context("failed to parse the messages")?;
+        for key in messages.keys() {
+            if !self.builtin.contains_key(key) {
+                warn!("Ignoring the unknown message `{key}` for the locale `{locale}`.");
+            }
+        }
+        self.locales.insert(locale, messages);
+        Ok(())
+    }
+
+    pub fn default_locale(&self) -> &str {
+        &self.default_locale
+    }
+
+    // Picks the requested locale, then its language without the region, then the default one
+    pub fn negotiate(&self, requested: Option<&str>) -> &str {
+        let Some(requested) = requested.map(normalize) else {
+            return &self.default_locale;
+        };
+        let language = requested.split('-').next().unwrap_or_default();
+        for locale in [requested.as_str(), language] {
+            if let Some((locale, _)) = self.locales.get_key_value(locale) {
+                return locale;
+            }
+        }
+        &self.default_locale
+    }
+
+    pub fn render(&self, locale: &str, message: Message, args: &[(&str, &dyn Display)]) -> String {
+        self.render_text(locale, message, args, &[]).plain
+    }
+
+    // `texts` are already rendered messages, like the items of a list
+    pub fn render_text(
+        &self,
+        locale: &str,
+        message: Message,
+        args: &[(&str, &dyn Display)],
+        texts: &[(&str, &Text)],
+    ) -> Text {
+        let key = message.key();
+        let template = [locale, &self.default_locale]
+            .into_iter()
+            .find_map(|locale| self.locales.get(locale)?.get(key))
+            .or_else(|| self.builtin.get(key))
+            .map_or(key, String::as_str);
+        fill(template, args, texts)
+    }
+
+    // Renders `message` for each value of its `name` placeholder
+    pub fn render_list(
+        &self,
+        locale: &str,
+        message: Message,
+        separator: Message,
+        name: &str,
+        values: &[impl Display],
+    ) -> Text {
+        let separator = self.render_text(locale, separator, &[], &[]);
+        let items: Vec<_> = values
+            .iter()
+            .map(|value| self.render_text(locale, message, &[(name, value)], &[]))
+            .collect();
+        let plain: Vec<_> = items.iter().map(|item| item.plain.as_str()).collect();
+        let tagged = separator.rich.is_some() || items.iter().any(|item| item.rich.is_some());
+        let rich = tagged.then(|| {
+            let rich = |text: &Text| {
+                text.rich
+                    .clone()
+                    .unwrap_or_else(|| escape(&text.plain, false))
+            };
+            let items: Vec<_> = items.iter().map(rich).collect();
+            items.join(&rich(&separator))
+        });
+        Text {
+            plain: plain.join(&separator.plain),
+            rich,
+        }
+    }
+}
+
+// Tags are left out of the plain text. Unknown placeholders are kept as they are, so mistakes show
+// up in the message.
+fn fill(template: &str, args: &[(&str, &dyn Display)], texts: &[(&str, &Text)]) -> Text {
+    let mut plain = String::with_capacity(template.len());
+    let mut rich = String::with_capacity(template.len());
+    let mut tagged = false;
+    // Whether we're inside a tag, and which quote its current argument is in if any
+    let mut tag: Option<Option<char>> = None;
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '\\' => {
+                let escaped: String = rest.chars().take(2).collect();
+                rich.push_str(&escaped);
+                if tag.is_none() {
+                    match escaped.strip_prefix('\\') {
+                        Some(next @ ("<" | "\\")) => plain.push_str(next),
+                        _ => plain.push_str(&escaped),
+                    }
+                }
+                rest = &rest[escaped.len()..];
+                continue;
+            }
+            '{' => {
+                let name = rest.find('}').map(|end| &rest[1..end]);
+                let in_tag = tag.is_some();
+                if let Some((_, value)) = args.iter().find(|(arg, _)| Some(*arg) == name) {
+                    let value = value.to_string();
+                    if !in_tag {
+                        plain.push_str(&value);
+                    }
+                    rich.push_str(&escape(&value, in_tag));
+                } else if let Some((_, text)) = texts.iter().find(|(arg, _)| Some(*arg) == name) {
+                    if !in_tag {
+                        plain.push_str(&text.plain);
+                    }
+                    match &text.rich {
+                        Some(markup) if !in_tag => {
+                            tagged = true;
+                            rich.push_str(markup);
+                        }
+                        _ => rich.push_str(&escape(&text.plain, in_tag)),
+                    }
+                } else {
+                    plain.push('{');
+                    rich.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+                rest = &rest[name.unwrap_or_default().len() + 2..];
+                continue;
+            }
+            '<' if tag.is_none() => {
+                tagged = true;
+                tag = Some(None);
+            }
+            '>' if tag == Some(None) => {
+                tag = None;
+                rich.push('>');
+                rest = &rest[1..];
+                continue;
+            }
+            '\'' | '"' if tag == Some(None) => tag = Some(Some(c)),
+            c if tag == Some(Some(c)) => tag = Some(None),
+            _ => {}
+        }
+        rich.push(c);
+        if tag.is_none() {
+            plain.push(c);
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    Text {
+        plain,
+        rich: tagged.then_some(rich),
+    }
+}
+
+// Placeholder values are shown as they are instead of being parsed as tags
+fn escape(value: &str, in_tag: bool) -> String {
+    let special: &[char] = if in_tag {
+        &['\\', '\'', '"', '<']
+    } else {
+        &['\\', '<']
+    };
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn normalize(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_lowercase()
+}
+
+#[derive(Debug, Default)]
+pub struct CatalogHandle {
+    current: RwLock<Arc<Catalog>>,
+}
+
+impl CatalogHandle {
+    pub fn new(catalog: Catalog) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(catalog)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Catalog> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    pub fn replace(&self, catalog: Catalog) -> Arc<Catalog> {
+        mem::replace(&mut self.current.write().unwrap(), Arc::new(catalog))
+    }
+}
@@ -0,0 +1,197 @@
+use super::*;
+
+fn german() -> Catalog {
+    let mut catalog = Catalog::builtin();
+    catalog
+        .add_locale(
+            "de",
+            r#"
+registered = "{authors}, danke für die Registrierung von /{cmd}!"
+timed_out = "Die Verbindung ist abgelaufen."
+"#,
+        )
+        .unwrap();
+    catalog
+}
+
+#[test]
+fn builtin_messages_are_complete() {
+    let catalog = Catalog::builtin();
+    for message in Message::ALL {
+        assert!(
+            catalog.builtin.contains_key(message.key()),
+            "`{}` has no built-in message",
+            message.key()
+        );
+    }
+    assert_eq!(catalog.builtin.len(), Message::ALL.len());
+}
+
+#[test]
+fn placeholders_are_filled() {
+    let catalog = Catalog::builtin();
+    assert_eq!(
+        catalog.render(
+            "en",
+            Message::Registered,
+            &[("authors", &"Essentials Team"), ("cmd", &"afk")]
+        ),
+        "Essentials Team, thank you for registering /afk!"
+    );
+}
+
+fn plain(plain: &str) -> Text {
+    Text {
+        plain: plain.into(),
+        rich: None,
+    }
+}
+
+fn rich(plain: &str, rich: &str) -> Text {
+    Text {
+        plain: plain.into(),
+        rich: Some(rich.into()),
+    }
+}
+
+#[test]
+fn unknown_placeholders_are_kept() {
+    assert_eq!(
+        fill("{cmd} {unknown} {", &[("cmd", &"afk")], &[]),
+        plain("afk {unknown} {")
+    );
+    assert_eq!(fill("{{cmd}}", &[("cmd", &"afk")], &[]), plain("{afk}"));
+}
+
+#[test]
+fn tags_are_left_out_of_the_plain_text() {
+    assert_eq!(
+        fill(
+            "<hover:show_text:'Registered to {owner}'><red>/{cmd}</red></hover> is taken.",
+            &[("cmd", &"afk"), ("owner", &"Essentials")],
+            &[]
+        ),
+        rich(
+            "/afk is taken.",
+            "<hover:show_text:'Registered to Essentials'><red>/afk</red></hover> is taken."
+        )
+    );
+    assert_eq!(
+        fill("<hover:show_text:'a > b'>c</hover>", &[], &[]),
+        rich("c", "<hover:show_text:'a > b'>c</hover>")
+    );
+    assert_eq!(
+        fill(r"\<red> is a tag, \\ isn't", &[], &[]),
+        plain(r"<red> is a tag, \ isn't")
+    );
+}
+
+#[test]
+fn values_are_escaped_in_rich_text() {
+    assert_eq!(
+        fill(
+            "<green>{plugin}</green> <hover:show_text:'{plugin}'>x</hover>",
+            &[("plugin", &r"<bold>Bob's\")],
+            &[]
+        ),
+        rich(
+            r"<bold>Bob's\ x",
+            r"<green>\<bold>Bob's\\</green> <hover:show_text:'\<bold>Bob\'s\\'>x</hover>"
+        )
+    );
+}
+
+#[test]
+fn lists_join_rendered_items() {
+    let catalog = Catalog::builtin();
+    let suggestions = catalog.render_list(
+        "en",
+        Message::Suggestion,
+        Message::SuggestionSeparator,
+        "cmd",
+        &["afk2", "afk_"],
+    );
+    assert_eq!(suggestions.plain, "afk2, afk_");
+    assert_eq!(
+        catalog.render_text(
+            "en",
+            Message::Suggestions,
+            &[],
+            &[("suggestions", &suggestions)]
+        ),
+        rich(
+            "Try one of these instead: afk2, afk_",
+            &format!(
+                "Try one of these instead: {}",
+                suggestions.rich.as_deref().unwrap()
+            )
+        )
+    );
+
+    let mut catalog = Catalog::builtin();
+    catalog.add_locale("en", r#"suggestion = "{cmd}""#).unwrap();
+    let suggestions = catalog.render_list(
+        "en",
+        Message::Suggestion,
+        Message::SuggestionSeparator,
+        "cmd",
+        &["a<b", "c"],
+    );
+    assert_eq!(suggestions, plain("a<b, c"));
+}
+
+#[test]
+fn locales_are_negotiated() {
+    let catalog = german();
+    assert_eq!(catalog.negotiate(Some("de")), "de");
+    assert_eq!(catalog.negotiate(Some("de_AT")), "de");
+    assert_eq!(catalog.negotiate(Some("DE-de")), "de");
+    assert_eq!(catalog.negotiate(Some("fr-FR")), "en");
+    assert_eq!(catalog.negotiate(None), "en");
+}
+
+#[test]
+fn regional_locales_are_preferred() {
+    let mut catalog = german();
+    catalog
+        .add_locale("de_CH", r#"timed_out = "Zeitüberschreitung.""#)
+        .unwrap();
+    assert_eq!(catalog.negotiate(Some("de-CH")), "de-ch");
+    assert_eq!(catalog.negotiate(Some("de-AT")), "de");
+}
+
+#[test]
+fn missing_messages_fall_back() {
+    let catalog = german();
+    assert_eq!(
+        catalog.render("de", Message::TimedOut, &[]),
+        "Die Verbindung ist abgelaufen."
+    );
+    assert_eq!(
+        catalog.render("de", Message::ShuttingDown, &[]),
+        "The registry server is shutting down."
+    );
+
+    let mut catalog = Catalog::new("de");
+    catalog
+        .add_locale("de", r#"timed_out = "Die Verbindung ist abgelaufen.""#)
+        .unwrap();
+    catalog
+        .add_locale("fr", r#"shutting_down = "Le registre s'arrête.""#)
+        .unwrap();
+    assert_eq!(
+        catalog.render("fr", Message::TimedOut, &[]),
+        "Die Verbindung ist abgelaufen."
+    );
+    assert_eq!(
+        catalog.render("fr", Message::NotAllowed, &[]),
+        "This IP isn't allowed to connect."
+    );
+}
+
+#[test]
+fn invalid_catalogs_are_rejected() {
+    let mut catalog = Catalog::builtin();
+    assert!(catalog.add_locale("de", "registered = 1").is_err());
+    assert!(catalog.add_locale("de", "registered = ").is_err());
+}
@@ -0,0 +1,383 @@
+use crate::data::config::RateLimitScope;
+use crate::data::store::DataStore;
+use crate::net::packets::ClientPacket;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+const SAVE_DURATION_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+const ADS_PER_CONNECTION_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(test)]
+mod tests;
+
+// Why an ad that was rolled for wasn't sent
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum AdSuppression {
+    MaxPerConnection,
+    MinMsgsBetween,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum RegisterOutcome {
+    Registered,
+    Unregistered,
+    Denied,
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    active_connections: AtomicU64,
+    packets: Mutex<BTreeMap<&'static str, u64>>,
+    registered: AtomicU64,
+    unregistered: AtomicU64,
+    denied: AtomicU64,
+    ads_sent: AtomicU64,
+    ads_suppressed_max_per_connection: AtomicU64,
+    ads_suppressed_min_msgs_between: AtomicU64,
+    ads_per_connection: Mutex<Histogram>,
+    rate_limited_connection: AtomicU64,
+    rate_limited_ip: AtomicU64,
+    rate_limited_server: AtomicU64,
+    rejected_connections: AtomicU64,
+    saves: Mutex<Histogram>,
+    save_failures: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: vec![0; bounds.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bucket, &bound) in self.buckets.iter_mut().zip(self.bounds) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        for (count, bound) in self.buckets.iter().zip(self.bounds) {
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum {}", self.sum);
+        let _ = writeln!(out, "{name}_count {}", self.count);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            active_connections: AtomicU64::new(0),
+            // Every packet is listed from the start, even if none were received yet
+            packets: Mutex::new(ClientPacket::NAMES.iter().map(|&name| (name, 0)).collect()),
+            registered: AtomicU64::new(0),
+            unregistered: AtomicU64::new(0),
+            denied: AtomicU64::new(0),
+            ads_sent: AtomicU64::new(0),
+            ads_suppressed_max_per_connection: AtomicU64::new(0),
+            ads_suppressed_min_msgs_between: AtomicU64::new(0),
+            ads_per_connection: Mutex::new(Histogram::new(ADS_PER_CONNECTION_BUCKETS)),
+            rate_limited_connection: AtomicU64::new(0),
+            rate_limited_ip: AtomicU64::new(0),
+            rate_limited_server: AtomicU64::new(0),
+            rejected_connections: AtomicU64::new(0),
+            saves: Mutex::new(Histogram::new(SAVE_DURATION_BUCKETS)),
+            save_failures: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn connection_opened(&self) {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn packet_handled(&self, packet: &ClientPacket) {
+        *self
+            .packets
+            .lock()
+            .unwrap()
+            .entry(packet.name())
+            .or_default() += 1;
+    }
+
+    pub fn register_outcome(&self, outcome: RegisterOutcome) {
+        let counter = match outcome {
+            RegisterOutcome::Registered => &self.registered,
+            RegisterOutcome::Unregistered => &self.unregistered,
+            RegisterOutcome::Denied => &self.denied,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn ad_sent(&self) {
+        self.ads_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn ad_suppressed(&self, suppression: AdSuppression) {
+        let counter = match suppression {
+            AdSuppression::MaxPerConnection => &self.ads_suppressed_max_per_connection,
+            AdSuppression::MinMsgsBetween => &self.ads_suppressed_min_msgs_between,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_ads(&self, ads_sent: u32) {
+        self.ads_per_connection
+            .lock()
+            .unwrap()
+            .observe(ads_sent.into());
+    }
+
+    pub fn rate_limited(&self, scope: RateLimitScope) {
+        let counter = match scope {
+            RateLimitScope::Connection => &self.rate_limited_connection,
+            RateLimitScope::Ip => &self.rate_limited_ip,
+            RateLimitScope::Server => &self.rate_limited_server,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_rejected(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn save_succeeded(&self, duration: Duration) {
+        self.saves.lock().unwrap().observe(duration.as_secs_f64());
+    }
+
+    pub fn save_failed(&self) {
+        self.save_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Renders the metrics in the Prometheus text format
+    pub fn render(&self, data: &DataStore) -> String {
+        let mut out = String::new();
+
+        let name = header(
+            &mut out,
+            "registry_active_connections",
+            "gauge",
+            "The number of open connections.",
+        );
+        let _ = writeln!(
+            out,
+            "{name} {}",
+            self.active_connections.load(Ordering::Relaxed)
+        );
+
+        let name = header(
+            &mut out,
+            "registry_packets_handled_total",
+            "counter",
+            "The number of packets received from clients.",
+        );
+        for (packet, count) in self.packets.lock().unwrap().iter() {
+            let _ = writeln!(out, "{name}{{packet=\"{packet}\"}} {count}");
+        }
+
+        let name = header(
+            &mut out,
+            "registry_register_outcomes_total",
+            "counter",
+            "The number of command registrations by outcome.",
+        );
+        for (outcome, counter) in [
+            ("registered", &self.registered),
+            ("unregistered", &self.unregistered),
+            ("denied", &self.denied),
+        ] {
+            let count = counter.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}{{outcome=\"{outcome}\"}} {count}");
+        }
+
+        let name = header(
+            &mut out,
+            "registry_ads_sent_total",
+            "counter",
+            "The number of ads sent to clients.",
+        );
+        let _ = writeln!(out, "{name} {}", self.ads_sent.load(Ordering::Relaxed));
+
+        let name = header(
+            &mut out,
+            "registry_ads_suppressed_total",
+            "counter",
+            "The number of ads not sent because of a frequency cap, by the cap.",
+        );
+        for (cap, counter) in [
+            (
+                "max_per_connection",
+                &self.ads_suppressed_max_per_connection,
+            ),
+            ("min_msgs_between", &self.ads_suppressed_min_msgs_between),
+        ] {
+            let count = counter.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}{{cap=\"{cap}\"}} {count}");
+        }
+
+        let name = header(
+            &mut out,
+            "registry_ads_per_connection",
+            "histogram",
+            "How many ads closed connections were sent.",
+        );
+        self.ads_per_connection
+            .lock()
+            .unwrap()
+            .render(&mut out, name);
+
+        let name = header(
+            &mut out,
+            "registry_rate_limited_total",
+            "counter",
+            "The number of rate limited requests by the scope of the exceeded limit.",
+        );
+        for (scope, counter) in [
+            ("connection", &self.rate_limited_connection),
+            ("ip", &self.rate_limited_ip),
+            ("server", &self.rate_limited_server),
+        ] {
+            let count = counter.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}{{scope=\"{scope}\"}} {count}");
+        }
+
+        let name = header(
+            &mut out,
+            "registry_rejected_connections_total",
+            "counter",
+            "The number of connections rejected for exceeding the per-IP limit.",
+        );
+        let _ = writeln!(
+            out,
+            "{name} {}",
+            self.rejected_connections.load(Ordering::Relaxed)
+        );
+
+        let name = header(
+            &mut out,
+            "registry_save_duration_seconds",
+            "histogram",
+            "How long saving the data store took.",
+        );
+        self.saves.lock().unwrap().render(&mut out, name);
+
+        let name = header(
+            &mut out,
+            "registry_save_failures_total",
+            "counter",
+            "The number of times saving the data store failed.",
+        );
+        let _ = writeln!(out, "{name} {}", self.save_failures.load(Ordering::Relaxed));
+
+        let name = header(
+            &mut out,
+            "registry_registered_cmds",
+            "gauge",
+            "The number of commands in the data store.",
+        );
+        let _ = writeln!(out, "{name} {}", data.len());
+        out
+    }
+}
+
+fn header(out: &mut String, name: &'static str, kind: &str, help: &str) -> &'static str {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+    name
+}
+
+pub fn serve(bind_addr: &str, metrics: Arc<Metrics>, data: Arc<RwLock<DataStore>>) -> Result<()> {
+    let listener = TcpListener::bind(bind_addr)
+        .with_context(|| format!("failed to bind the metrics endpoint to `{bind_addr}`"))?;
+    info!("Serving metrics on {bind_addr}!");
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("Failed to accept a metrics request: {error}.");
+                continue;
+            }
+        };
+        // Scrapes are rare, and the timeouts stop a stalled scraper from holding up the next one
+        if let Err(error) = serve_scrape(stream, &metrics, &data) {
+            warn!("Failed to serve metrics: {error:?}");
+        }
+    }
+    Ok(())
+}
+
+fn serve_scrape(stream: TcpStream, metrics: &Metrics, data: &RwLock<DataStore>) -> Result<()> {
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .context("failed to set the read timeout")?;
+    stream
+        .set_write_timeout(Some(REQUEST_TIMEOUT))
+        .context("failed to set the write timeout")?;
+    respond(stream, metrics, data)
+}
+
+fn respond(
+    mut stream: impl Read + Write,
+    metrics: &Metrics,
+    data: &RwLock<DataStore>,
+) -> Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .context("failed to read the request line")?;
+    // The headers aren't needed, but they have to be read before responding
+    let mut header = String::new();
+    while reader
+        .read_line(&mut header)
+        .context("failed to read a header")?
+        > 2
+    {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = metrics.render(&data.read().unwrap());
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".into(),
+    };
+    stream
+        .write_all(response.as_bytes())
+        .context("failed to write the response")
+}
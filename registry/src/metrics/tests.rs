@@ -0,0 +1,156 @@
+use super::*;
+use std::io::{self, Cursor};
+
+// Reads a scripted request and records the response
+struct ScriptedStream {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl Read for ScriptedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for ScriptedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn response(metrics: &Metrics, data: &RwLock<DataStore>, request: &str) -> String {
+    let mut stream = ScriptedStream {
+        input: Cursor::new(request.as_bytes().to_vec()),
+        output: Vec::new(),
+    };
+    respond(&mut stream, metrics, data).unwrap();
+    String::from_utf8(stream.output).unwrap()
+}
+
+#[test]
+fn every_metric_has_help_and_type_lines() {
+    let out = Metrics::default().render(&DataStore::default());
+    for (name, kind) in [
+        ("registry_active_connections", "gauge"),
+        ("registry_packets_handled_total", "counter"),
+        ("registry_register_outcomes_total", "counter"),
+        ("registry_ads_sent_total", "counter"),
+        ("registry_ads_suppressed_total", "counter"),
+        ("registry_ads_per_connection", "histogram"),
+        ("registry_rate_limited_total", "counter"),
+        ("registry_rejected_connections_total", "counter"),
+        ("registry_save_duration_seconds", "histogram"),
+        ("registry_save_failures_total", "counter"),
+        ("registry_registered_cmds", "gauge"),
+    ] {
+        let help = format!("# HELP {name} ");
+        let type_line = format!("\n# TYPE {name} {kind}\n");
+        let start = out
+            .find(&help)
+            .unwrap_or_else(|| panic!("no HELP for {name}"));
+        assert!(
+            out[start..].contains(&type_line),
+            "no TYPE after the HELP for {name}"
+        );
+    }
+}
+
+#[test]
+fn counters_and_gauges_are_rendered() {
+    let metrics = Metrics::default();
+    metrics.connection_opened();
+    metrics.connection_opened();
+    metrics.connection_closed();
+    metrics.packet_handled(&ClientPacket::Ping { request_id: 0 });
+    metrics.register_outcome(RegisterOutcome::Denied);
+    metrics.ad_suppressed(AdSuppression::MinMsgsBetween);
+    metrics.rate_limited(RateLimitScope::Ip);
+    let mut data = DataStore::default();
+    data.register("afk", "Essentials").unwrap();
+
+    let out = metrics.render(&data);
+    for line in [
+        "registry_active_connections 1",
+        "registry_packets_handled_total{packet=\"Ping\"} 1",
+        "registry_packets_handled_total{packet=\"RegisterCmd\"} 0",
+        "registry_register_outcomes_total{outcome=\"denied\"} 1",
+        "registry_register_outcomes_total{outcome=\"registered\"} 0",
+        "registry_ads_suppressed_total{cap=\"min_msgs_between\"} 1",
+        "registry_rate_limited_total{scope=\"ip\"} 1",
+        "registry_registered_cmds 1",
+    ] {
+        assert!(out.lines().any(|rendered| rendered == line), "no `{line}`");
+    }
+}
+
+#[test]
+fn histogram_buckets_are_cumulative() {
+    let metrics = Metrics::default();
+    for ads_sent in [0, 2, 3, 100] {
+        metrics.connection_ads(ads_sent);
+    }
+    let out = metrics.render(&DataStore::default());
+    let lines: Vec<_> = out
+        .lines()
+        .filter(|line| line.starts_with("registry_ads_per_connection_"))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            "registry_ads_per_connection_bucket{le=\"0\"} 1",
+            "registry_ads_per_connection_bucket{le=\"1\"} 1",
+            "registry_ads_per_connection_bucket{le=\"2\"} 2",
+            "registry_ads_per_connection_bucket{le=\"5\"} 3",
+            "registry_ads_per_connection_bucket{le=\"10\"} 3",
+            "registry_ads_per_connection_bucket{le=\"20\"} 3",
+            "registry_ads_per_connection_bucket{le=\"50\"} 3",
+            "registry_ads_per_connection_bucket{le=\"+Inf\"} 4",
+            "registry_ads_per_connection_sum 105",
+            "registry_ads_per_connection_count 4",
+        ]
+    );
+}
+
+#[test]
+fn metrics_are_served_with_the_text_format() {
+    let metrics = Metrics::default();
+    metrics.ad_sent();
+    let data = RwLock::new(DataStore::default());
+    let response = response(
+        &metrics,
+        &data,
+        "GET /metrics HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n",
+    );
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert_eq!(body, metrics.render(&data.read().unwrap()));
+    assert_eq!(
+        head,
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                Content-Length: {}\r\nConnection: close",
+            body.len()
+        )
+    );
+    assert!(body.contains("\nregistry_ads_sent_total 1\n"));
+}
+
+#[test]
+fn other_paths_and_methods_are_not_found() {
+    let not_found = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    for request in [
+        "GET / HTTP/1.1\r\n\r\n",
+        "GET /metrics/ HTTP/1.1\r\n\r\n",
+        "POST /metrics HTTP/1.1\r\nContent-Length: 0\r\n\r\n",
+        "",
+    ] {
+        assert_eq!(
+            response(&Metrics::default(), &RwLock::default(), request),
+            not_found
+        );
+    }
+}
@@ -1,88 +1,22 @@
-use crate::net::types::{NetReadExt, NetWriteExt};
-use anyhow::{bail, Context, Result};
-use log::Level;
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 
-#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
-pub enum ClientPacket {
-    Handshake {
-        version: String,
-    },
-    SelectPlugin {
-        name: String,
-        authors: Option<String>,
-    },
-    EnablePlugin,
-    DisablePlugin,
-    RegisterCmd(String),
-    Disconnect,
-}
+#[cfg(test)]
+mod tests;
 
-impl ClientPacket {
-    pub fn read(id: u8, buf: &mut impl Read) -> Result<Self> {
-        let packet = match id {
-            0x00 => {
-                let version = buf.read_string().context("failed to read the version")?;
-                Self::Handshake { version }
-            }
-            0x01 => {
-                let name = buf
-                    .read_string()
-                    .context("failed to read the plugin name")?;
-                let authors = buf
-                    .read_option(NetReadExt::read_string)
-                    .context("failed to read the plugin authors")?;
-                Self::SelectPlugin { name, authors }
-            }
-            0x02 => Self::EnablePlugin,
-            0x03 => Self::DisablePlugin,
-            0x04 => {
-                let name = buf
-                    .read_string()
-                    .context("failed to read the command name")?;
-                Self::RegisterCmd(name)
-            }
-            0x05 => Self::Disconnect,
-            _ => bail!("the packet ID is invalid ({id:#04x})"),
-        };
-        Ok(packet)
-    }
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/packets.rs"));
 }
 
-#[derive(Clone, Eq, PartialEq, Hash, Debug)]
-pub enum ServerPacket {
-    Handshake { ads_enabled: bool },
-    Msg { log_level: Level, contents: String },
-    Deny,
-    Done,
-    Disconnect,
-}
+pub use generated::*;
 
-impl ServerPacket {
-    pub fn write(&self, buf: &mut impl Write) -> Result<u8> {
-        let id = match self {
-            Self::Handshake { ads_enabled } => {
-                buf.write_bool(*ads_enabled)
-                    .context("failed to write the ad indicator")?;
-                0x00
-            }
-            Self::Msg {
-                log_level,
-                contents,
-            } => {
-                buf.write_log_level(*log_level)
-                    .context("failed to write the log level")?;
-                buf.write_str(contents)
-                    .context("failed to write the contents")?;
-                0x01
-            }
-            Self::Deny => 0x02,
-            Self::Done => 0x03,
-            Self::Disconnect => 0x04,
-        };
-        Ok(id)
-    }
+pub type RequestId = u32;
+
+pub trait Packet: Sized {
+    fn read(id: u8, buf: &mut impl Read) -> Result<Self>;
+
+    fn write(&self, buf: &mut impl Write) -> Result<u8>;
 }
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
@@ -132,10 +66,10 @@ impl PartialPacket {
                 }
             }
             Self::Incomplete {
-                expected,
+                expected: 0,
                 id: _,
                 packet,
-            } if expected == 0 => Self::Complete { id: byte, packet },
+            } => Self::Complete { id: byte, packet },
             Self::Incomplete {
                 expected,
                 id: _,
@@ -0,0 +1,206 @@
+use super::*;
+use crate::net::types::{NetReadExt, NetWriteExt, PacketOpResult};
+use log::Level;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample;
+
+fn request_id() -> impl Strategy<Value = RequestId> {
+    any::<RequestId>()
+}
+
+fn string() -> impl Strategy<Value = String> {
+    ".{0,64}"
+}
+
+fn disconnect_reason() -> impl Strategy<Value = DisconnectReason> {
+    sample::select(DisconnectReason::ALL.to_vec())
+}
+
+fn log_level() -> impl Strategy<Value = Level> {
+    prop_oneof![
+        Just(Level::Error),
+        Just(Level::Warn),
+        Just(Level::Info),
+        Just(Level::Debug),
+        Just(Level::Trace),
+    ]
+}
+
+fn client_packet() -> impl Strategy<Value = ClientPacket> {
+    prop_oneof![
+        (
+            request_id(),
+            string(),
+            proptest::option::of(string()),
+            proptest::option::of(string()),
+            proptest::option::of(any::<bool>()),
+            proptest::option::of(string())
+        )
+            .prop_map(
+                |(request_id, version, server_id, api_key, ads_opt_out, locale)| {
+                    ClientPacket::Handshake {
+                        request_id,
+                        version,
+                        server_id,
+                        api_key,
+                        ads_opt_out,
+                        locale,
+                    }
+                }
+            ),
+        (
+            request_id(),
+            string(),
+            proptest::option::of(string()),
+            proptest::option::of(string()),
+            proptest::option::of(string()),
+            proptest::option::of(string()),
+            proptest::option::of(string())
+        )
+            .prop_map(
+                |(request_id, name, authors, plugin_id, version, website, description)| {
+                    ClientPacket::SelectPlugin {
+                        request_id,
+                        name,
+                        authors,
+                        plugin_id,
+                        version,
+                        website,
+                        description,
+                    }
+                }
+            ),
+        request_id().prop_map(|request_id| ClientPacket::EnablePlugin { request_id }),
+        request_id().prop_map(|request_id| ClientPacket::DisablePlugin { request_id }),
+        (request_id(), string())
+            .prop_map(|(request_id, name)| ClientPacket::RegisterCmd { request_id, name }),
+        (disconnect_reason(), string())
+            .prop_map(|(reason, message)| ClientPacket::Disconnect { reason, message }),
+        (request_id(), string())
+            .prop_map(|(request_id, cmd)| ClientPacket::QueryOwner { request_id, cmd }),
+        (request_id(), string()).prop_map(|(request_id, plugin)| {
+            ClientPacket::QueryPluginCmds { request_id, plugin }
+        }),
+        (request_id(), string())
+            .prop_map(|(request_id, prefix)| ClientPacket::QueryPrefix { request_id, prefix }),
+        request_id().prop_map(|request_id| ClientPacket::Ping { request_id }),
+        Just(ClientPacket::Pong),
+    ]
+}
+
+fn server_packet() -> impl Strategy<Value = ServerPacket> {
+    prop_oneof![
+        (request_id(), any::<bool>(), string()).prop_map(|(request_id, ads_enabled, locale)| {
+            ServerPacket::Handshake {
+                request_id,
+                ads_enabled,
+                locale,
+            }
+        }),
+        (
+            request_id(),
+            log_level(),
+            string(),
+            proptest::option::of(string())
+        )
+            .prop_map(
+                |(request_id, log_level, contents, rich)| ServerPacket::Msg {
+                    request_id,
+                    log_level,
+                    contents,
+                    rich,
+                }
+            ),
+        request_id().prop_map(|request_id| ServerPacket::Deny { request_id }),
+        request_id().prop_map(|request_id| ServerPacket::Done { request_id }),
+        (disconnect_reason(), string())
+            .prop_map(|(reason, message)| ServerPacket::Disconnect { reason, message }),
+        (request_id(), proptest::option::of(string()))
+            .prop_map(|(request_id, owner)| ServerPacket::Owner { request_id, owner }),
+        (
+            request_id(),
+            vec(
+                (string(), string()).prop_map(|(cmd, owner)| CmdOwner { cmd, owner }),
+                0..16
+            ),
+            any::<bool>()
+        )
+            .prop_map(|(request_id, cmds, truncated)| ServerPacket::CmdOwners {
+                request_id,
+                cmds,
+                truncated,
+            }),
+        request_id().prop_map(|request_id| ServerPacket::Pong { request_id }),
+        Just(ServerPacket::Ping),
+        (request_id(), any::<u32>()).prop_map(|(request_id, retry_after_ms)| {
+            ServerPacket::RateLimited {
+                request_id,
+                retry_after_ms,
+            }
+        }),
+        (request_id(), string()).prop_map(|(request_id, contents)| ServerPacket::Ad {
+            request_id,
+            contents
+        }),
+    ]
+}
+
+fn round_trip<P: Packet>(packet: &P) -> P {
+    let mut buf = Vec::new();
+    assert_eq!(buf.write_packet(packet).unwrap(), PacketOpResult::Ok(()));
+    let mut reader = &buf[..];
+    let PacketOpResult::Ok(decoded) = reader.read_packet().unwrap() else {
+        panic!("the packet was cut short");
+    };
+    assert!(reader.is_empty(), "the packet wasn't fully read");
+    decoded
+}
+
+#[test]
+fn api_keys_are_redacted_in_debug_output() {
+    let handshake = |api_key: Option<&str>| ClientPacket::Handshake {
+        request_id: 0,
+        version: "1.20.4".into(),
+        server_id: Some("survival".into()),
+        api_key: api_key.map(Into::into),
+        ads_opt_out: None,
+        locale: None,
+    };
+    let debug = format!("{:?}", handshake(Some("csr_0123456789abcdef")));
+    assert!(!debug.contains("csr_0123456789abcdef"));
+    assert_eq!(
+        debug,
+        r#"Handshake { request_id: 0, version: "1.20.4", server_id: Some("survival"), api_key: Some("***"), ads_opt_out: None, locale: None }"#
+    );
+    assert!(format!("{:?}", handshake(None)).contains("api_key: None"));
+}
+
+proptest! {
+    #[test]
+    fn client_packets_round_trip(packet in client_packet()) {
+        prop_assert_eq!(round_trip(&packet), packet);
+    }
+
+    #[test]
+    fn server_packets_round_trip(packet in server_packet()) {
+        prop_assert_eq!(round_trip(&packet), packet);
+    }
+
+    #[test]
+    fn arbitrary_client_payloads_do_not_panic(id in any::<u8>(), payload in vec(any::<u8>(), 0..64)) {
+        let _ = ClientPacket::read(id, &mut &payload[..]);
+    }
+
+    #[test]
+    fn partial_packets_frame_arbitrary_payloads(id in any::<u8>(), payload in vec(any::<u8>(), 0..512)) {
+        let len = u16::try_from(payload.len()).unwrap().to_be_bytes();
+        let mut partial = PartialPacket::new();
+        for byte in len.into_iter().chain([id]).chain(payload.iter().copied()) {
+            let complete = matches!(partial, PartialPacket::Complete { .. });
+            prop_assert!(!complete);
+            partial = partial.next(byte);
+        }
+        prop_assert_eq!(partial, PartialPacket::Complete { id, packet: payload });
+    }
+}
@@ -1,13 +1,22 @@
-use crate::net::packets::{ClientPacket, PartialPacket, ServerPacket};
+use crate::net::packets::{Packet, PartialPacket, RequestId};
 use anyhow::{bail, Context, Result};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use log::Level;
-use std::io;
 use std::io::{ErrorKind, Read, Write};
+use std::{io, mem};
+
+// Packet lengths are written as a u16
+pub const MAX_PAYLOAD_LEN: usize = u16::MAX as usize;
 
 pub trait NetReadExt: Read {
-    fn read_packet(&mut self) -> Result<PacketOpResult<ClientPacket>> {
-        let mut partial = PartialPacket::new();
+    fn read_packet<P: Packet>(&mut self) -> Result<PacketOpResult<P>> {
+        self.resume_packet(&mut PartialPacket::new())
+    }
+
+    fn resume_packet<P: Packet>(
+        &mut self,
+        partial: &mut PartialPacket,
+    ) -> Result<PacketOpResult<P>> {
         loop {
             let next = match self.read_u8() {
                 Ok(next) => next,
@@ -16,12 +25,12 @@ pub trait NetReadExt: Read {
                         .context("failed to read the next byte");
                 }
             };
-            match partial.next(next) {
+            match mem::take(partial).next(next) {
                 PartialPacket::Complete { id, packet } => {
-                    let packet = ClientPacket::read(id, &mut &packet[..])?;
+                    let packet = P::read(id, &mut &packet[..])?;
                     break Ok(PacketOpResult::Ok(packet));
                 }
-                p => partial = p,
+                p => *partial = p,
             }
         }
     }
@@ -37,6 +46,18 @@ pub trait NetReadExt: Read {
         }
     }
 
+    fn read_list<T>(&mut self, mut read: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let len = self
+            .read_u16::<BigEndian>()
+            .context("failed to read the list length")?
+            .into();
+        let mut list = Vec::with_capacity(len);
+        for _ in 0..len {
+            list.push(read(self)?);
+        }
+        Ok(list)
+    }
+
     fn read_bool(&mut self) -> Result<bool> {
         let byte = self.read_u8().context("failed to read the boolean byte")?;
         Ok(byte != 0)
@@ -53,6 +74,11 @@ pub trait NetReadExt: Read {
         String::from_utf8(buf).context("the string is malformed")
     }
 
+    fn read_request_id(&mut self) -> Result<RequestId> {
+        self.read_u32::<BigEndian>()
+            .context("failed to read the request ID")
+    }
+
     fn read_log_level(&mut self) -> Result<Level> {
         let byte = self
             .read_u8()
@@ -72,7 +98,7 @@ pub trait NetReadExt: Read {
 impl<R> NetReadExt for R where R: Read + ?Sized {}
 
 pub trait NetWriteExt: Write {
-    fn write_packet(&mut self, packet: &ServerPacket) -> Result<PacketOpResult<()>> {
+    fn write_packet(&mut self, packet: &impl Packet) -> Result<PacketOpResult<()>> {
         let mut payload = Vec::with_capacity(1024);
         let id = packet
             .write(&mut payload)
@@ -113,6 +139,23 @@ pub trait NetWriteExt: Write {
         }
     }
 
+    fn write_list<T>(
+        &mut self,
+        list: &[T],
+        mut write: impl FnMut(&mut Self, &T) -> Result<()>,
+    ) -> Result<()> {
+        let len = list
+            .len()
+            .try_into()
+            .context("the list length doesn't fit in a u16")?;
+        self.write_u16::<BigEndian>(len)
+            .context("failed to write the list length")?;
+        for element in list {
+            write(self, element)?;
+        }
+        Ok(())
+    }
+
     fn write_bool(&mut self, b: bool) -> Result<()> {
         self.write_u8(if b { 1 } else { 0 })
             .context("failed to write the boolean byte")
@@ -130,6 +173,11 @@ pub trait NetWriteExt: Write {
             .context("failed to write the string contents")
     }
 
+    fn write_request_id(&mut self, request_id: RequestId) -> Result<()> {
+        self.write_u32::<BigEndian>(request_id)
+            .context("failed to write the request ID")
+    }
+
     fn write_log_level(&mut self, level: Level) -> Result<()> {
         let byte = match level {
             Level::Error => 4,
@@ -149,15 +197,20 @@ impl<W> NetWriteExt for W where W: Write + ?Sized {}
 pub enum PacketOpResult<T> {
     Ok(T),
     AppearsDisconnected,
+    TimedOut,
 }
 
 impl<T> PacketOpResult<T> {
     pub fn from_io_error(error: io::Error) -> Result<Self> {
         if let ErrorKind::ConnectionAborted
         | ErrorKind::ConnectionReset
+        | ErrorKind::BrokenPipe
+        | ErrorKind::NotConnected
         | ErrorKind::UnexpectedEof = error.kind()
         {
             Ok(Self::AppearsDisconnected)
+        } else if let ErrorKind::WouldBlock | ErrorKind::TimedOut = error.kind() {
+            Ok(Self::TimedOut)
         } else {
             Err(error.into())
         }
@@ -1,3 +1,4 @@
+use crate::data::store::PluginMeta;
 use anyhow::{anyhow, bail, Result};
 use log::debug;
 use serde::{Deserialize, Serialize};
@@ -47,10 +48,19 @@ impl Plugins {
         self.current_info_mut().cmds.insert(name, status);
     }
 
+    pub fn is_selected(&self) -> bool {
+        self.plugins.contains_key(&self.current)
+    }
+
     pub fn current_authors(&self) -> &str {
         &self.current_info().authors
     }
 
+    // What owns the selected plugin's commands, its ID if it declared one
+    pub fn selected_owner(&self) -> &str {
+        self.current_info().id.as_deref().unwrap_or(&self.current)
+    }
+
     fn current_info(&self) -> &PluginInfo {
         self.plugins.get(&self.current).unwrap()
     }
@@ -67,6 +77,8 @@ impl Plugins {
 #[derive(Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
 pub struct PluginInfo {
     pub authors: String,
+    pub id: Option<String>,
+    pub meta: PluginMeta,
     pub enabled: bool,
     pub cmds: HashMap<String, GlobalCommandStatus>,
 }
@@ -75,10 +87,15 @@ impl PluginInfo {
     pub fn from_optional_authors(authors: Option<String>) -> Option<Self> {
         authors.map(|authors| PluginInfo {
             authors,
-            enabled: false,
-            cmds: HashMap::new(),
+            ..PluginInfo::default()
         })
     }
+
+    pub fn with_meta(mut self, id: Option<String>, meta: PluginMeta) -> Self {
+        self.id = id;
+        self.meta = meta;
+        self
+    }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Serialize, Deserialize)]
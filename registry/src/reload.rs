@@ -0,0 +1,163 @@
+use crate::data::config::{Config, ConfigHandle};
+use crate::data::PersistentData;
+use crate::logging;
+use crate::messages::{Catalog, CatalogHandle};
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use toml::Value;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[cfg(test)]
+mod tests;
+
+pub fn watch_config(path: PathBuf, config: Arc<ConfigHandle>, messages: Arc<CatalogHandle>) {
+    let mut watcher = Watcher::new(path, config, messages);
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        watcher.poll();
+    }
+}
+
+struct Watcher {
+    path: PathBuf,
+    config: Arc<ConfigHandle>,
+    messages: Arc<CatalogHandle>,
+    last_modified: Option<SystemTime>,
+    last_messages_modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    fn new(path: PathBuf, config: Arc<ConfigHandle>, messages: Arc<CatalogHandle>) -> Self {
+        Self {
+            last_modified: modified(&path),
+            last_messages_modified: messages_modified(&path, &config.get()),
+            path,
+            config,
+            messages,
+        }
+    }
+
+    fn poll(&mut self) {
+        let modified = modified(&self.path);
+        if modified != self.last_modified {
+            self.last_modified = modified;
+            if let Err(error) = reload(&self.path, &self.config, &self.messages) {
+                error!("Failed to reload the config, keeping the current one: {error:?}");
+            }
+        }
+
+        let config = self.config.get();
+        let messages_modified = messages_modified(&self.path, &config);
+        if messages_modified != self.last_messages_modified {
+            self.last_messages_modified = messages_modified;
+            match reload_messages(&self.path, &config, &self.messages) {
+                Ok(()) => info!("Reloaded the messages."),
+                Err(error) => {
+                    error!("Failed to reload the messages, keeping the current ones: {error:?}")
+                }
+            }
+        }
+    }
+}
+
+pub fn reload(
+    path: impl AsRef<Path>,
+    config: &ConfigHandle,
+    messages: &CatalogHandle,
+) -> Result<()> {
+    let path = path.as_ref();
+    let new = Config::load(path).context("failed to load the config")?;
+    let old = config.get();
+    let changes = diff(&old, &new).context("failed to compare the configs")?;
+    if changes.is_empty() {
+        debug!("The config file changed, but its contents didn't.");
+        return Ok(());
+    }
+
+    if new.server.bind_addr != old.server.bind_addr {
+        warn!("The bind address will only change after restarting.");
+    }
+    if new.server.read_timeout != old.server.read_timeout {
+        warn!("The read timeout will only change for new connections.");
+    }
+    if new.metrics != old.metrics {
+        warn!("The metrics endpoint will only change after restarting.");
+    }
+    if new.logging != old.logging {
+        logging::configure(&new.logging, crate::config_dir(path))
+            .context("failed to configure logging")?;
+    }
+    if new.messages != old.messages {
+        reload_messages(path, &new, messages).context("failed to reload the messages")?;
+    }
+    config.replace(new);
+    info!("Reloaded the config.");
+    for change in changes {
+        info!("{change}");
+    }
+    Ok(())
+}
+
+fn reload_messages(config_path: &Path, config: &Config, messages: &CatalogHandle) -> Result<()> {
+    let dir = crate::config_dir(config_path).join(&config.messages.dir);
+    messages.replace(Catalog::load(dir, &config.messages.default_locale)?);
+    Ok(())
+}
+
+// The latest modification of the catalogs, or of the directory itself when one is removed
+fn messages_modified(config_path: &Path, config: &Config) -> Option<SystemTime> {
+    let dir = crate::config_dir(config_path).join(&config.messages.dir);
+    let entries = fs::read_dir(&dir).ok()?;
+    entries
+        .filter_map(|entry| modified(&entry.ok()?.path()))
+        .chain(modified(&dir))
+        .max()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn diff(old: &Config, new: &Config) -> Result<Vec<String>> {
+    let old = Value::try_from(old).context("failed to serialize the current config")?;
+    let new = Value::try_from(new).context("failed to serialize the new config")?;
+    let mut changes = Vec::new();
+    diff_values(None, &old, &new, &mut changes);
+    Ok(changes)
+}
+
+fn diff_values(key: Option<&str>, old: &Value, new: &Value, changes: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Table(old_table), Value::Table(new_table)) => {
+            let key_for = |name: &str| match key {
+                Some(key) => format!("{key}.{name}"),
+                None => name.to_string(),
+            };
+            for (name, old) in old_table {
+                let key = key_for(name);
+                match new_table.get(name) {
+                    Some(new) => diff_values(Some(&key), old, new, changes),
+                    None => changes.push(format!("Removed `{key}`.")),
+                }
+            }
+            for (name, new) in new_table {
+                if !old_table.contains_key(name) {
+                    changes.push(format!("Added `{}` as {new}.", key_for(name)));
+                }
+            }
+        }
+        (old, new) if old != new => {
+            let key = key.unwrap_or_default();
+            changes.push(format!("Changed `{key}` from {old} to {new}."));
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,139 @@
+use super::*;
+use crate::data::config::AdTier;
+use std::fs::File;
+use std::{env, process};
+
+fn default_config() -> Config {
+    Config::from_toml(Config::DEFAULT).unwrap()
+}
+
+fn changes(configure: impl FnOnce(&mut Config)) -> Vec<String> {
+    let old = default_config();
+    let mut new = old.clone();
+    configure(&mut new);
+    diff(&old, &new).unwrap()
+}
+
+fn gold_tier() -> AdTier {
+    AdTier {
+        enabled: Some(false),
+        ..AdTier::default()
+    }
+}
+
+#[test]
+fn identical_configs_have_no_changes() {
+    assert!(changes(|_| {}).is_empty());
+}
+
+#[test]
+fn changed_keys_are_reported() {
+    assert_eq!(
+        changes(|config| config.server.max_connections_per_ip = 16),
+        ["Changed `server.max_connections_per_ip` from 8 to 16."]
+    );
+}
+
+#[test]
+fn added_keys_are_reported() {
+    assert_eq!(
+        changes(|config| {
+            config.ads.tiers.insert("gold".into(), gold_tier());
+        }),
+        ["Added `ads.tiers.gold` as { enabled = false }."]
+    );
+}
+
+#[test]
+fn removed_keys_are_reported() {
+    let mut old = default_config();
+    old.ads.tiers.insert("gold".into(), gold_tier());
+    assert_eq!(
+        diff(&old, &default_config()).unwrap(),
+        ["Removed `ads.tiers.gold`."]
+    );
+}
+
+fn config_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!(
+        "cardstock-registry-reload-{name}-{}",
+        process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    fs::write(&path, Config::DEFAULT).unwrap();
+    path
+}
+
+fn watcher(path: &Path) -> Watcher {
+    let config = Arc::new(ConfigHandle::new(Config::load(path).unwrap()));
+    let messages = Arc::new(CatalogHandle::new(Catalog::builtin()));
+    Watcher::new(path.to_path_buf(), config, messages)
+}
+
+// Moves the modification time forward, since a write can land within the file system's resolution
+fn edit(path: &Path, change: impl FnOnce(&str) -> String, later: Duration) {
+    fs::write(path, change(Config::DEFAULT)).unwrap();
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(SystemTime::now() + later)
+        .unwrap();
+}
+
+#[test]
+fn polling_reloads_edited_configs() {
+    let path = config_dir("poll");
+    let mut watcher = watcher(&path);
+    watcher.poll();
+    let unchanged = watcher.config.get();
+    edit(
+        &path,
+        |contents| contents.replace("max_connections_per_ip = 8", "max_connections_per_ip = 16"),
+        Duration::from_secs(1),
+    );
+    watcher.poll();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(*unchanged, default_config());
+    assert_eq!(watcher.config.get().server.max_connections_per_ip, 16);
+}
+
+#[test]
+fn invalid_edits_keep_the_current_config() {
+    let path = config_dir("invalid");
+    let mut watcher = watcher(&path);
+    edit(
+        &path,
+        |contents| contents.replace("bind_addr = \"0.0.0.0:15656\"", "bind_addr = \"\""),
+        Duration::from_secs(1),
+    );
+    watcher.poll();
+    let after_invalid = watcher.config.get();
+    edit(
+        &path,
+        |contents| contents.replace("max_connections_per_ip = 8", "max_connections_per_ip = 16"),
+        Duration::from_secs(2),
+    );
+    watcher.poll();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(*after_invalid, default_config());
+    assert_eq!(watcher.config.get().server.max_connections_per_ip, 16);
+}
+
+#[test]
+fn reloading_applies_added_keys() {
+    let path = config_dir("added");
+    let config = ConfigHandle::new(Config::load(&path).unwrap());
+    let messages = CatalogHandle::new(Catalog::builtin());
+
+    let contents = Config::DEFAULT.replace("tiers = {}", "tiers = { gold = { enabled = false } }");
+    fs::write(&path, contents).unwrap();
+    let result = reload(&path, &config, &messages);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    result.unwrap();
+    assert_eq!(config.get().ads.tiers.get("gold"), Some(&gold_tier()));
+}
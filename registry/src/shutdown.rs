@@ -0,0 +1,62 @@
+use log::warn;
+use std::collections::HashMap;
+use std::net::{self, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    next_id: AtomicU64,
+    streams: Mutex<HashMap<u64, TcpStream>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    pub fn track(&self, stream: &TcpStream) -> Option<u64> {
+        let stream = match stream.try_clone() {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("Failed to clone a stream for shutdown tracking: {error:?}");
+                return None;
+            }
+        };
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.streams.lock().unwrap().insert(id, stream);
+        Some(id)
+    }
+
+    pub fn untrack(&self, id: u64) {
+        self.streams.lock().unwrap().remove(&id);
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        for stream in self.streams.lock().unwrap().values() {
+            // Only shut down the read half so connections can still send a disconnect packet
+            if let Err(error) = stream.shutdown(net::Shutdown::Read) {
+                warn!("Failed to interrupt a connection: {error:?}");
+            }
+        }
+    }
+
+    pub fn wait_for_connections(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while !self.streams.lock().unwrap().is_empty() {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        true
+    }
+}
@@ -1,8 +1,13 @@
 package sh.lpx.cardstock.registry;
 
+import net.kyori.adventure.text.Component;
+import net.kyori.adventure.text.minimessage.MiniMessage;
 import org.jetbrains.annotations.NotNull;
+import org.jetbrains.annotations.Nullable;
 import org.slf4j.Logger;
+import sh.lpx.cardstock.registry.packet.server.ServerPacket;
 
+import java.time.Duration;
 import java.util.ArrayList;
 import java.util.List;
 import java.util.function.BiConsumer;
@@ -10,23 +15,62 @@ import java.util.function.BiConsumer;
 public class RegisterResponse {
     private boolean denied = false;
     private final List<Msg> msgs = new ArrayList<>();
+    private ServerPacket answer = null;
+    private Duration retryAfter = null;
 
     public void setDenied() {
         this.denied = true;
     }
 
-    public void addMsg(@NotNull BiConsumer<@NotNull Logger, String> logFn, @NotNull String contents) {
-        this.msgs.add(new Msg(logFn, contents));
+    public void setRateLimited(@NotNull Duration retryAfter) {
+        this.retryAfter = retryAfter;
+    }
+
+    public void setAnswer(@NotNull ServerPacket answer) {
+        this.answer = answer;
+    }
+
+    public void addMsg(
+        @NotNull BiConsumer<@NotNull Logger, String> logFn,
+        @NotNull String contents,
+        @Nullable String rich
+    ) {
+        this.msgs.add(new Msg(logFn, contents, rich));
     }
 
     public @NotNull Complete reset() {
-        Complete complete = new Complete(this.denied, this.msgs.toArray(new Msg[0]));
+        Complete complete = new Complete(this.denied, this.msgs.toArray(new Msg[0]), this.answer, this.retryAfter);
         this.denied = false;
         this.msgs.clear();
+        this.answer = null;
+        this.retryAfter = null;
         return complete;
     }
 
-    public record Msg(@NotNull BiConsumer<@NotNull Logger, String> logFn, @NotNull String contents) {}
+    // `rich` is MiniMessage markup for showing the message in-game, `contents` is the same as plain text
+    public record Msg(
+        @NotNull BiConsumer<@NotNull Logger, String> logFn,
+        @NotNull String contents,
+        @Nullable String rich
+    ) {
+        public @NotNull Component component() {
+            return this.rich != null ? MiniMessage.miniMessage().deserialize(this.rich) : Component.text(this.contents);
+        }
+    }
+
+    // `retryAfter` is set if the server rate limited the request instead of handling it
+    public record Complete(
+        boolean denied,
+        @NotNull Msg @NotNull [] msgs,
+        @Nullable ServerPacket answer,
+        @Nullable Duration retryAfter
+    ) {
+        public static @NotNull Complete empty() {
+            return new Complete(false, new Msg[0], null, null);
+        }
 
-    public record Complete(boolean denied, @NotNull Msg @NotNull [] msgs) {}
+        public boolean rateLimited() {
+            return this.retryAfter != null;
+        }
+    }
 }
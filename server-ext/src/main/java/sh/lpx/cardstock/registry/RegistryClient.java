@@ -6,34 +6,54 @@ import org.jetbrains.annotations.Nullable;
 import org.slf4j.Logger;
 import org.slf4j.LoggerFactory;
 import sh.lpx.cardstock.Cardstock;
+import sh.lpx.cardstock.registry.packet.DisconnectReason;
 import sh.lpx.cardstock.registry.packet.PacketByteBuf;
 import sh.lpx.cardstock.registry.packet.PartialPacket;
 import sh.lpx.cardstock.registry.packet.client.ClientDisconnectPacket;
 import sh.lpx.cardstock.registry.packet.client.ClientHandshakePacket;
 import sh.lpx.cardstock.registry.packet.client.ClientPacket;
+import sh.lpx.cardstock.registry.packet.client.ClientPingPacket;
+import sh.lpx.cardstock.registry.packet.client.ClientPongPacket;
 import sh.lpx.cardstock.registry.packet.server.*;
 
 import java.io.*;
 import java.net.Socket;
+import java.net.SocketTimeoutException;
+import java.time.Duration;
+import java.util.Locale;
+import java.util.Map;
 import java.util.Optional;
-import java.util.concurrent.ArrayBlockingQueue;
-import java.util.concurrent.BlockingQueue;
+import java.util.concurrent.CompletableFuture;
+import java.util.concurrent.ConcurrentHashMap;
+import java.util.concurrent.ExecutionException;
+import java.util.concurrent.atomic.AtomicLong;
 
 public class RegistryClient
     implements AutoCloseable
 {
     private static final int ERROR_TOLERANCE = 5;
     private static final boolean ERROR_TOLERANCE_SET = ERROR_TOLERANCE >= 0;
+    private static final int READ_TIMEOUT_MILLIS = 15_000;
+    private static final long IDLE_TIMEOUT_NANOS = 60_000_000_000L;
+    private static final int RATE_LIMIT_RETRIES = 3;
+    private static final String SERVER_ID_PROPERTY = "cardstock.registry.serverId";
+    // An environment variable rather than a property, so the key doesn't show up in process lists
+    private static final String API_KEY_ENV = "CARDSTOCK_REGISTRY_API_KEY";
+    private static final String ADS_OPT_OUT_PROPERTY = "cardstock.registry.adsOptOut";
+    private static final String LOCALE_PROPERTY = "cardstock.registry.locale";
 
     private final Logger logger = LoggerFactory.getLogger(RegistryClient.class);
     private final Server server;
 
     private final Socket socket;
     private boolean didHandshake = false;
+    private boolean requestedAdsOptOut = false;
     private boolean shutDown = false;
+    private long lastReceived = System.nanoTime();
 
-    private final RegisterResponse registerResponse = new RegisterResponse();
-    private final BlockingQueue<RegisterResponse.Complete> registerResponseQueue = new ArrayBlockingQueue<>(1);
+    private final AtomicLong nextRequestId = new AtomicLong();
+    private final Map<Long, RegisterResponse> registerResponses = new ConcurrentHashMap<>();
+    private final Map<Long, CompletableFuture<RegisterResponse.Complete>> pendingResponses = new ConcurrentHashMap<>();
 
     private final InputStream inputStream;
     private final OutputStream outputStream;
@@ -71,13 +91,30 @@ public class RegistryClient
         }
 
         Socket socket = new Socket(host, port);
+        socket.setSoTimeout(READ_TIMEOUT_MILLIS);
         RegistryClient client = new RegistryClient(server, socket, socket.getInputStream(), socket.getOutputStream());
         if (handshake != null) {
+            handshake = withDefaults(handshake);
+            client.requestedAdsOptOut = Boolean.TRUE.equals(handshake.adsOptOut());
             client.sendPacket(handshake);
         }
         return client;
     }
 
+    // Fills in what the caller left out from the environment
+    private static @NotNull ClientHandshakePacket withDefaults(@NotNull ClientHandshakePacket handshake) {
+        String adsOptOut = System.getProperty(ADS_OPT_OUT_PROPERTY);
+        return new ClientHandshakePacket(
+            handshake.version(),
+            handshake.serverId() != null ? handshake.serverId() : System.getProperty(SERVER_ID_PROPERTY),
+            handshake.apiKey() != null ? handshake.apiKey() : System.getenv(API_KEY_ENV),
+            handshake.adsOptOut() != null || adsOptOut == null ? handshake.adsOptOut() : Boolean.valueOf(adsOptOut),
+            handshake.locale() != null
+                ? handshake.locale()
+                : System.getProperty(LOCALE_PROPERTY, Locale.getDefault().toLanguageTag())
+        );
+    }
+
     public void run() {
         if (this.shutDown) {
             return;
@@ -93,13 +130,23 @@ public class RegistryClient
                     if (nextByte == -1) {
                         throw new EOFException();
                     }
+                } catch (SocketTimeoutException e) {
+                    if (System.nanoTime() - this.lastReceived >= IDLE_TIMEOUT_NANOS) {
+                        Cardstock.LOGGER.error("The registry server has stopped responding.");
+                        break;
+                    }
+                    this.sendPacket(new ClientPingPacket());
+                    continue;
                 } catch (IOException e) {
                     break;
                 }
 
                 Optional<ServerPacket> next = partial.next(nextByte);
-                if (next.isPresent() && this.actOnPacket(next.get()) == PacketHandleResult.DISCONNECT) {
-                    break;
+                if (next.isPresent()) {
+                    this.lastReceived = System.nanoTime();
+                    if (this.actOnPacket(next.get()) == PacketHandleResult.DISCONNECT) {
+                        break;
+                    }
                 }
                 if (ERROR_TOLERANCE_SET) {
                     errors = 0;
@@ -115,6 +162,8 @@ public class RegistryClient
             }
         }
 
+        this.pendingResponses.values().forEach(response -> response.complete(RegisterResponse.Complete.empty()));
+        this.pendingResponses.clear();
         if (!this.shutDown) {
             Cardstock.LOGGER.error("We're no longer connected to the registry server; aborting.");
             this.shutDown = true;
@@ -122,31 +171,77 @@ public class RegistryClient
         }
     }
 
-    private @NotNull PacketHandleResult actOnPacket(@NotNull ServerPacket packet) {
+    private @NotNull PacketHandleResult actOnPacket(@NotNull ServerPacket packet)
+        throws IOException
+    {
         switch (packet) {
+            case ServerPingPacket ignored -> this.sendPacket(new ClientPongPacket());
+            case ServerPongPacket pongPacket -> this.completeResponse(pongPacket.requestId());
             case ServerHandshakePacket handshakePacket -> {
                 if (handshakePacket.adsEnabled()) {
                     Cardstock.LOGGER.warn(
                         "Your configured registry server will send you ads. "
                             + "These ads are not officially endorsed by Cardstock or any plugin."
                     );
+                    if (this.requestedAdsOptOut) {
+                        Cardstock.LOGGER.warn("The registry server doesn't allow opting out of its ads.");
+                    }
                 }
+                this.logger.debug("The registry server sends messages in `{}`.", handshakePacket.locale());
                 this.didHandshake = true;
+                this.completeResponse(handshakePacket.requestId());
+            }
+            // The server may also reject us before the handshake
+            case ServerDisconnectPacket disconnectPacket -> {
+                Cardstock.LOGGER.error(
+                    "The registry server has disconnected us ({}): {}",
+                    disconnectPacket.reason(),
+                    disconnectPacket.message()
+                );
+                return PacketHandleResult.DISCONNECT;
             }
             case ServerPacket ignored && !this.didHandshake ->
                 throw new IllegalStateException("Received a non-handshake packet before handshake.");
-            case ServerMsgPacket msgPacket -> this.registerResponse.addMsg(msgPacket.logFn(), msgPacket.contents());
-            case ServerDenyPacket ignored -> this.registerResponse.setDenied();
-            case ServerDonePacket ignored -> this.registerResponseQueue.add(this.registerResponse.reset());
-            case ServerDisconnectPacket ignored -> {
-                Cardstock.LOGGER.error("The registry server has disconnected us.");
-                return PacketHandleResult.DISCONNECT;
+            case ServerMsgPacket msgPacket ->
+                this.registerResponse(msgPacket.requestId())
+                    .addMsg(msgPacket.logLevel(), msgPacket.contents(), msgPacket.rich().orElse(null));
+            case ServerDenyPacket denyPacket -> this.registerResponse(denyPacket.requestId()).setDenied();
+            case ServerAdPacket adPacket -> Cardstock.LOGGER.info("[Registry ad] {}", adPacket.contents());
+            case ServerDonePacket donePacket -> this.completeResponse(donePacket.requestId());
+            case ServerRateLimitedPacket rateLimitedPacket -> {
+                this.logger.warn(
+                    "The registry server rate limited a request; it will accept more in {} ms.",
+                    rateLimitedPacket.retryAfterMs()
+                );
+                this.registerResponse(rateLimitedPacket.requestId())
+                    .setRateLimited(Duration.ofMillis(rateLimitedPacket.retryAfterMs()));
+                this.completeResponse(rateLimitedPacket.requestId());
             }
+            case ServerOwnerPacket ownerPacket -> this.registerResponse(ownerPacket.requestId()).setAnswer(ownerPacket);
+            case ServerCmdOwnersPacket cmdOwnersPacket ->
+                this.registerResponse(cmdOwnersPacket.requestId()).setAnswer(cmdOwnersPacket);
             default -> this.logger.warn("Ignoring packet: {}", packet);
         }
         return PacketHandleResult.OK;
     }
 
+    private @NotNull RegisterResponse registerResponse(long requestId) {
+        return this.registerResponses.computeIfAbsent(requestId, id -> new RegisterResponse());
+    }
+
+    private void completeResponse(long requestId) {
+        RegisterResponse response = this.registerResponses.remove(requestId);
+        CompletableFuture<RegisterResponse.Complete> pending = this.pendingResponses.remove(requestId);
+        if (pending == null) {
+            throw new IllegalStateException(String.format("Received a response to an unknown request. (%d)", requestId));
+        }
+        pending.complete(response != null ? response.reset() : RegisterResponse.Complete.empty());
+    }
+
+    private static @NotNull CompletableFuture<RegisterResponse.@NotNull Complete> emptyResponse() {
+        return CompletableFuture.completedFuture(RegisterResponse.Complete.empty());
+    }
+
     private enum PacketHandleResult {
         OK,
         DISCONNECT,
@@ -154,13 +249,28 @@ public class RegistryClient
 
     public void sendPacket(@NotNull ClientPacket packet)
         throws IOException
+    {
+        this.sendRequest(packet);
+    }
+
+    public @NotNull CompletableFuture<RegisterResponse.@NotNull Complete> sendRequest(@NotNull ClientPacket packet)
+        throws IOException
     {
         if (this.shutDown) {
-            return;
+            return emptyResponse();
+        }
+
+        long requestId = this.nextRequestId.getAndIncrement() & 0xFFFFFFFFL;
+        CompletableFuture<RegisterResponse.Complete> response;
+        if (packet.isRequest()) {
+            response = new CompletableFuture<>();
+            this.pendingResponses.put(requestId, response);
+        } else {
+            response = emptyResponse();
         }
 
         PacketByteBuf buf = PacketByteBuf.allocateDefault(3);
-        buf.writePacket(packet);
+        buf.writePacket(packet, requestId);
         try {
             buf.writeToOtherFromBeginning(bytes -> {
                 try {
@@ -170,19 +280,42 @@ public class RegistryClient
                 }
             });
         } catch (UncheckedIOException e) {
+            this.pendingResponses.remove(requestId);
             throw e.getCause();
         }
+        return response;
     }
 
-    public RegisterResponse.@NotNull Complete takeRegisterResponse() {
-        if (this.shutDown) {
-            return new RegisterResponse.Complete(false, new RegisterResponse.Msg[0]);
+    // Sends the request again after the delay the server asks for if it's rate limited, a few times
+    // at most
+    public RegisterResponse.@NotNull Complete request(@NotNull ClientPacket packet)
+        throws IOException
+    {
+        int retries = 0;
+        while (true) {
+            RegisterResponse.Complete response = await(this.sendRequest(packet));
+            if (!response.rateLimited() || retries++ == RATE_LIMIT_RETRIES) {
+                return response;
+            }
+            try {
+                Thread.sleep(response.retryAfter().toMillis());
+            } catch (InterruptedException e) {
+                Thread.currentThread().interrupt();
+                return response;
+            }
         }
+    }
+
+    private static RegisterResponse.@NotNull Complete await(
+        @NotNull CompletableFuture<RegisterResponse.@NotNull Complete> response
+    ) {
         while (true) {
             try {
-                return this.registerResponseQueue.take();
+                return response.get();
             } catch (InterruptedException e) {
                 // Continue looping
+            } catch (ExecutionException e) {
+                throw new IllegalStateException("The response completed exceptionally.", e.getCause());
             }
         }
     }
@@ -192,7 +325,7 @@ public class RegistryClient
         throws IOException
     {
         try {
-            this.sendPacket(new ClientDisconnectPacket());
+            this.sendPacket(new ClientDisconnectPacket(DisconnectReason.SHUTDOWN, "The server is shutting down."));
         } catch (IOException e) {
             Cardstock.LOGGER.warn("Failed to gracefully disconnect from the server.");
         }
@@ -0,0 +1,16 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
+package sh.lpx.cardstock.registry.packet;
+
+import org.jetbrains.annotations.NotNull;
+
+public record CmdOwner(@NotNull String cmd, @NotNull String owner) {
+    public static @NotNull CmdOwner read(@NotNull PacketByteBuf buf) {
+        return new CmdOwner(buf.readString(), buf.readString());
+    }
+
+    public void write(@NotNull PacketByteBuf buf) {
+        buf.writeString(this.cmd);
+        buf.writeString(this.owner);
+    }
+}
@@ -0,0 +1,30 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
+package sh.lpx.cardstock.registry.packet;
+
+import org.jetbrains.annotations.NotNull;
+
+public enum DisconnectReason {
+    REQUESTED,
+    SHUTDOWN,
+    ERROR_THRESHOLD,
+    TIMED_OUT,
+    PROTOCOL_VIOLATION,
+    BANNED,
+    TOO_MANY_CONNECTIONS,
+    NOT_ALLOWED,
+    UNAUTHORIZED;
+
+    public static @NotNull DisconnectReason read(@NotNull PacketByteBuf buf) {
+        int variant = buf.readUnsignedByte();
+        DisconnectReason[] variants = values();
+        if (variant >= variants.length) {
+            throw new IndexOutOfBoundsException(variant);
+        }
+        return variants[variant];
+    }
+
+    public void write(@NotNull PacketByteBuf buf) {
+        buf.writeUnsignedByte(this.ordinal());
+    }
+}
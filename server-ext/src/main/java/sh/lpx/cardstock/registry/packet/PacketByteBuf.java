@@ -9,7 +9,9 @@ import sh.lpx.cardstock.registry.packet.server.ServerPacket;
 import java.nio.ByteBuffer;
 import java.nio.ByteOrder;
 import java.nio.charset.StandardCharsets;
+import java.util.ArrayList;
 import java.util.Arrays;
+import java.util.List;
 import java.util.Optional;
 import java.util.function.BiConsumer;
 import java.util.function.Consumer;
@@ -79,8 +81,11 @@ public class PacketByteBuf {
         return ServerPacket.read(id, wrap(payload));
     }
 
-    public void writePacket(@NotNull ClientPacket packet) {
+    public void writePacket(@NotNull ClientPacket packet, long requestId) {
         PacketByteBuf buf = allocateDefault();
+        if (packet.isRequest()) {
+            buf.writeUnsignedInt(requestId);
+        }
         packet.write(buf);
         buf.writeToOtherFromBeginning(
             len -> {
@@ -108,6 +113,22 @@ public class PacketByteBuf {
         }
     }
 
+    public <T> @NotNull List<T> readList(@NotNull Function<@NotNull PacketByteBuf, T> read) {
+        int len = this.readUnsignedShort();
+        List<T> list = new ArrayList<>(len);
+        for (int i = 0; i < len; i++) {
+            list.add(read.apply(this));
+        }
+        return list;
+    }
+
+    public <T> void writeList(@NotNull List<T> list, @NotNull BiConsumer<@NotNull PacketByteBuf, T> write) {
+        this.writeUnsignedShort(list.size());
+        for (T element : list) {
+            write.accept(this, element);
+        }
+    }
+
     public boolean readBoolean() {
         return this.readUnsignedByte() != 0;
     }
@@ -1,3 +1,5 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
 package sh.lpx.cardstock.registry.packet.client;
 
 public record ClientDisablePluginPacket()
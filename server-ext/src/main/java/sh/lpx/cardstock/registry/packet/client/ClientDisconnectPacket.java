@@ -1,10 +1,27 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
 package sh.lpx.cardstock.registry.packet.client;
 
-public record ClientDisconnectPacket()
+import org.jetbrains.annotations.NotNull;
+import sh.lpx.cardstock.registry.packet.DisconnectReason;
+import sh.lpx.cardstock.registry.packet.PacketByteBuf;
+
+public record ClientDisconnectPacket(@NotNull DisconnectReason reason, @NotNull String message)
     implements ClientPacket
 {
     @Override
     public int id() {
         return 0x05;
     }
+
+    @Override
+    public boolean isRequest() {
+        return false;
+    }
+
+    @Override
+    public void write(@NotNull PacketByteBuf buf) {
+        this.reason.write(buf);
+        buf.writeString(this.message);
+    }
 }
@@ -1,11 +1,30 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
 package sh.lpx.cardstock.registry.packet.client;
 
 import org.jetbrains.annotations.NotNull;
+import org.jetbrains.annotations.Nullable;
 import sh.lpx.cardstock.registry.packet.PacketByteBuf;
 
-public record ClientHandshakePacket(@NotNull String version)
+public record ClientHandshakePacket(@NotNull String version, @Nullable String serverId, @Nullable String apiKey, @Nullable Boolean adsOptOut, @Nullable String locale)
     implements ClientPacket
 {
+    public ClientHandshakePacket(@NotNull String version) {
+        this(version, null, null, null, null);
+    }
+
+    public ClientHandshakePacket(@NotNull String version, @Nullable String serverId) {
+        this(version, serverId, null, null, null);
+    }
+
+    public ClientHandshakePacket(@NotNull String version, @Nullable String serverId, @Nullable String apiKey) {
+        this(version, serverId, apiKey, null, null);
+    }
+
+    public ClientHandshakePacket(@NotNull String version, @Nullable String serverId, @Nullable String apiKey, @Nullable Boolean adsOptOut) {
+        this(version, serverId, apiKey, adsOptOut, null);
+    }
+
     @Override
     public int id() {
         return 0x00;
@@ -14,5 +33,14 @@ public record ClientHandshakePacket(@NotNull String version)
     @Override
     public void write(@NotNull PacketByteBuf buf) {
         buf.writeString(this.version);
+        buf.writeOptional(this.serverId, PacketByteBuf::writeString);
+        buf.writeOptional(this.apiKey, PacketByteBuf::writeString);
+        buf.writeOptional(this.adsOptOut, PacketByteBuf::writeBoolean);
+        buf.writeOptional(this.locale, PacketByteBuf::writeString);
+    }
+
+    @Override
+    public @NotNull String toString() {
+        return "ClientHandshakePacket[version=" + this.version + ", serverId=" + this.serverId + ", apiKey=" + (this.apiKey == null ? null : "***") + ", adsOptOut=" + this.adsOptOut + ", locale=" + this.locale + "]";
     }
 }
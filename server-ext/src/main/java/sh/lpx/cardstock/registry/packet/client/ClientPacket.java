@@ -6,5 +6,9 @@ import sh.lpx.cardstock.registry.packet.PacketByteBuf;
 public interface ClientPacket {
     int id();
 
+    default boolean isRequest() {
+        return true;
+    }
+
     default void write(@NotNull PacketByteBuf buf) {}
 }
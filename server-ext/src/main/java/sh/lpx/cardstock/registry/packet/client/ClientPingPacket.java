@@ -0,0 +1,12 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
+package sh.lpx.cardstock.registry.packet.client;
+
+public record ClientPingPacket()
+    implements ClientPacket
+{
+    @Override
+    public int id() {
+        return 0x09;
+    }
+}
@@ -0,0 +1,17 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
+package sh.lpx.cardstock.registry.packet.client;
+
+public record ClientPongPacket()
+    implements ClientPacket
+{
+    @Override
+    public int id() {
+        return 0x0a;
+    }
+
+    @Override
+    public boolean isRequest() {
+        return false;
+    }
+}
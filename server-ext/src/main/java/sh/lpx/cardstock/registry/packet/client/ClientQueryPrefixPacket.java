@@ -0,0 +1,20 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
+package sh.lpx.cardstock.registry.packet.client;
+
+import org.jetbrains.annotations.NotNull;
+import sh.lpx.cardstock.registry.packet.PacketByteBuf;
+
+public record ClientQueryPrefixPacket(@NotNull String prefix)
+    implements ClientPacket
+{
+    @Override
+    public int id() {
+        return 0x08;
+    }
+
+    @Override
+    public void write(@NotNull PacketByteBuf buf) {
+        buf.writeString(this.prefix);
+    }
+}
@@ -1,12 +1,34 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
 package sh.lpx.cardstock.registry.packet.client;
 
 import org.jetbrains.annotations.NotNull;
 import org.jetbrains.annotations.Nullable;
 import sh.lpx.cardstock.registry.packet.PacketByteBuf;
 
-public record ClientSelectPluginPacket(@NotNull String name, @Nullable String authors)
+public record ClientSelectPluginPacket(@NotNull String name, @Nullable String authors, @Nullable String pluginId, @Nullable String version, @Nullable String website, @Nullable String description)
     implements ClientPacket
 {
+    public ClientSelectPluginPacket(@NotNull String name) {
+        this(name, null, null, null, null, null);
+    }
+
+    public ClientSelectPluginPacket(@NotNull String name, @Nullable String authors) {
+        this(name, authors, null, null, null, null);
+    }
+
+    public ClientSelectPluginPacket(@NotNull String name, @Nullable String authors, @Nullable String pluginId) {
+        this(name, authors, pluginId, null, null, null);
+    }
+
+    public ClientSelectPluginPacket(@NotNull String name, @Nullable String authors, @Nullable String pluginId, @Nullable String version) {
+        this(name, authors, pluginId, version, null, null);
+    }
+
+    public ClientSelectPluginPacket(@NotNull String name, @Nullable String authors, @Nullable String pluginId, @Nullable String version, @Nullable String website) {
+        this(name, authors, pluginId, version, website, null);
+    }
+
     @Override
     public int id() {
         return 0x01;
@@ -16,5 +38,9 @@ public record ClientSelectPluginPacket(@NotNull String name, @Nullable String au
     public void write(@NotNull PacketByteBuf buf) {
         buf.writeString(this.name);
         buf.writeOptional(this.authors, PacketByteBuf::writeString);
+        buf.writeOptional(this.pluginId, PacketByteBuf::writeString);
+        buf.writeOptional(this.version, PacketByteBuf::writeString);
+        buf.writeOptional(this.website, PacketByteBuf::writeString);
+        buf.writeOptional(this.description, PacketByteBuf::writeString);
     }
 }
@@ -0,0 +1,8 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
+package sh.lpx.cardstock.registry.packet.server;
+
+import org.jetbrains.annotations.NotNull;
+
+public record ServerAdPacket(long requestId, @NotNull String contents)
+    implements ServerPacket {}
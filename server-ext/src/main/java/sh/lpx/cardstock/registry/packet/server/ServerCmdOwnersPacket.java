@@ -0,0 +1,11 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
+package sh.lpx.cardstock.registry.packet.server;
+
+import org.jetbrains.annotations.NotNull;
+import sh.lpx.cardstock.registry.packet.CmdOwner;
+
+import java.util.List;
+
+public record ServerCmdOwnersPacket(long requestId, @NotNull List<@NotNull CmdOwner> cmds, boolean truncated)
+    implements ServerPacket {}
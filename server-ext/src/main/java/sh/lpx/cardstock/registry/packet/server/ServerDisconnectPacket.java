@@ -1,4 +1,9 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
 package sh.lpx.cardstock.registry.packet.server;
 
-public record ServerDisconnectPacket()
+import org.jetbrains.annotations.NotNull;
+import sh.lpx.cardstock.registry.packet.DisconnectReason;
+
+public record ServerDisconnectPacket(@NotNull DisconnectReason reason, @NotNull String message)
     implements ServerPacket {}
@@ -1,4 +1,6 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
 package sh.lpx.cardstock.registry.packet.server;
 
-public record ServerDonePacket()
+public record ServerDonePacket(long requestId)
     implements ServerPacket {}
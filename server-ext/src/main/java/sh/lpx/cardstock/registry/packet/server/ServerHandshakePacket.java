@@ -1,4 +1,8 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
 package sh.lpx.cardstock.registry.packet.server;
 
-public record ServerHandshakePacket(boolean adsEnabled)
+import org.jetbrains.annotations.NotNull;
+
+public record ServerHandshakePacket(long requestId, boolean adsEnabled, @NotNull String locale)
     implements ServerPacket {}
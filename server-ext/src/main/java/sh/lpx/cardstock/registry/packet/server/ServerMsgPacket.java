@@ -1,9 +1,12 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
 package sh.lpx.cardstock.registry.packet.server;
 
 import org.jetbrains.annotations.NotNull;
 import org.slf4j.Logger;
 
+import java.util.Optional;
 import java.util.function.BiConsumer;
 
-public record ServerMsgPacket(@NotNull BiConsumer<@NotNull Logger, String> logFn, @NotNull String contents)
+public record ServerMsgPacket(long requestId, @NotNull BiConsumer<@NotNull Logger, String> logLevel, @NotNull String contents, @NotNull Optional<@NotNull String> rich)
     implements ServerPacket {}
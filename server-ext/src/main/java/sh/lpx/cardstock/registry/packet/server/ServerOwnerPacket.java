@@ -0,0 +1,10 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
+package sh.lpx.cardstock.registry.packet.server;
+
+import org.jetbrains.annotations.NotNull;
+
+import java.util.Optional;
+
+public record ServerOwnerPacket(long requestId, @NotNull Optional<@NotNull String> owner)
+    implements ServerPacket {}
@@ -1,16 +1,35 @@
+// Generated from the protocol schema by cardstock-registry-codegen. Do not edit.
+
 package sh.lpx.cardstock.registry.packet.server;
 
 import org.jetbrains.annotations.NotNull;
+import sh.lpx.cardstock.registry.packet.CmdOwner;
+import sh.lpx.cardstock.registry.packet.DisconnectReason;
 import sh.lpx.cardstock.registry.packet.PacketByteBuf;
 
 public interface ServerPacket {
     static @NotNull ServerPacket read(int id, @NotNull PacketByteBuf buf) {
         return switch (id) {
-            case 0x00 -> new ServerHandshakePacket(buf.readBoolean());
-            case 0x01 -> new ServerMsgPacket(buf.readLogFn(), buf.readString());
-            case 0x02 -> new ServerDenyPacket();
-            case 0x03 -> new ServerDonePacket();
-            case 0x04 -> new ServerDisconnectPacket();
+            case 0x00 -> new ServerHandshakePacket(buf.readUnsignedInt(), buf.readBoolean(), buf.readString());
+            case 0x01 -> new ServerMsgPacket(
+                buf.readUnsignedInt(),
+                buf.readLogFn(),
+                buf.readString(),
+                buf.readOptional(PacketByteBuf::readString)
+            );
+            case 0x02 -> new ServerDenyPacket(buf.readUnsignedInt());
+            case 0x03 -> new ServerDonePacket(buf.readUnsignedInt());
+            case 0x04 -> new ServerDisconnectPacket(DisconnectReason.read(buf), buf.readString());
+            case 0x05 -> new ServerOwnerPacket(buf.readUnsignedInt(), buf.readOptional(PacketByteBuf::readString));
+            case 0x06 -> new ServerCmdOwnersPacket(
+                buf.readUnsignedInt(),
+                buf.readList(CmdOwner::read),
+                buf.readBoolean()
+            );
+            case 0x07 -> new ServerPongPacket(buf.readUnsignedInt());
+            case 0x08 -> new ServerPingPacket();
+            case 0x09 -> new ServerRateLimitedPacket(buf.readUnsignedInt(), buf.readUnsignedInt());
+            case 0x0a -> new ServerAdPacket(buf.readUnsignedInt(), buf.readString());
             default -> throw new IllegalArgumentException(String.format("The packet ID is invalid. (0x%02x)", id));
         };
     }